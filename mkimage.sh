@@ -55,16 +55,23 @@ fi
 [ ! -z "$outfile" ] || outfile="$infile.img"
 objcopy -j .boot -j .kernel -O binary "$infile" "$outfile"
 
+tmpdir=$(mktemp -d)
+trap 'rm -rf "$tmpdir"' EXIT
 for binary in "${binaries[@]}"; do
     if [ -f "$binary" ]; then 
-        cat "$binary"
+        path="$binary"
     elif [ -f "$(dirname "$infile")/$binary" ]; then
-        cat "$(dirname "$infile")/$binary"
+        path="$(dirname "$infile")/$binary"
     else
         echo "No such binary '$binary'" >&2
         echo "Try building it 'cargo build' or 'cargo build --release'" >&2
         exit 1
-    fi >> "$outfile"
+    fi
+
+    # Record the program's name in a section, so that it can be executed by name.
+    printf '%s' "$(basename "$binary")" > "$tmpdir/name"
+    objcopy --add-section .ros.name="$tmpdir/name" "$path" "$tmpdir/elf"
+    cat "$tmpdir/elf" >> "$outfile"
 done
 
 echo "Image written to $outfile" >&2
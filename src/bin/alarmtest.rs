@@ -0,0 +1,10 @@
+#![no_std]
+use ros::{println, syscall};
+
+fn main() {
+    // Without a handler, the alarm acts as a watchdog and terminates the process.
+    println!("spinning until the alarm goes off...");
+    syscall::alarm(1);
+    #[allow(clippy::empty_loop)]
+    loop {}
+}
@@ -0,0 +1,26 @@
+#![no_std]
+use ros::{
+    io, println,
+    syscall::{self, ioctl},
+};
+
+/// Types "ab<backspace>c" into the console, and checks that the backspace and the character it
+/// erased never reach the reader.
+fn main() {
+    syscall::set_echo(true);
+    syscall::ioctl(0, ioctl::CONSOLE_FLUSH_INPUT, &mut []).expect("stdin is not the console");
+    let mut input = *b"ab\x7fc\n";
+    syscall::ioctl(0, ioctl::CONSOLE_PUSH_INPUT, &mut input).expect("failed to push input");
+
+    let mut stdin = io::stdin();
+    let mut line = [0u8; 16];
+    let mut len = 0;
+    while len == 0 || line[len - 1] != b'\n' {
+        match stdin.read(&mut line[len..]).expect("input error") {
+            0 => panic!("unexpected end of file"),
+            n => len += n,
+        }
+    }
+    assert_eq!(&line[..len], b"ac\n");
+    println!("backspacetest passed");
+}
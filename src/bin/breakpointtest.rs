@@ -0,0 +1,67 @@
+#![no_std]
+use core::sync::atomic::{AtomicU32, Ordering};
+use ros::{
+    io::File,
+    println,
+    syscall::{self, BreakpointKind, DebugEvent, SetBreakpointArg},
+};
+
+static WATCHED: AtomicU32 = AtomicU32::new(0);
+
+#[inline(never)]
+fn target() {
+    println!("child reached the target function");
+}
+
+fn main() {
+    let (read, write) = syscall::pipe().expect("pipe failed");
+    let child = syscall::fork().expect("fork failed");
+    if child == 0 {
+        // Wait for the parent to set the breakpoints.
+        syscall::close(write);
+        File::new(read).read_all(&mut [0u8]).expect("read error");
+        WATCHED.store(42, Ordering::Relaxed);
+        target();
+        return;
+    }
+    syscall::close(read);
+
+    let breakpoints = [
+        (WATCHED.as_ptr() as usize, BreakpointKind::Write, 4),
+        (target as fn() as usize, BreakpointKind::Execute, 1),
+    ];
+    for (index, &(addr, kind, len)) in breakpoints.iter().enumerate() {
+        syscall::set_breakpoint(&SetBreakpointArg {
+            pid: child,
+            index: index as u32,
+            addr,
+            kind,
+            len,
+        })
+        .expect("set_breakpoint failed");
+    }
+    File::new(write).write_all(&[0]).expect("write error");
+
+    // The write breakpoint fires after the store; the execute breakpoint fires before the first
+    // instruction of the function.
+    assert_eq!(
+        syscall::debug_wait(child).expect("debug_wait failed"),
+        DebugEvent::Breakpoint(0)
+    );
+    println!("child wrote the watched variable");
+    syscall::debug_resume(child, false).expect("debug_resume failed");
+    assert_eq!(
+        syscall::debug_wait(child).expect("debug_wait failed"),
+        DebugEvent::Breakpoint(1)
+    );
+    let regs = syscall::get_regs(child).expect("get_regs failed");
+    assert_eq!(regs.eip as usize, target as fn() as usize);
+
+    syscall::debug_resume(child, false).expect("debug_resume failed");
+    assert_eq!(
+        syscall::debug_wait(child).expect("debug_wait failed"),
+        DebugEvent::Exited
+    );
+    syscall::wait(child);
+    println!("breakpointtest passed");
+}
@@ -0,0 +1,15 @@
+#![no_std]
+use ros::{println, syscall};
+
+fn main() {
+    let time = syscall::time().expect("the real-time clock is not set");
+    let (days, seconds) = (time / 86400, time % 86400);
+    println!(
+        "{} seconds since the epoch ({} days, {:02}:{:02}:{:02} UTC)",
+        time,
+        days,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+}
@@ -0,0 +1,21 @@
+#![no_std]
+use ros::{env, println, syscall};
+
+fn main() {
+    match env::getenv("ENVTEST") {
+        None => {
+            // Run ourselves again with an environment.
+            println!("testing environment variables");
+            let error = syscall::exec_path_env("envtest", &["ENVTEST=1", "TERM=ansi"]);
+            panic!("exec failed: {:?}", error);
+        }
+        Some(_) => {
+            for var in env::vars() {
+                println!("{}", var);
+            }
+            assert_eq!(env::getenv("TERM"), Some("ansi"));
+            assert_eq!(env::getenv("PATH"), None);
+            println!("environment test passed");
+        }
+    }
+}
@@ -0,0 +1,43 @@
+#![no_std]
+use core::arch::asm;
+use ros::{
+    println,
+    syscall::{self, ExitStatus},
+};
+
+/// The start of kernelspace.
+const KERNEL_BASE: usize = 0xf0000000;
+
+/// Runs `fault` in a child process, and checks that the kernel kills only the child.
+fn expect_fault(name: &str, fault: fn()) {
+    let child = syscall::fork().expect("fork failed");
+    if child == 0 {
+        fault();
+        panic!("{} did not fault", name);
+    }
+    let status = loop {
+        if let Some(status) = syscall::try_wait(child).expect("try_wait failed") {
+            break status;
+        }
+        syscall::yield_cpu();
+    };
+    assert_eq!(status, ExitStatus::Faulted);
+    println!("{} killed the child", name);
+}
+
+fn main() {
+    expect_fault("divide error", || unsafe {
+        asm!("div {:e}", in(reg) 0, inout("eax") 1 => _, inout("edx") 0 => _);
+    });
+    expect_fault("invalid opcode", || unsafe { asm!("ud2") });
+    expect_fault("unmapped syscall argument", || {
+        let buf = unsafe { core::slice::from_raw_parts(0x10 as *const u8, 16) };
+        let _ = syscall::write(1, buf);
+    });
+    expect_fault("syscall argument reaching into kernelspace", || {
+        // The start of the buffer is in userspace, but the kernel must still refuse it.
+        let buf = unsafe { core::slice::from_raw_parts((KERNEL_BASE - 16) as *const u8, 32) };
+        let _ = syscall::write(1, buf);
+    });
+    println!("faulttest passed");
+}
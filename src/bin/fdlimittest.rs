@@ -0,0 +1,46 @@
+#![no_std]
+extern crate alloc;
+use alloc::vec::Vec;
+use ros::{
+    io, println,
+    syscall::{self, NewFdError, MAX_FDS},
+};
+
+fn main() {
+    // Open file descriptors until we run out.
+    let mut fds = Vec::new();
+    let error = loop {
+        match syscall::null_fd() {
+            Ok(fd) => fds.push(fd),
+            Err(e) => break e,
+        }
+    };
+    assert!(matches!(error, NewFdError::TooManyFiles));
+    assert!(fds.len() <= MAX_FDS as usize - 3);
+    assert!(fds.iter().all(|&fd| fd < MAX_FDS));
+    println!("opened {} file descriptors", fds.len());
+
+    // A pipe needs two free descriptors, so it should fail even with one available.
+    let last = fds.pop().unwrap();
+    syscall::close(last);
+    assert!(matches!(syscall::pipe(), Err(NewFdError::TooManyFiles)));
+    assert!(syscall::dup2(io::stdout().fd, MAX_FDS).is_err());
+
+    // The freed descriptor should be reused.
+    let fd = syscall::null_fd().expect("null_fd failed after close");
+    assert_eq!(fd, last);
+    assert!(syscall::close(fd));
+    assert!(
+        !syscall::close(fd),
+        "double close should report a missing descriptor"
+    );
+
+    for fd in fds {
+        syscall::close(fd);
+    }
+    let (read, write) = syscall::pipe().expect("pipe failed after closing everything");
+    syscall::close(read);
+    syscall::close(write);
+
+    println!("fdlimittest passed");
+}
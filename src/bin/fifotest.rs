@@ -0,0 +1,27 @@
+#![no_std]
+use ros::{io::File, prelude::*, syscall};
+
+/// Run two copies of this program (for instance, `N & N` in smallersh). The first copy creates the
+/// FIFO and writes a message into it; the second reads the message back out, and removes the FIFO
+/// so that the test can be run again.
+fn main() {
+    match syscall::mkfifo("fifotest") {
+        Ok(()) => {
+            let mut fifo = File::new(syscall::open_fifo("fifotest", true).expect("open failed"));
+            fifo.write_all(b"hello through a fifo\n")
+                .expect("write error");
+            fifo.close();
+            println!("fifotest: message sent");
+        }
+        Err(syscall::MkfifoError::AlreadyExists) => {
+            let mut fifo = File::new(syscall::open_fifo("fifotest", false).expect("open failed"));
+            syscall::unlink_fifo("fifotest").expect("unlink failed");
+            let mut message = Vec::new();
+            fifo.read_line(&mut message).expect("read error");
+            print!(
+                "fifotest: received {}",
+                core::str::from_utf8(&message).expect("invalid message")
+            );
+        }
+    }
+}
@@ -0,0 +1,43 @@
+#![no_std]
+use ros::{io::File, println, syscall};
+
+const ROUNDS: u8 = 16;
+
+fn main() {
+    println!("testing fork between blocking reads");
+
+    let (read, write) = syscall::pipe().expect("pipe failed");
+    let (mut read, mut write) = (File::new(read), File::new(write));
+
+    // Feed the pipe slowly, so the reads below usually block first.
+    let producer = match syscall::fork().expect("fork failed") {
+        0 => {
+            read.close();
+            for i in 0..ROUNDS {
+                write.write_all(&[i]).expect("write error");
+                syscall::yield_cpu();
+            }
+            syscall::exit();
+        }
+        pid => pid,
+    };
+    write.close();
+
+    for i in 0..ROUNDS {
+        let mut buf = [0];
+        read.read_all(&mut buf).expect("read error");
+        assert_eq!(buf[0], i, "read the wrong byte");
+
+        match syscall::fork().expect("fork failed") {
+            // The child must return from fork, not resume the read that blocked.
+            0 => {
+                println!("round {}: child returned from fork", i);
+                syscall::exit();
+            }
+            child => syscall::wait(child),
+        }
+    }
+
+    syscall::wait(producer);
+    println!("fork test passed");
+}
@@ -4,10 +4,10 @@ use ros::{io::File, println, syscall};
 fn main() {
     println!("testing fork");
 
-    let (read, write) = syscall::pipe();
+    let (read, write) = syscall::pipe().expect("pipe failed");
     let (mut read, mut write) = (File::new(read), File::new(write));
 
-    match syscall::fork() {
+    match syscall::fork().expect("fork failed") {
         0 => {
             println!("hello from child!");
             write.close();
@@ -0,0 +1,31 @@
+#![no_std]
+use ros::{io, syscall};
+
+fn main() {
+    const WIDTH: usize = 320;
+    const HEIGHT: usize = 200;
+
+    let framebuffer =
+        syscall::set_video_mode(syscall::VideoMode::Graphics).expect("graphics mode is in use");
+    let framebuffer = unsafe { core::slice::from_raw_parts_mut(framebuffer, WIDTH * HEIGHT) };
+
+    // Red increases to the right and green increases downward, with blue in the four corners.
+    for (y, row) in framebuffer.chunks_exact_mut(WIDTH).enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let r = (x * 8 / WIDTH) as u8;
+            let g = (y * 8 / HEIGHT) as u8;
+            let b = ((x * 2 / WIDTH) + (y * 2 / HEIGHT) * 2) as u8;
+            *pixel = r << 5 | g << 2 | b;
+        }
+    }
+
+    // Wait for a key, without echoing it.
+    syscall::set_echo(false);
+    let mut c = 0u8;
+    io::stdin()
+        .read(core::slice::from_mut(&mut c))
+        .expect("read error");
+    syscall::set_echo(true);
+
+    syscall::set_video_mode(syscall::VideoMode::Text).unwrap();
+}
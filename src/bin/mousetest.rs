@@ -0,0 +1,23 @@
+#![no_std]
+use ros::{println, syscall};
+
+fn main() {
+    const LEFT_AND_RIGHT: u8 = 0b011;
+
+    println!("Move the mouse, or press the left and right buttons together to exit.");
+    let mouse = syscall::open_mouse().expect("open_mouse failed");
+    let mut events = [syscall::MouseEvent::default(); 8];
+    'outer: loop {
+        let count = syscall::read_mouse(mouse, &mut events).expect("read error");
+        for event in &events[..count] {
+            println!(
+                "dx {:>4} dy {:>4} buttons {:03b}",
+                event.dx, event.dy, event.buttons
+            );
+            if event.buttons & LEFT_AND_RIGHT == LEFT_AND_RIGHT {
+                break 'outer;
+            }
+        }
+    }
+    syscall::close(mouse);
+}
@@ -0,0 +1,26 @@
+#![no_std]
+use ros::{io, print, println, syscall};
+
+fn main() {
+    print!("Password: ");
+
+    // Read a line without echoing it to the screen.
+    syscall::set_echo(false);
+    let mut stdin = io::stdin();
+    let mut len = 0;
+    loop {
+        let mut c = 0u8;
+        match stdin
+            .read(core::slice::from_mut(&mut c))
+            .expect("read error")
+        {
+            0 => break,
+            _ if c == b'\n' => break,
+            _ => len += 1,
+        }
+    }
+    syscall::set_echo(true);
+
+    println!();
+    println!("read a password of {} characters", len);
+}
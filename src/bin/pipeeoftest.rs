@@ -0,0 +1,53 @@
+#![no_std]
+extern crate alloc;
+use alloc::vec::Vec;
+use ros::{io::File, println, syscall};
+
+fn main() {
+    let (read, write) = syscall::pipe().expect("pipe failed");
+
+    // Start two children writing into the pipe. Each forks while holding the write half, sends its
+    // stdout to it, and execs a program that writes and exits.
+    let mut children = Vec::new();
+    for program in ["helloworld", "count"].iter().copied() {
+        let child = syscall::fork().expect("fork failed");
+        if child == 0 {
+            syscall::dup2(write, 1).expect("dup2 failed");
+            syscall::close(write);
+            syscall::close(read);
+            let error = syscall::exec_path(program);
+            panic!("exec failed: {:?}", error);
+        }
+        children.push(child);
+    }
+
+    // Once we close our copy, the pipe should reach end-of-file only after both children exit.
+    syscall::close(write);
+    let mut read = File::new(read);
+    let mut output = Vec::new();
+    let mut buf = [0u8; 64];
+    loop {
+        let len = read.read(&mut buf).expect("read error");
+        if len == 0 {
+            break;
+        }
+        output.extend_from_slice(&buf[..len]);
+    }
+    for child in children {
+        assert!(
+            syscall::try_wait(child).expect("try_wait failed").is_some(),
+            "reached end-of-file before a writer exited"
+        );
+    }
+
+    let output = core::str::from_utf8(&output).expect("invalid output");
+    assert!(output.contains("Hello, world!\n"));
+    assert_eq!(
+        output
+            .lines()
+            .filter(|line| *line != "Hello, world!")
+            .count(),
+        10
+    );
+    println!("pipeeoftest passed");
+}
@@ -2,17 +2,33 @@
 use ros::{io::File, syscall};
 
 fn main() {
-    let (read, write) = syscall::pipe();
+    let (read, write) = syscall::pipe().expect("pipe failed");
     let (mut read, mut write) = (File::new(read), File::new(write));
 
     const TEST_STR: &str = "Hello, world!";
 
     write.write_all(TEST_STR.as_bytes()).expect("write error");
+    assert_eq!(syscall::fd_pending(read.fd).unwrap(), TEST_STR.len());
 
     let mut buf = [0u8; TEST_STR.len()];
     assert_eq!(read.read_all(&mut buf).expect("read error"), buf.len());
     assert_eq!(buf, TEST_STR.as_bytes());
 
+    // Vectored I/O should transfer the buffers in order.
+    let header: &[u8] = b"Hello, ";
+    let body: &[u8] = b"world!";
+    assert_eq!(
+        syscall::writev(write.fd, &[header, body]).expect("write error"),
+        TEST_STR.len()
+    );
+    let (mut a, mut b) = ([0u8; 7], [0u8; 6]);
+    assert_eq!(
+        syscall::readv(read.fd, &mut [&mut a, &mut b]).expect("read error"),
+        TEST_STR.len()
+    );
+    assert_eq!(&a, header);
+    assert_eq!(&b, body);
+
     write.close();
     assert_eq!(read.read_all(&mut buf).expect("read error"), 0);
 
@@ -0,0 +1,19 @@
+#![no_std]
+use ros::{prelude::*, syscall};
+
+fn main() {
+    let mut buf = vec![0u8; 256];
+    loop {
+        let len = syscall::list_programs(&mut buf);
+        if len <= buf.len() {
+            buf.truncate(len);
+            break;
+        }
+        buf.resize(len, 0);
+    }
+
+    let names = core::str::from_utf8(&buf).expect("invalid program name");
+    for (index, name) in names.lines().enumerate() {
+        println!("{:>3} {}", index, name);
+    }
+}
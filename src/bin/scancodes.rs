@@ -0,0 +1,22 @@
+#![no_std]
+use ros::{println, syscall};
+
+fn main() {
+    const ESCAPE: u8 = 0x01;
+
+    println!("Press keys to see their scancodes, or Escape to exit.");
+    syscall::set_echo(false);
+    let keyboard = syscall::open_raw_keyboard().expect("open_raw_keyboard failed");
+    let mut buf = [0u8; 16];
+    'outer: loop {
+        let len = syscall::read(keyboard, &mut buf).expect("read error");
+        for &code in &buf[..len] {
+            println!("{:#04x}", code);
+            if code == ESCAPE {
+                break 'outer;
+            }
+        }
+    }
+    syscall::close(keyboard);
+    syscall::set_echo(true);
+}
@@ -0,0 +1,13 @@
+#![no_std]
+use ros::{println, syscall};
+
+fn main() {
+    let stats = syscall::sched_stats(0).expect("sched_stats failed");
+    println!("context switches: {}", stats.context_switches);
+    println!("preemptions:      {}", stats.preemptions);
+    println!("yields:           {}", stats.yields);
+    println!(
+        "our CPU time:     {} us",
+        stats.process_cpu_time * 1_000_000 / syscall::tsc_hz()
+    );
+}
@@ -0,0 +1,33 @@
+#![no_std]
+use ros::{io, println, syscall};
+
+/// Reads one line from standard input into `buf`, returning the number of bytes read.
+fn read_line(buf: &mut [u8]) -> usize {
+    let mut stdin = io::stdin();
+    let mut len = 0;
+    while len < buf.len() {
+        match stdin.read(&mut buf[len..len + 1]).expect("read error") {
+            0 => break,
+            _ if buf[len] == b'\n' => break,
+            _ => len += 1,
+        }
+    }
+    len
+}
+
+fn main() {
+    println!("two processes are now reading from the console; type a line for each");
+
+    let child = syscall::fork().expect("fork failed");
+    let name = if child == 0 { "child" } else { "parent" };
+
+    let mut buf = [0u8; 256];
+    let len = read_line(&mut buf);
+    let line = core::str::from_utf8(&buf[0..len]).expect("UTF-8 error");
+    println!("{} read '{}'", name, line);
+
+    if child != 0 {
+        syscall::wait(child);
+        println!("sharedstdin passed");
+    }
+}
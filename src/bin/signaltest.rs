@@ -0,0 +1,86 @@
+#![no_std]
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use ros::{
+    io::File,
+    println,
+    syscall::{self, Signal},
+};
+
+static RECEIVED: AtomicBool = AtomicBool::new(false);
+/// If set, the handler writes a byte here after receiving a signal.
+static ACK_FD: AtomicU32 = AtomicU32::new(u32::MAX);
+
+fn handler(signal: Signal) {
+    println!("received {:?}", signal);
+    RECEIVED.store(true, Ordering::Relaxed);
+    let ack_fd = ACK_FD.load(Ordering::Relaxed);
+    if ack_fd != u32::MAX {
+        File::new(ack_fd).write_all(b"!").expect("write error");
+    }
+}
+
+/// Forks a child that waits for a signal, and sends it `Signal::Term`.
+fn signal_child() {
+    let child = syscall::fork().expect("fork failed");
+    if child == 0 {
+        while !RECEIVED.load(Ordering::Relaxed) {
+            syscall::yield_cpu();
+        }
+        println!("child handled signal");
+        syscall::exit();
+    }
+    syscall::kill(child, Signal::Term).expect("kill failed");
+    syscall::wait(child);
+}
+
+/// Forks a child that blocks reading from an empty pipe, and sends it `Signal::Term`. The signal
+/// should interrupt the read to run the handler, after which the read is issued again.
+fn signal_blocked_child() {
+    let (data_read, data_write) = syscall::pipe().expect("pipe failed");
+    let (ack_read, ack_write) = syscall::pipe().expect("pipe failed");
+    let child = syscall::fork().expect("fork failed");
+    if child == 0 {
+        syscall::close(data_write);
+        syscall::close(ack_read);
+        ACK_FD.store(ack_write, Ordering::Relaxed);
+
+        let mut buf = [0u8; 1];
+        assert_eq!(
+            File::new(data_read).read_all(&mut buf).expect("read error"),
+            1
+        );
+        assert!(RECEIVED.load(Ordering::Relaxed));
+        println!("child finished read after handling signal");
+        syscall::exit();
+    }
+    syscall::close(data_read);
+    syscall::close(ack_write);
+
+    // Give the child time to block in its read.
+    for _ in 0..10 {
+        syscall::yield_cpu();
+    }
+    syscall::kill(child, Signal::Term).expect("kill failed");
+
+    // Nothing can be read until the handler has run, so this hangs if the signal waits for the
+    // read to complete.
+    let mut ack = [0u8; 1];
+    assert_eq!(
+        File::new(ack_read).read_all(&mut ack).expect("read error"),
+        1
+    );
+    File::new(data_write).write_all(b"x").expect("write error");
+    syscall::wait(child);
+}
+
+fn main() {
+    // The child inherits the handler, so it survives the signal.
+    syscall::signal(Some(handler));
+    signal_child();
+    signal_blocked_child();
+
+    // Without a handler, the signal terminates the child.
+    syscall::signal(None);
+    signal_child();
+    println!("child terminated");
+}
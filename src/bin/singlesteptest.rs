@@ -0,0 +1,47 @@
+#![no_std]
+use ros::{
+    println,
+    syscall::{self, DebugEvent},
+};
+
+fn main() {
+    let child = syscall::fork().expect("fork failed");
+    if child == 0 {
+        let mut total = 0u32;
+        for i in 0..1000 {
+            total = core::hint::black_box(total.wrapping_add(i));
+        }
+        println!("child computed {}", total);
+        return;
+    }
+
+    // Step through a few of the child's instructions, checking that it stops after each one.
+    const STEPS: usize = 16;
+    let mut last_eip = None;
+    for _ in 0..STEPS {
+        syscall::debug_resume(child, true).expect("debug_resume failed");
+        assert_eq!(
+            syscall::debug_wait(child).expect("debug_wait failed"),
+            DebugEvent::Stopped
+        );
+        let regs = syscall::get_regs(child).expect("get_regs failed");
+        assert_eq!(
+            regs.eflags & (1 << 8),
+            0,
+            "trap flag leaked into the stopped child"
+        );
+        if let Some(last_eip) = last_eip {
+            println!("stepped {:#x} -> {:#x}", last_eip, regs.eip);
+        }
+        last_eip = Some(regs.eip);
+    }
+
+    // Let the child run to completion.
+    syscall::debug_resume(child, false).expect("debug_resume failed");
+    assert_eq!(
+        syscall::debug_wait(child).expect("debug_wait failed"),
+        DebugEvent::Exited
+    );
+    syscall::wait(child);
+    println!("singlesteptest passed");
+}
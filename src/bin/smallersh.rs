@@ -1,130 +1,433 @@
 #![no_std]
-use ros::{eprintln, io, print, println, syscall};
+extern crate alloc;
 
-const BUFSIZE: usize = 64;
+use alloc::collections::VecDeque;
+use ros::{
+    io,
+    prelude::*,
+    syscall::{self, ExitStatus, Pid},
+};
 
 fn main() {
     println!("Welcome to smallersh");
     println!("Usage:");
-    println!("    process [ | process ... ] & ...");
+    println!("    program [2>&1 | 2> fifo] [ | program ... ] & ...");
 
     let mut stdin = io::stdin();
+    let mut history = VecDeque::new();
+    let mut jobs = Jobs::default();
     loop {
-        print!("> ");
-
-        let mut input = [0u8; BUFSIZE];
-        let mut i = 0;
-        loop {
-            let mut c = 0u8;
-            match stdin.read(core::slice::from_mut(&mut c)) {
-                Ok(0) => return, // EOF
-                Ok(_) => {}
-                Err(e) => panic!("input error: {:?}", e),
-            };
+        jobs.reap();
+        print!("{}", PROMPT);
 
-            input[i] = c;
-            match c {
-                b'0'..=b'9' | b'|' | b'&' => {}
-                b' ' => {}
-                b'\n' => {
-                    process_command(&input[0..i]);
-                    break;
+        let input = match read_command(&mut stdin, &history) {
+            Some(input) => input,
+            None => return, // EOF
+        };
+        if !input.is_empty() && history.back() != Some(&input) {
+            if history.len() == HISTORY_SIZE {
+                history.pop_front();
+            }
+            history.push_back(input.clone());
+        }
+
+        match input
+            .iter()
+            .find(|&&c| !(is_name_char(c) || matches!(c, b'|' | b'&' | b'>' | b' ')))
+        {
+            Some(&c) => eprintln!("invalid character: {}", c as char),
+            None => process_command(&input, &mut jobs),
+        }
+    }
+}
+
+const PROMPT: &str = "> ";
+
+/// The number of command lines remembered in the history.
+const HISTORY_SIZE: usize = 32;
+
+/// Reads a command line from standard input, without the trailing newline. The up and down arrow
+/// keys navigate through `history`. Returns None if the end-of-file is reached.
+fn read_command(stdin: &mut io::File, history: &VecDeque<Vec<u8>>) -> Option<Vec<u8>> {
+    // Echo input ourselves, so that we can redraw the line when navigating the history.
+    syscall::set_echo(false);
+
+    let mut line = Vec::new();
+    // The history entry being edited; history.len() refers to the new line.
+    let mut index = history.len();
+    // The new line, saved while we're looking at the history.
+    let mut new_line = Vec::new();
+    let eof = loop {
+        let c = match read_byte(stdin) {
+            Some(c) => c,
+            None => break true,
+        };
+        match c {
+            b'\n' => {
+                print!("\n");
+                break false;
+            }
+            0x7F | 0x08 => {
+                if line.pop().is_some() {
+                    print!("\x08 \x08");
                 }
-                c => {
-                    println!();
-                    eprintln!("invalid character: {}", c as char);
-                    break;
+            }
+            0x1B => {
+                // Arrow keys are sent as "ESC [ A" (up) through "ESC [ D" (left).
+                if read_byte(stdin) != Some(b'[') {
+                    continue;
+                }
+                let new_index = match read_byte(stdin) {
+                    Some(b'A') => index.checked_sub(1),
+                    Some(b'B') if index < history.len() => Some(index + 1),
+                    _ => None,
+                };
+                if let Some(new_index) = new_index {
+                    if index == history.len() {
+                        new_line = core::mem::take(&mut line);
+                    }
+                    index = new_index;
+                    line = match history.get(index) {
+                        Some(entry) => entry.clone(),
+                        None => core::mem::take(&mut new_line),
+                    };
+
+                    // Return to the start of the line, and overwrite it.
+                    print!("\r{}", PROMPT);
+                    io::stdout().write_all(&line).expect("I/O error");
+                    print!("\x1b[K");
                 }
             }
+            c => {
+                line.push(c);
+                io::stdout().write_all(&[c]).expect("I/O error");
+            }
+        }
+    };
 
-            i += 1;
-            if i == input.len() {
-                println!();
-                eprintln!("input too long");
-                break;
+    syscall::set_echo(true);
+    if eof && line.is_empty() {
+        None
+    } else {
+        Some(line)
+    }
+}
+
+/// Reads a single byte from `stdin`, returning None at end-of-file.
+fn read_byte(stdin: &mut io::File) -> Option<u8> {
+    let mut c = 0u8;
+    match stdin.read(core::slice::from_mut(&mut c)) {
+        Ok(0) => None,
+        Ok(_) => Some(c),
+        Err(e) => panic!("input error: {:?}", e),
+    }
+}
+
+/// A pipeline running in the background.
+struct Job {
+    id: usize,
+    /// The processes in the pipeline that haven't been reaped yet.
+    processes: Vec<Pid>,
+    /// The exit status of the last process in the pipeline, once it has terminated.
+    status: Option<ExitStatus>,
+}
+
+/// The table of background jobs.
+#[derive(Default)]
+struct Jobs {
+    jobs: Vec<Job>,
+    next_id: usize,
+}
+
+impl Jobs {
+    /// Starts tracking a background pipeline.
+    fn add(&mut self, processes: Vec<Pid>) {
+        self.next_id += 1;
+        println!("[{}] {}", self.next_id, processes[0]);
+        self.jobs.push(Job {
+            id: self.next_id,
+            processes,
+            status: None,
+        });
+    }
+
+    /// Reaps terminated background processes, and reports any jobs that have finished.
+    fn reap(&mut self) {
+        for job in &mut self.jobs {
+            let last = *job.processes.last().unwrap();
+            let mut status = job.status;
+            job.processes.retain(|&pid| match syscall::try_wait(pid) {
+                Ok(None) => true,
+                Ok(Some(exit_status)) => {
+                    if pid == last {
+                        status = Some(exit_status);
+                    }
+                    false
+                }
+                Err(_) => false,
+            });
+            job.status = status;
+        }
+
+        self.jobs.retain(|job| {
+            if job.processes.is_empty() {
+                match job.status {
+                    Some(status) => println!("[{}] done: {:?}", job.id, status),
+                    None => println!("[{}] done", job.id),
+                }
+                false
+            } else {
+                true
             }
+        });
+        if self.jobs.is_empty() {
+            self.next_id = 0;
         }
     }
 }
 
-fn process_command(command: &[u8]) {
-    let iterator = command
-        .iter()
-        .map(|&c| c as char)
-        .filter(|c| !c.is_whitespace())
-        .peekable();
+/// Returns true if `c` may appear in a program name.
+fn is_name_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, b'_' | b'-')
+}
+
+/// A token in a command line.
+#[derive(Clone, Copy)]
+enum Token<'a> {
+    /// A program name or number.
+    Process(&'a str),
+    /// '|'
+    Pipe,
+    /// '&'
+    Background,
+    /// '2>&1'
+    MergeStderr,
+    /// '2> name', redirecting stderr to the named pipe `name`
+    StderrTo(&'a str),
+}
 
-    // validate that the command consists of alternating commands & tokens
-    {
-        let mut iterator = iterator.clone();
-        match iterator.peek() {
-            None => return, // command is empty
-            Some(c) if c.is_digit(10) => {}
-            Some(_) => {
-                eprintln!("command line must begin with process");
-                return;
+/// Splits a command line into tokens, returning None on a parse error.
+fn tokenize(command: &[u8]) -> Option<Vec<Token<'_>>> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < command.len() {
+        let (token, len) = match command[i] {
+            c if c.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            _ if command[i..].starts_with(b"2>&1") => (Token::MergeStderr, 4),
+            _ if command[i..].starts_with(b"2>") => {
+                let start = i
+                    + 2
+                    + command[(i + 2)..]
+                        .iter()
+                        .position(|c| !c.is_ascii_whitespace())?;
+                let len = command[start..]
+                    .iter()
+                    .position(|&c| !is_name_char(c))
+                    .unwrap_or(command.len() - start);
+                if len == 0 {
+                    return None;
+                }
+                let name = core::str::from_utf8(&command[start..(start + len)]).ok()?;
+                (Token::StderrTo(name), start + len - i)
             }
+            c if is_name_char(c) => {
+                let len = command[i..]
+                    .iter()
+                    .position(|&c| !is_name_char(c))
+                    .unwrap_or(command.len() - i);
+                let name = core::str::from_utf8(&command[i..(i + len)]).ok()?;
+                (Token::Process(name), len)
+            }
+            b'|' => (Token::Pipe, 1),
+            b'&' => (Token::Background, 1),
+            _ => return None,
+        };
+        tokens.push(token);
+        i += len;
+    }
+    Some(tokens)
+}
+
+/// A process in a pipeline.
+#[derive(Clone, Copy)]
+struct Command<'a> {
+    /// The name or number of the program to run.
+    process: &'a str,
+    /// Where the process's stderr should be redirected.
+    stderr: Stderr<'a>,
+}
+
+/// The destination of a process's stderr.
+#[derive(Clone, Copy)]
+enum Stderr<'a> {
+    /// The shell's own stderr.
+    Inherit,
+    /// The process's stdout.
+    Stdout,
+    /// The named pipe with the given name.
+    Fifo(&'a str),
+}
+
+fn process_command(command: &[u8], jobs: &mut Jobs) {
+    let tokens = match tokenize(command) {
+        Some(tokens) => tokens,
+        None => {
+            eprintln!("parse error");
+            return;
         }
-        while let Some(c) = iterator.next() {
-            if let Some(d) = iterator.peek() {
-                if c.is_digit(10) && d.is_digit(10) || !c.is_digit(10) && !d.is_digit(10) {
+    };
+
+    // validate the command line: each process may be followed by a redirection, and processes are
+    // separated by pipes or '&'
+    {
+        let mut previous = None;
+        for &token in &tokens {
+            let valid = match token {
+                Token::Process(_) => {
+                    matches!(previous, None | Some(Token::Pipe | Token::Background))
+                }
+                Token::MergeStderr | Token::StderrTo(_) => {
+                    matches!(previous, Some(Token::Process(_)))
+                }
+                Token::Pipe | Token::Background => matches!(
+                    previous,
+                    Some(Token::Process(_) | Token::MergeStderr | Token::StderrTo(_))
+                ),
+            };
+            if !valid {
+                if previous.is_none() {
+                    eprintln!("command line must begin with process");
+                } else {
                     eprintln!("parse error");
-                    return;
                 }
+                return;
             }
+            previous = Some(token);
+        }
+        if let Some(Token::Pipe) = previous {
+            eprintln!("parse error");
+            return;
         }
     }
 
-    let mut pipeline_idx = 0;
-    let mut pipeline_pids = [0u32; BUFSIZE];
-    for c in iterator {
-        if let Some(process) = c.to_digit(10) {
-            pipeline_pids[pipeline_idx] = process;
-            pipeline_idx += 1;
-        } else if c == '&' {
-            execute_pipeline(&pipeline_pids[0..pipeline_idx], false);
-            pipeline_idx = 0;
+    let mut pipeline = Vec::new();
+    for &token in &tokens {
+        match token {
+            Token::Process(process) => pipeline.push(Command {
+                process,
+                stderr: Stderr::Inherit,
+            }),
+            Token::MergeStderr => pipeline.last_mut().unwrap().stderr = Stderr::Stdout,
+            Token::StderrTo(name) => pipeline.last_mut().unwrap().stderr = Stderr::Fifo(name),
+            Token::Pipe => {}
+            Token::Background => {
+                let processes = execute_pipeline(&pipeline, false);
+                if !processes.is_empty() {
+                    jobs.add(processes);
+                }
+                pipeline.clear();
+            }
         }
     }
-    execute_pipeline(&pipeline_pids[0..pipeline_idx], true);
+    execute_pipeline(&pipeline, true);
 }
 
-fn execute_pipeline(processes: &[u32], wait: bool) {
+/// Runs a pipeline, returning the PIDs of its processes. If `wait` is true, waits for the
+/// pipeline to finish.
+fn execute_pipeline(processes: &[Command], wait: bool) -> Vec<Pid> {
+    let mut child_pids = Vec::new();
     if processes.is_empty() {
-        return;
+        return child_pids;
     }
 
-    let mut child_pids = [0; BUFSIZE];
-    let mut child_idx = 0;
+    // Each pipeline gets its own process group, named after its first process.
+    let mut pgid = 0;
     let mut iterator = processes.iter().peekable();
     let mut input = if wait {
         io::stdin().fd
     } else {
-        syscall::null_fd()
+        match syscall::null_fd() {
+            Ok(fd) => fd,
+            Err(e) => {
+                eprintln!("null_fd failed: {:?}", e);
+                return child_pids;
+            }
+        }
     };
     while let Some(process) = iterator.next() {
         let (next_input, output) = if iterator.peek().is_some() {
-            syscall::pipe()
+            match syscall::pipe() {
+                Ok(pipe) => pipe,
+                Err(e) => {
+                    eprintln!("pipe failed: {:?}", e);
+                    if input > io::stderr().fd {
+                        syscall::close(input);
+                    }
+                    break;
+                }
+            }
         } else {
             (input, io::stdout().fd)
         };
 
-        let pid = syscall::fork();
+        let pid = match syscall::fork() {
+            Ok(pid) => pid,
+            Err(e) => {
+                eprintln!("fork failed: {:?}", e);
+                // Close the pipes we would have handed to the child
+                if input > io::stderr().fd {
+                    syscall::close(input);
+                }
+                if output > io::stderr().fd {
+                    syscall::close(output);
+                }
+                if next_input != input {
+                    syscall::close(next_input);
+                }
+                break;
+            }
+        };
         if pid == 0 {
             // we're the child
-            syscall::dup2(input, io::stdin().fd);
-            syscall::dup2(output, io::stdout().fd);
+            syscall::setpgid(0, pgid).expect("setpgid failed");
+            syscall::dup2(input, io::stdin().fd).expect("dup2 failed");
+            syscall::dup2(output, io::stdout().fd).expect("dup2 failed");
+            match process.stderr {
+                Stderr::Inherit => {}
+                Stderr::Stdout => {
+                    syscall::dup2(output, io::stderr().fd).expect("dup2 failed");
+                }
+                Stderr::Fifo(name) => {
+                    // Create the pipe if it doesn't exist yet; a reader can open it later.
+                    let _ = syscall::mkfifo(name);
+                    let fd = syscall::open_fifo(name, true)
+                        .unwrap_or_else(|e| panic!("cannot open {}: {:?}", name, e));
+                    syscall::dup2(fd, io::stderr().fd).expect("dup2 failed");
+                    syscall::close(fd);
+                }
+            }
             if next_input != input {
-                syscall::close(next_input)
+                syscall::close(next_input);
             }
 
-            let error = syscall::exec(*process);
+            let error = match process.process.parse() {
+                Ok(index) => syscall::exec(index),
+                Err(_) => syscall::exec_path(process.process),
+            };
             panic!("exec failed: {:?}", error);
         }
 
         // we're the parent
-        child_pids[child_idx] = pid;
-        child_idx += 1;
+        if pgid == 0 {
+            pgid = pid;
+        }
+        // Also set the child's group here, in case it hasn't run yet. (If it has already exited,
+        // there's nothing to do.)
+        let _ = syscall::setpgid(pid, pgid);
+        child_pids.push(pid);
         // Close the child's pipes
         if input > io::stderr().fd {
             syscall::close(input);
@@ -136,9 +439,12 @@ fn execute_pipeline(processes: &[u32], wait: bool) {
         input = next_input;
     }
 
-    if wait {
-        for &child in &child_pids[0..child_idx] {
+    if wait && !child_pids.is_empty() {
+        syscall::set_foreground(pgid);
+        for &child in &child_pids {
             syscall::wait(child);
         }
+        syscall::set_foreground(syscall::getpgid());
     }
+    child_pids
 }
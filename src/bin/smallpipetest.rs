@@ -0,0 +1,53 @@
+#![no_std]
+use ros::{io::File, println, syscall};
+
+const CAPACITY: usize = 16;
+
+fn main() {
+    let (read, write) = syscall::pipe_sized(CAPACITY).expect("pipe failed");
+    // The child writes a byte here once its write to the small pipe has completed.
+    let (done_read, done_write) = syscall::pipe().expect("pipe failed");
+
+    let child = syscall::fork().expect("fork failed");
+    if child == 0 {
+        // Try to write twice the pipe's capacity; this should block halfway through.
+        syscall::close(read);
+        syscall::close(done_read);
+        File::new(write)
+            .write_all(&[0x55; CAPACITY * 2])
+            .expect("write error");
+        File::new(done_write).write_all(b"!").expect("write error");
+        return;
+    }
+    syscall::close(write);
+    syscall::close(done_write);
+
+    // Wait for the child to fill the pipe. Until we read from it, the child's write can't
+    // complete, so it must not have signalled that it's done, and the pipe must not hold more than
+    // its capacity.
+    while syscall::fd_pending(read).unwrap() < CAPACITY {
+        syscall::yield_cpu();
+    }
+    assert_eq!(syscall::fd_pending(read).unwrap(), CAPACITY);
+    assert_eq!(
+        syscall::fd_pending(done_read).unwrap(),
+        0,
+        "write to a full pipe did not block"
+    );
+
+    // Draining the pipe should unblock the writer.
+    let mut read = File::new(read);
+    let mut buf = [0u8; CAPACITY * 2];
+    assert_eq!(read.read_all(&mut buf).expect("read error"), buf.len());
+    assert!(buf.iter().all(|&b| b == 0x55));
+
+    let mut done = [0u8; 1];
+    assert_eq!(
+        File::new(done_read)
+            .read_all(&mut done)
+            .expect("read error"),
+        1
+    );
+    syscall::wait(child);
+    println!("smallpipetest passed");
+}
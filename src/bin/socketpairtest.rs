@@ -0,0 +1,36 @@
+#![no_std]
+use ros::{io::File, println, syscall};
+
+fn main() {
+    let (parent, child) = syscall::socketpair().expect("socketpair failed");
+
+    match syscall::fork().expect("fork failed") {
+        0 => {
+            let mut socket = File::new(child);
+            syscall::close(parent);
+
+            // Answer requests until the parent hangs up.
+            let mut buf = [0u8; 4];
+            while socket.read_all(&mut buf).expect("read error") == buf.len() {
+                assert_eq!(&buf, b"ping");
+                socket.write_all(b"pong").expect("write error");
+            }
+        }
+
+        pid => {
+            let mut socket = File::new(parent);
+            syscall::close(child);
+
+            let mut buf = [0u8; 4];
+            for _ in 0..3 {
+                socket.write_all(b"ping").expect("write error");
+                assert_eq!(socket.read_all(&mut buf).expect("read error"), buf.len());
+                assert_eq!(&buf, b"pong");
+            }
+
+            socket.close();
+            syscall::wait(pid);
+            println!("socketpairtest passed");
+        }
+    }
+}
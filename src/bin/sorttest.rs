@@ -0,0 +1,26 @@
+#![no_std]
+use ros::{prelude::*, time::rdtsc};
+
+const COUNT: usize = 1000;
+
+fn main() {
+    // Fill a vector with pseudorandom numbers from a linear congruential generator
+    let mut state = rdtsc() as u32;
+    let mut numbers = Vec::new();
+    for _ in 0..COUNT {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        numbers.push(state >> 16);
+    }
+
+    numbers.sort_unstable();
+    assert!(
+        numbers.windows(2).all(|w| w[0] <= w[1]),
+        "vector is not sorted"
+    );
+    println!(
+        "sorted {} numbers: min {}, max {}",
+        numbers.len(),
+        numbers[0],
+        numbers[COUNT - 1]
+    );
+}
@@ -0,0 +1,38 @@
+#![no_std]
+use core::arch::asm;
+use ros::{
+    println,
+    syscall::{self, ExitStatus},
+};
+
+const ITERATIONS: usize = 1000;
+
+/// Loads `value` into every lane of `xmm0`, then yields repeatedly, checking that the register
+/// still holds it each time this process is scheduled again.
+fn spin(value: u32) {
+    let lanes = [value; 4];
+    unsafe { asm!("movups xmm0, [{}]", in(reg) lanes.as_ptr()) };
+    for _ in 0..ITERATIONS {
+        syscall::yield_cpu();
+        let mut saved = [0u32; 4];
+        unsafe { asm!("movups [{}], xmm0", in(reg) saved.as_mut_ptr()) };
+        assert_eq!(saved, lanes, "xmm0 was clobbered");
+    }
+}
+
+fn main() {
+    let child = syscall::fork().expect("fork failed");
+    if child == 0 {
+        spin(0xc0ffee);
+        return;
+    }
+    spin(0xdecade);
+    let status = loop {
+        if let Some(status) = syscall::try_wait(child).expect("try_wait failed") {
+            break status;
+        }
+        syscall::yield_cpu();
+    };
+    assert_eq!(status, ExitStatus::Exited);
+    println!("ssetest passed");
+}
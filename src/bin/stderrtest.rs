@@ -0,0 +1,32 @@
+#![no_std]
+use ros::{eprintln, io::File, println, syscall};
+
+fn main() {
+    let (read, write) = syscall::pipe().expect("pipe failed");
+
+    const TEST_STR: &str = "Hello, stderr!";
+
+    let child = syscall::fork().expect("fork failed");
+    if child == 0 {
+        // Send stderr to the pipe, and close every other reference to the write half.
+        syscall::dup2(write, 2).expect("dup2 failed");
+        syscall::close(write);
+        syscall::close(read);
+
+        eprintln!("{}", TEST_STR);
+        return;
+    }
+
+    syscall::close(write);
+    let mut read = File::new(read);
+
+    // Once the child exits, its stderr should be the only reference to the write half, so we should
+    // see the message followed by end-of-file.
+    let mut buf = [0u8; 64];
+    let len = read.read_all(&mut buf).expect("read error");
+    assert_eq!(len, TEST_STR.len() + 1);
+    assert_eq!(&buf[0..TEST_STR.len()], TEST_STR.as_bytes());
+
+    syscall::wait(child);
+    println!("stderrtest passed");
+}
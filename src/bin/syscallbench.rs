@@ -0,0 +1,66 @@
+#![no_std]
+use ros::{
+    io::File,
+    println, syscall,
+    time::{cycles_to_ns, rdtsc},
+};
+
+const ITERATIONS: u64 = 1000;
+const PIPE_SIZE: usize = 256;
+const TRANSFER_SIZE: usize = 64 * 1024;
+
+/// Sends TRANSFER_SIZE bytes through a small pipe to a child process, using the provided write
+/// function. Returns the number of elapsed cycles.
+fn transfer(write: fn(&mut File, &[u8])) -> u64 {
+    let (read, write_fd) = syscall::pipe_sized(PIPE_SIZE).expect("pipe failed");
+    let child = syscall::fork().expect("fork failed");
+    if child == 0 {
+        syscall::close(write_fd);
+        let mut read = File::new(read);
+        let mut buf = [0u8; PIPE_SIZE];
+        while read.read(&mut buf).expect("read error") != 0 {}
+        syscall::exit();
+    }
+    syscall::close(read);
+
+    let data = [0u8; TRANSFER_SIZE];
+    let mut file = File::new(write_fd);
+    let start = rdtsc();
+    write(&mut file, &data);
+    let end = rdtsc();
+
+    file.close();
+    syscall::wait(child);
+    end - start
+}
+
+fn main() {
+    // Measure the round-trip cost of a syscall that does no work.
+    let null = syscall::null_fd().expect("null_fd failed");
+    let start = rdtsc();
+    for _ in 0..ITERATIONS {
+        syscall::write(null, &[]).expect("write error");
+    }
+    let end = rdtsc();
+    println!(
+        "empty write: {} cycles ({} ns) per syscall",
+        (end - start) / ITERATIONS,
+        cycles_to_ns(end - start) / ITERATIONS
+    );
+
+    // Compare looping in userspace with looping in the kernel.
+    let userspace = transfer(|file, mut buf| {
+        while !buf.is_empty() {
+            let written = file.write(buf).expect("write error");
+            buf = &buf[written..];
+        }
+    });
+    let kernel = transfer(|file, buf| file.write_all(buf).expect("write error"));
+    println!(
+        "{} bytes through a {}-byte pipe: {} ns looping in userspace, {} ns in the kernel",
+        TRANSFER_SIZE,
+        PIPE_SIZE,
+        cycles_to_ns(userspace),
+        cycles_to_ns(kernel)
+    );
+}
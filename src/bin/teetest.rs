@@ -0,0 +1,25 @@
+#![no_std]
+use ros::{io::File, println, syscall};
+
+fn main() {
+    let (read_a, write_a) = syscall::pipe().expect("pipe failed");
+    let (read_b, write_b) = syscall::pipe().expect("pipe failed");
+
+    let mut tee = File::new(syscall::tee(&[write_a, write_b]).expect("tee error"));
+    syscall::close(write_a);
+    syscall::close(write_b);
+
+    const TEST_STR: &str = "Hello, world!";
+    tee.write_all(TEST_STR.as_bytes()).expect("write error");
+    tee.close();
+
+    // Both pipes should contain the message, followed by end-of-file.
+    for read in [read_a, read_b] {
+        let mut read = File::new(read);
+        let mut buf = [0u8; 64];
+        let len = read.read_all(&mut buf).expect("read error");
+        assert_eq!(&buf[0..len], TEST_STR.as_bytes());
+    }
+
+    println!("teetest passed");
+}
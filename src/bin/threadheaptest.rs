@@ -0,0 +1,27 @@
+#![no_std]
+use core::sync::atomic::{AtomicBool, Ordering};
+use ros::{prelude::*, syscall};
+
+const STACK_SIZE: usize = 0x4000;
+const HEAP_GROWTH: usize = 0x100000;
+
+static DONE: AtomicBool = AtomicBool::new(false);
+
+fn wait_until_done() {
+    while !DONE.load(Ordering::Acquire) {
+        syscall::yield_cpu();
+    }
+}
+
+fn main() {
+    // The thread's stack is mapped while the heap still has room to grow. Growing the heap by
+    // much more than the stack's size must not run into it.
+    let thread = syscall::spawn_thread(wait_until_done, STACK_SIZE).expect("spawn_thread failed");
+
+    let buf = vec![0xaau8; HEAP_GROWTH];
+    assert!(buf.iter().all(|&b| b == 0xaa));
+
+    DONE.store(true, Ordering::Release);
+    syscall::wait(thread);
+    println!("threadheaptest passed");
+}
@@ -0,0 +1,32 @@
+#![no_std]
+use core::sync::atomic::{AtomicU32, Ordering};
+use ros::{prelude::*, syscall};
+
+const THREADS: usize = 4;
+const ITERATIONS: u32 = 100_000;
+const STACK_SIZE: usize = 0x4000;
+
+static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+fn count() {
+    for _ in 0..ITERATIONS {
+        COUNTER.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn main() {
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| syscall::spawn_thread(count, STACK_SIZE).expect("spawn_thread failed"))
+        .collect();
+    for thread in threads {
+        syscall::wait(thread);
+    }
+
+    let total = COUNTER.load(Ordering::Relaxed);
+    println!(
+        "counter: {} (expected {})",
+        total,
+        THREADS as u32 * ITERATIONS
+    );
+    assert_eq!(total, THREADS as u32 * ITERATIONS);
+}
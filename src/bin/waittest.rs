@@ -0,0 +1,34 @@
+#![no_std]
+use ros::{println, syscall};
+
+const CHILDREN: u32 = 8;
+const ROUNDS: u32 = 16;
+
+fn main() {
+    println!("testing wait");
+
+    for round in 0..ROUNDS {
+        // Children spin for different lengths of time, so that they are preempted by the timer and
+        // exit while the parent is blocked in 'wait' as well as before it calls 'wait'.
+        let mut children = [0; CHILDREN as usize];
+        for (i, child) in children.iter_mut().enumerate() {
+            match syscall::fork().expect("fork failed") {
+                0 => {
+                    for _ in 0..(i as u32 + round) * 100_000 {
+                        core::hint::spin_loop();
+                    }
+                    syscall::exit();
+                }
+                pid => *child = pid,
+            }
+        }
+
+        // Wait in reverse order, so some children have already exited.
+        for &child in children.iter().rev() {
+            syscall::wait(child);
+        }
+        println!("round {} ok", round);
+    }
+
+    println!("wait test passed");
+}
@@ -5,8 +5,11 @@
 .section .boot, "ax"
 .code16
 
+// The number of entries in the memory map, followed by the entries themselves.
+.global _BIOS_MEMORY_MAP_COUNT
+_BIOS_MEMORY_MAP_COUNT = 0x7e00
 .global _BIOS_MEMORY_MAP
-_BIOS_MEMORY_MAP = 0x7e00
+_BIOS_MEMORY_MAP = 0x7e08
 
 .global _start
 _start:
@@ -20,27 +23,32 @@ _start:
     // Detect available memory
     mov edi, offset _BIOS_MEMORY_MAP
     xor ebx, ebx
-    mov edx, 0x534D4150
+    mov dword ptr [_BIOS_MEMORY_MAP_COUNT], ebx
 memloop:
     mov eax, 0xE820
     mov ecx, 0x18
+    mov edx, 0x534D4150
     int 0x15
-    // If the BIOS returned only 5 words, initialize the 6th
-    mov dword ptr [ecx + edi], 0x1
-    jc memdone
-    test ebx, ebx
-    jz memdone
-
-    // If this entry is valid (size is nonzero), update pointer to point to next entry.
+    jc memdone  // no entry was returned
+    // Only 24-byte (ACPI 3.0) entries have an attributes field. If the BIOS returned a 20-byte
+    // entry, fill in attributes that mark it as valid, so it's never ignored.
+    cmp cl, 0x18
+    jae memattrs
+    mov dword ptr [di + 0x14], 0x1
+memattrs:
+
+    // If this entry is valid (size is nonzero), keep it and move on to the next entry.
     // Otherwise, just rewrite the same entry again.
-    test dword ptr [di + 0x8], 0xffffffff
-    jz memloop
+    mov eax, [di + 0x8]
+    or eax, [di + 0xc]
+    jz memnext
     add di, 0x18
-    jmp memloop
+    inc dword ptr [_BIOS_MEMORY_MAP_COUNT]
+memnext:
+    test ebx, ebx
+    jnz memloop
 
 memdone:
-    // Terminate the list with a zero-sized entry
-    mov dword ptr [di + 0x28], 0
 
     // Enable A20
     in al, 0x92
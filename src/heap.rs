@@ -6,6 +6,13 @@
 //! For "big" allocations (greater than 2048 bytes, or half a page) we simply allocate whole pages
 //! using the MMU. For "small" allocations, we use a buddy allocator to subdivide individual pages.
 //!
+//! Big allocations are placed at the top of kernelspace, and everything else at the bottom, so that
+//! scattered single pages don't break up the contiguous runs big allocations need.
+//!
+//! Big allocations aligned to more than a page search for a larger run of unused address space and
+//! map only the aligned pages within it. The unmapped slack around them stays free, so 'deallocate'
+//! can unmap a big allocation without knowing where its run started.
+//!
 //! Each page belonging to the small allocator begins with a 64-byte header, indicating which
 //! blocks of that page are free vs in use. The header contains 9 arrays with one bit per element:
 //! a 1-element array covering the whole page, a 2-element array covering 2048-byte blocks, a
@@ -64,17 +71,33 @@ unsafe impl GlobalAlloc for HeapAllocator {
     unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
         debugln!("alloc({:#08x?})", layout);
         self.allocator
-            .take()
-            .unwrap()
+            .lock()
             .allocate(layout)
             .unwrap_or(core::ptr::null_mut())
     }
 
+    unsafe fn alloc_zeroed(&self, layout: core::alloc::Layout) -> *mut u8 {
+        let ptr = self.alloc(layout);
+        // Big allocations map the zero page until they're written, so only small ones, which may
+        // reuse a freed block, need clearing.
+        if !ptr.is_null() && block_size(layout) <= MAX_ALLOC {
+            ptr.write_bytes(0, layout.size());
+        }
+        ptr
+    }
+
     unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
-        self.allocator.take().unwrap().deallocate(ptr, layout);
+        self.allocator.lock().deallocate(ptr, layout);
     }
 }
 
+/// Returns the size of the block that holds an allocation of 'layout'.
+fn block_size(layout: core::alloc::Layout) -> usize {
+    // Size and alignment are the same; use whichever is greater.
+    let size = core::cmp::max(layout.size(), layout.align()).next_power_of_two();
+    core::cmp::max(size, MIN_ALLOC)
+}
+
 pub struct _HeapAllocator {
     freelists: [*mut FreelistEntry; NUM_FREELISTS],
 }
@@ -87,24 +110,21 @@ impl _HeapAllocator {
     }
 
     unsafe fn allocate(&mut self, layout: core::alloc::Layout) -> Result<*mut u8, AllocError> {
-        // We can't align something to more than a page.
-        if layout.align() > mmu::PAGE_SIZE {
-            return Err(AllocError);
-        }
-
-        // Size and alignment are the same; use whichever is greater.
-        let size = core::cmp::max(layout.size(), layout.align()).next_power_of_two();
-        let size = core::cmp::max(size, MIN_ALLOC);
-        let mut mmu = mmu::MMU.take().unwrap();
+        let size = block_size(layout);
+        let mut mmu = mmu::MMU.lock();
         let mmu = mmu.deref_mut();
         if size > MAX_ALLOC {
             // This is a big allocation; simply map some pages for it and we're done.
             debugln!("big");
             let pages = mmu::page_align_up(size).unwrap() / mmu::PAGE_SIZE;
+            // Any run this long contains an address aligned to 'layout.align()'.
+            let align = core::cmp::max(layout.align(), mmu::PAGE_SIZE);
+            let slack = align / mmu::PAGE_SIZE - 1;
             let vaddr = mmu
                 .mapper
-                .find_unused_kernelspace(pages)
+                .find_unused_kernelspace_high(pages + slack)
                 .ok_or(AllocError)?;
+            let vaddr = vaddr.checked_add(align - 1).ok_or(AllocError)? & !(align - 1);
             debugln!("alloced at {:#08x}", vaddr);
             mmu.mapper.map_zeroed(
                 &mut mmu.allocator,
@@ -137,10 +157,7 @@ impl _HeapAllocator {
                 let idx = (addr as usize - page_start) / size;
 
                 // Remove the entry from the freelist
-                *head = (*addr).next;
-                if !(*head).is_null() {
-                    (**head).prev = core::ptr::null_mut();
-                }
+                self.remove_free(i, addr);
 
                 debugln!("\tpage header:");
                 debugln!("\tbefore: {:?}", header);
@@ -191,10 +208,9 @@ impl _HeapAllocator {
         debugln!("deallocate({:#08x?}, {:#08x?})", ptr, layout);
         debugln!("before:");
         self.print_freelists();
-        let size = core::cmp::max(layout.size(), layout.align()).next_power_of_two();
-        let size = core::cmp::max(size, MIN_ALLOC);
+        let size = block_size(layout);
         let vaddr = ptr as usize;
-        let mut mmu = mmu::MMU.take().unwrap();
+        let mut mmu = mmu::MMU.lock();
         let mmu = mmu.deref_mut();
         if size > MAX_ALLOC {
             let pages = mmu::page_align_up(size).unwrap() / mmu::PAGE_SIZE;
@@ -226,6 +242,36 @@ impl _HeapAllocator {
         self.print_freelists();
     }
 
+    /// Pushes `entry` onto the freelist for blocks at `depth`.
+    unsafe fn push_free(&mut self, depth: usize, entry: *mut FreelistEntry) {
+        let head = self.freelists[depth];
+        (*entry).prev = core::ptr::null_mut();
+        (*entry).next = head;
+        if !head.is_null() {
+            (*head).prev = entry;
+        }
+        self.freelists[depth] = entry;
+    }
+
+    /// Unlinks `entry` from the freelist for blocks at `depth`, fixing up both of its neighbours.
+    /// Does nothing if `entry` is not on the freelist.
+    unsafe fn remove_free(&mut self, depth: usize, entry: *mut FreelistEntry) {
+        let (prev, next) = ((*entry).prev, (*entry).next);
+        if !prev.is_null() {
+            (*prev).next = next;
+        } else if self.freelists[depth] == entry {
+            self.freelists[depth] = next;
+        } else {
+            return;
+        }
+        if !next.is_null() {
+            (*next).prev = prev;
+        }
+        // Clear the links so that removing the entry again can't touch its old neighbours.
+        (*entry).prev = core::ptr::null_mut();
+        (*entry).next = core::ptr::null_mut();
+    }
+
     fn print_freelists(&self) {
         unsafe {
             debugln!("Freelists:");
@@ -243,6 +289,229 @@ impl _HeapAllocator {
     }
 }
 
+/// Runs the heap's self-tests.
+#[cfg(feature = "self-test")]
+pub fn self_test() {
+    test_big_alignment();
+    test_stress();
+    test_coalescing();
+    test_fragmentation();
+    test_big_reuse();
+    test_alloc_zeroed();
+}
+
+/// Checks that big allocations honor alignments of more than a page.
+#[cfg(feature = "self-test")]
+fn test_big_alignment() {
+    for align in [2 * mmu::PAGE_SIZE, 4 * mmu::PAGE_SIZE] {
+        let layout = core::alloc::Layout::from_size_align(mmu::PAGE_SIZE, align).unwrap();
+        unsafe {
+            let ptr = alloc::alloc::alloc(layout);
+            assert!(!ptr.is_null(), "allocation failed: {:?}", layout);
+            assert_eq!(ptr as usize & (align - 1), 0, "misaligned: {:?}", layout);
+            ptr.write_bytes(0xaa, layout.size());
+            alloc::alloc::dealloc(ptr, layout);
+        }
+    }
+}
+
+/// Allocates and frees blocks of pseudorandom sizes, checking that no two live blocks overlap. The
+/// freelists' links are checked on every allocation by 'print_freelists'.
+#[cfg(feature = "self-test")]
+fn test_stress() {
+    use alloc::vec::Vec;
+    const ITERATIONS: usize = 4096;
+    const MAX_LIVE: usize = 64;
+
+    // Linear congruential generator, as in the sorttest program.
+    let mut state = 12345u32;
+    let mut random = move || {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        (state >> 16) as usize
+    };
+
+    let mut live: Vec<(*mut u8, core::alloc::Layout, u8)> = Vec::with_capacity(MAX_LIVE);
+    for i in 0..ITERATIONS {
+        if live.len() == MAX_LIVE || (!live.is_empty() && random() & 1 == 0) {
+            let (ptr, layout, fill) = live.swap_remove(random() % live.len());
+            unsafe {
+                let block = core::slice::from_raw_parts(ptr, layout.size());
+                assert!(
+                    block.iter().all(|&b| b == fill),
+                    "block {:?} corrupted",
+                    ptr
+                );
+                alloc::alloc::dealloc(ptr, layout);
+            }
+        } else {
+            let size = random() % mmu::PAGE_SIZE + 1;
+            let layout = core::alloc::Layout::from_size_align(size, 1).unwrap();
+            let fill = i as u8;
+            unsafe {
+                let ptr = alloc::alloc::alloc(layout);
+                assert!(!ptr.is_null(), "allocation failed: {:?}", layout);
+                ptr.write_bytes(fill, size);
+                live.push((ptr, layout, fill));
+            }
+        }
+    }
+    for (ptr, layout, _) in live {
+        unsafe { alloc::alloc::dealloc(ptr, layout) };
+    }
+}
+
+/// Checks that freeing every block on a page coalesces it back into a single free page, which is
+/// then unmapped, and that a page with a live block in each quarter can't hand out a quarter-page
+/// block.
+/// Uses a private allocator, so that the global heap's freelists can't supply any of the blocks.
+#[cfg(feature = "self-test")]
+fn test_coalescing() {
+    let is_mapped = |page| mmu::MMU.lock().mapper.get_mapping(page).is_some();
+    let small = core::alloc::Layout::from_size_align(512, 1).unwrap();
+    let quarter = core::alloc::Layout::from_size_align(1024, 1).unwrap();
+    // The first half of a page overlaps its header, so it is never put on a freelist and a page
+    // holds 4 512-byte blocks.
+    const BLOCKS: usize = 4;
+
+    let mut heap = _HeapAllocator::new();
+    unsafe {
+        let mut blocks = [core::ptr::null_mut(); BLOCKS];
+        for block in &mut blocks {
+            *block = heap.allocate(small).unwrap();
+        }
+        let page = mmu::page_align_down(blocks[0] as usize);
+        for &block in &blocks {
+            assert_eq!(mmu::page_align_down(block as usize), page);
+        }
+
+        // Free the blocks out of order, so that most frees find their buddy still in use.
+        for i in [0, 2, 3] {
+            heap.deallocate(blocks[i], small);
+            assert!(is_mapped(page), "page unmapped with a block still in use");
+        }
+        heap.deallocate(blocks[1], small);
+        assert!(
+            !is_mapped(page),
+            "page not unmapped after freeing every block"
+        );
+
+        // Keep one block in each of the page's usable quarters.
+        for block in &mut blocks {
+            *block = heap.allocate(small).unwrap();
+        }
+        let page = mmu::page_align_down(blocks[0] as usize);
+        let (low, high) = (page + 2048, page + 3072);
+        for &block in &blocks {
+            if block as usize != low && block as usize != high {
+                heap.deallocate(block, small);
+            }
+        }
+        assert!(is_mapped(page));
+        let other = heap.allocate(quarter).unwrap();
+        assert_ne!(
+            mmu::page_align_down(other as usize),
+            page,
+            "quarter-page block allocated from a fragmented page"
+        );
+        heap.deallocate(other, quarter);
+        heap.deallocate(low as *mut u8, small);
+        assert!(is_mapped(page));
+        heap.deallocate(high as *mut u8, small);
+        assert!(!is_mapped(page));
+    }
+}
+
+/// Fragments kernelspace with single-page allocations, then checks that a big allocation is still
+/// placed clear of them, and logs how long finding it took.
+#[cfg(feature = "self-test")]
+fn test_fragmentation() {
+    // A block this big fills the usable half of a page, so each takes a page of its own.
+    let small = core::alloc::Layout::from_size_align(MAX_ALLOC, 1).unwrap();
+    let big = core::alloc::Layout::from_size_align(64 * mmu::PAGE_SIZE, 1).unwrap();
+    const PAGES: usize = 256;
+
+    let mut heap = _HeapAllocator::new();
+    unsafe {
+        let mut pages = [core::ptr::null_mut(); PAGES];
+        for page in &mut pages {
+            *page = heap.allocate(small).unwrap();
+        }
+        // Free every other page, leaving one-page holes.
+        for page in pages.iter().copied().step_by(2) {
+            heap.deallocate(page, small);
+        }
+
+        let start = crate::x86::tsc::rdtsc();
+        let ptr = heap.allocate(big).expect("big allocation failed");
+        let cycles = crate::x86::tsc::rdtsc() - start;
+        let highest = pages.iter().copied().max().unwrap();
+        assert!(
+            ptr as usize > highest as usize,
+            "big allocation placed among small ones"
+        );
+        crate::kdebug!("big allocation after fragmentation took {} cycles", cycles);
+
+        heap.deallocate(ptr, big);
+        for page in pages.iter().copied().skip(1).step_by(2) {
+            heap.deallocate(page, small);
+        }
+    }
+}
+
+/// Allocates, writes to, and frees a big block repeatedly, checking that every iteration reuses the
+/// same virtual pages and leaves them unmapped, so the heap's footprint can't grow.
+#[cfg(feature = "self-test")]
+fn test_big_reuse() {
+    let layout = core::alloc::Layout::from_size_align(16 * mmu::PAGE_SIZE, 1).unwrap();
+    const ITERATIONS: usize = 64;
+
+    let mut heap = _HeapAllocator::new();
+    let mut first = None;
+    for _ in 0..ITERATIONS {
+        unsafe {
+            let ptr = heap.allocate(layout).unwrap();
+            // Touch every page, so that each gets its own physical page to free.
+            ptr.write_bytes(0xaa, layout.size());
+            assert_eq!(*first.get_or_insert(ptr), ptr, "big allocation moved");
+            heap.deallocate(ptr, layout);
+        }
+        let mmu = mmu::MMU.lock();
+        for offset in (0..layout.size()).step_by(mmu::PAGE_SIZE) {
+            let vaddr = first.unwrap() as usize + offset;
+            assert!(
+                mmu.mapper.get_mapping(vaddr).is_none(),
+                "freed page {:#010x} still mapped",
+                vaddr
+            );
+        }
+    }
+}
+
+/// Checks that a zeroed big allocation reads as zero while still mapped to the zero page, i.e.
+/// without having been cleared.
+#[cfg(feature = "self-test")]
+fn test_alloc_zeroed() {
+    let layout = core::alloc::Layout::from_size_align(mmu::PAGE_SIZE, mmu::PAGE_SIZE).unwrap();
+    unsafe {
+        let ptr = alloc::alloc::alloc_zeroed(layout);
+        assert!(!ptr.is_null(), "allocation failed: {:?}", layout);
+        let paddr = mmu::MMU
+            .lock()
+            .mapper
+            .get_mapping(ptr as usize)
+            .unwrap()
+            .physaddr() as usize;
+        assert_eq!(
+            paddr,
+            mmu::mmap::zero_page_paddr(),
+            "zeroed page was written"
+        );
+        let block = core::slice::from_raw_parts(ptr, layout.size());
+        assert!(block.iter().all(|&b| b == 0), "zeroed page isn't zero");
+        alloc::alloc::dealloc(ptr, layout);
+    }
+}
+
 struct FreelistEntry {
     prev: *mut FreelistEntry,
     next: *mut FreelistEntry,
@@ -321,12 +590,7 @@ impl PageHeader {
             // Add the unused sibling to a freelist
             let entry = self.get_freelist_entry(idx ^ 1, depth);
             if !entry.is_null() {
-                (*entry).prev = core::ptr::null_mut();
-                (*entry).next = allocator.freelists[depth];
-                if !allocator.freelists[depth].is_null() {
-                    (*allocator.freelists[depth]).prev = entry;
-                }
-                allocator.freelists[depth] = entry;
+                allocator.push_free(depth, entry);
             }
 
             idx /= 2;
@@ -338,15 +602,7 @@ impl PageHeader {
         // Remove the top-level block from the freelist
         let entry = self.get_freelist_entry(idx, depth);
         if !entry.is_null() {
-            let entry = &mut *entry;
-            if !entry.next.is_null() {
-                (*entry.next).prev = entry.prev;
-            }
-            if !entry.prev.is_null() {
-                (*entry.prev).next = entry.next;
-            } else if allocator.freelists[depth] == entry {
-                allocator.freelists[depth] = entry.next;
-            }
+            allocator.remove_free(depth, entry);
         }
     }
 
@@ -359,16 +615,9 @@ impl PageHeader {
             self.set_block_in_use_recursive(idx, depth, false);
 
             // ...and add the block to the freelist.
-            let head: &mut *mut FreelistEntry = &mut allocator.freelists[depth];
-            let new: *mut FreelistEntry = self.get_freelist_entry(idx, depth);
-
-            if !new.is_null() {
-                (*new).prev = core::ptr::null_mut();
-                (*new).next = *head;
-                if !head.is_null() {
-                    (**head).prev = new;
-                }
-                *head = new;
+            let entry = self.get_freelist_entry(idx, depth);
+            if !entry.is_null() {
+                allocator.push_free(depth, entry);
             }
             false
         } else {
@@ -376,16 +625,7 @@ impl PageHeader {
             // If the sibling is free, remove it from the freelist...
             let entry = self.get_freelist_entry(idx ^ 1, depth);
             if !entry.is_null() {
-                let entry = &mut *entry;
-                if !entry.prev.is_null() {
-                    (*entry.prev).next = entry.next;
-                } else if allocator.freelists[depth] == entry {
-                    allocator.freelists[depth] = entry.next;
-                }
-
-                if !entry.next.is_null() {
-                    (*entry.next).prev = entry.prev;
-                }
+                allocator.remove_free(depth, entry);
             }
 
             // ...and mark the parent as free instead.
@@ -53,10 +53,12 @@ bss:
     call _main  // (should never return, but we want to 'call' it anyway for the sake of stack unwinding)
     jmp _halt
 
-// Allocate some space for the kernel stack within the bss segment.
+// Allocate some space for the kernel stack within the bss segment, below a guard page that the
+// MMU leaves unmapped so that a stack overflow faults.
 .section .bss.kernel_stack, "w"
 .align 4096
-.global _KERNEL_STACK_SIZE, _KERNEL_STACK_BOTTOM, _KERNEL_STACK_TOP
+.global _KERNEL_STACK_GUARD, _KERNEL_STACK_SIZE, _KERNEL_STACK_BOTTOM, _KERNEL_STACK_TOP
+_KERNEL_STACK_GUARD: .space 4096
 _KERNEL_STACK_SIZE = 0x8000 // 32 KiB/8 pages
 _KERNEL_STACK_BOTTOM: .space _KERNEL_STACK_SIZE
 _KERNEL_STACK_TOP:
@@ -3,7 +3,10 @@
 //! Defines data structures and functions ROS programs can use to communicate with the kernel.
 
 #![no_std]
-#![feature(lang_items)]
+#![feature(lang_items, alloc_error_handler)]
+
+// Link against the 'alloc' crate, so programs can use heap-allocated collections
+extern crate alloc;
 
 // export syscall_common and syscall_user as syscall
 mod syscall_common;
@@ -20,7 +20,7 @@
 #![feature(allocator_api, alloc_error_handler)]
 //   APIs for low-level manipulation of Rust objects, used to implement syscalls
 #![feature(layout_for_ptr, slice_ptr_get, slice_ptr_len)]
-use core::arch::global_asm;
+use core::arch::{asm, global_asm};
 
 // Link against the 'alloc' crate, which defines
 // standard library data structures and collection
@@ -48,7 +48,10 @@ pub mod syscall {
     pub use super::{syscall_common::*, syscall_kernel::*};
 }
 
-use core::fmt::Write;
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
 use x86::io::{cga, serial};
 
 use crate::{
@@ -69,18 +72,44 @@ pub extern "C" fn halt() -> ! {
 unsafe fn panic(info: &core::panic::PanicInfo<'_>) -> ! {
     x86::interrupt::cli();
 
-    // Forcibly reset the serial port (even if someone else was using it)
+    // If we panic while reporting a panic, give up rather than recursing.
+    static PANICKING: AtomicBool = AtomicBool::new(false);
+    if PANICKING.swap(true, Ordering::Relaxed) {
+        halt()
+    }
+
+    // Send any queued output, then forcibly reset the serial port (even if someone else was
+    // using it)
+    if let Some(mut com1) = serial::COM1.try_take() {
+        com1.flush();
+    }
     let mut serial = serial::Serial::<{ serial::COM1_BASE }>::new();
 
     let mut write_panic_message = |fmt: core::fmt::Arguments<'_>| {
         let _ = serial.write_fmt(fmt);
-        // Also write to CGA, but ignore conflicts
-        if let Some(mut cga) = cga::CGA.take() {
+        // Also write to CGA in red on white so it stands out, but ignore conflicts
+        if let Some(mut cga) = cga::CGA.try_take() {
+            cga.set_color(cga::Color::Red, cga::Color::White);
             let _ = cga.write_fmt(fmt);
         }
     };
     write_panic_message(format_args!("\n\npanic: {}\n", info));
 
+    match process::percpu::current().current_pid() {
+        0 => write_panic_message(format_args!("No process running\n")),
+        pid => write_panic_message(format_args!("Current process: {}\n", pid)),
+    }
+
+    let (cr0, cr2, cr3, cr4): (usize, usize, usize, usize);
+    asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack));
+    asm!("mov {}, cr2", out(reg) cr2, options(nomem, nostack));
+    asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack));
+    asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack));
+    write_panic_message(format_args!(
+        "cr0={:#010x} cr2={:#010x} cr3={:#010x} cr4={:#010x}\n",
+        cr0, cr2, cr3, cr4
+    ));
+
     write_panic_message(format_args!("Stack trace:"));
     if debug::backtrace(|frame| write_panic_message(format_args!(" {:#08x}", frame))).is_some() {
         write_panic_message(format_args!(" <page fault>"));
@@ -100,42 +129,74 @@ fn alloc_error(layout: core::alloc::Layout) -> ! {
 #[no_mangle]
 #[allow(clippy::stable_sort_primitive)]
 pub extern "C" fn main() -> ! {
-    kprintln!("good morning, that's a nice tnettenba");
+    kinfo!("good morning, that's a nice tnettenba");
+
+    // The boot page directory maps the kernel with 4 MB pages.
+    let features = x86::cpuid::FEATURES.get();
+    kinfo!("CPU features: {}", features);
+    assert!(features.pse(), "CPU does not support 4 MB pages");
 
     // Initialize interrupts and MMU
     let idt = x86::interrupt::IDT.take_and_leak().unwrap();
     idt.lidt();
     let cr3 = {
-        let mut mmu = mmu::MMU.take().unwrap();
+        let mut mmu = mmu::MMU.lock();
         mmu.init();
         mmu.mapper.cr3()
     };
+    #[cfg(feature = "self-test")]
+    {
+        heap::self_test();
+        mmu::palloc::test_usable_regions();
+        mmu::palloc::test_share_overflow();
+        mmu::mmap::test_accessed_bit();
+    }
+    #[cfg(feature = "bench")]
+    {
+        util::mem::bench_copy_page();
+        cga::bench_redraw();
+    }
+    #[cfg(feature = "stack-overflow-test")]
+    x86::interrupt::double_fault::test_stack_overflow();
 
     // Initialize input & handle any pending interrupts
-    x86::io::serial::COM1.take().unwrap().enable_interrupts();
-    x86::io::keyboard::KEYBOARD.take().unwrap().handle_input();
+    x86::io::serial::COM1.lock().enable_interrupts();
+    x86::io::keyboard::KEYBOARD.lock().handle_input();
+    x86::io::mouse::MOUSE.lock().handle_input();
     unsafe {
         process::fd::CONSOLE_BUFFER.init();
     }
 
+    x86::fpu::init();
+
+    // Calibrate the timestamp counter
+    kinfo!("TSC frequency: {} MHz", *x86::tsc::TSC_HZ.get() / 1_000_000);
+
     // Enable interrupts
     x86::interrupt::sti();
 
     // Find ELF files located on disk after the kernel binary
     let elves = elfloader::ELVES.get();
-    kprintln!(
+    kinfo!(
         "Found {} {}.",
         elves.len(),
         if elves.len() == 1 { "elf" } else { "elves" }
     );
     // Execute the first elf
-    let trap_frame = elves
+    let (trap_frame, brk) = elves
         .first()
         .expect("no elves found")
         .load()
         .expect("failed to load elf");
 
-    let mut scheduler = scheduler::Scheduler::new(x86::env::Env { cr3, trap_frame });
+    let mut scheduler = scheduler::Scheduler::new(x86::env::Env {
+        cr3,
+        trap_frame,
+        brk_start: brk,
+        brk,
+        debug_regs: Default::default(),
+        fpu: x86::fpu::FpuState::new(),
+    });
 
     // set up stdio descriptors
     let console = alloc::rc::Rc::new(core::cell::RefCell::new(process::fd::Console));
@@ -143,6 +204,6 @@ pub extern "C" fn main() -> ! {
     scheduler.set_fd(scheduler.current_pid(), 1, Some(console.clone()));
     scheduler.set_fd(scheduler.current_pid(), 2, Some(console));
 
-    kprintln!("entering userland!");
+    kinfo!("entering userland!");
     scheduler.run()
 }
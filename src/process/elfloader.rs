@@ -2,7 +2,7 @@ use crate::{
     util::Lazy,
     x86::{interrupt::InterruptFrame, io::pio, mmu},
 };
-use alloc::vec::Vec;
+use alloc::{string::String, vec::Vec};
 
 pub static ELVES: Lazy<Vec<Elf32>> = Lazy::new(find_elves);
 
@@ -24,6 +24,8 @@ fn find_elves() -> Vec<Elf32> {
 /// An ELF header.
 #[derive(Debug)]
 pub struct Elf32 {
+    /// The name of the program, or an empty string if the image builder didn't record one.
+    pub name: String,
     pub start_offset: u32,
     pub program_headers: Vec<ProgramHeader>,
     pub max_offset: usize,
@@ -31,13 +33,14 @@ pub struct Elf32 {
 }
 
 impl Elf32 {
-    /// Loads the contents of an ELF file into memory.
-    pub fn load(&self) -> Result<InterruptFrame, pio::Error> {
-        let mut pio = pio::PIO.take().unwrap();
+    /// Loads the contents of an ELF file into memory, returning the initial trap frame and the
+    /// initial program break.
+    pub fn load(&self) -> Result<(InterruptFrame, usize), pio::Error> {
+        let mut pio = pio::PIO.lock();
 
         // First, map all the memory
         {
-            let mut mmu = mmu::MMU.take().unwrap();
+            let mut mmu = mmu::MMU.lock();
             let mmu = &mut *mmu;
 
             // Unmap userspace first
@@ -88,9 +91,9 @@ impl Elf32 {
             }
         }
 
-        // Allocate a 32-KiB user stack
-        let user_stack_top = {
-            let mut mmu = mmu::MMU.take().unwrap();
+        // Allocate a 32-KiB user stack, and start the heap at the next free page
+        let (user_stack_top, brk) = {
+            let mut mmu = mmu::MMU.lock();
             let mmu = &mut *mmu;
 
             let user_stack_bytes = 0x8000;
@@ -108,11 +111,16 @@ impl Elf32 {
                     .with_user_accessible(true),
             );
 
-            user_stack + user_stack_bytes
+            let brk = mmu
+                .mapper
+                .find_unused_userspace(1)
+                .expect("not enough address space for program heap");
+
+            (user_stack + user_stack_bytes, brk)
         };
 
         // Create an initial trap frame
-        Ok(InterruptFrame {
+        let trap_frame = InterruptFrame {
             eip: self.entrypoint,
             cs: mmu::SegmentId::UserCode as usize,
             ds: mmu::SegmentId::UserData as usize,
@@ -123,7 +131,8 @@ impl Elf32 {
             user_esp: user_stack_top,
             eflags: 0x200, // enable interrupts
             ..Default::default()
-        })
+        };
+        Ok((trap_frame, brk))
     }
 }
 
@@ -136,7 +145,7 @@ pub struct ProgramHeader {
 }
 
 pub fn read_elf_headers(offset: u32) -> Result<Option<Elf32>, pio::Error> {
-    let mut pio = pio::PIO.take().unwrap();
+    let mut pio = pio::PIO.lock();
 
     let mut header_reader = pio
         .reader(offset / pio::SECTOR_SIZE as u32)
@@ -199,6 +208,7 @@ pub fn read_elf_headers(offset: u32) -> Result<Option<Elf32>, pio::Error> {
     let ph_entry_count = read_u16(&mut header_reader)?;
     let sh_entry_size = read_u16(&mut header_reader)?;
     let sh_entry_count = read_u16(&mut header_reader)?;
+    let sh_string_index = read_u16(&mut header_reader)?;
 
     let mut max_offset = core::cmp::max(
         header_size,
@@ -238,26 +248,50 @@ pub fn read_elf_headers(offset: u32) -> Result<Option<Elf32>, pio::Error> {
         }
     }
 
-    // Read just enough of the section headers to determine max_offset
+    // Read just enough of the section headers to determine max_offset and find the name
+    let mut sections = Vec::new();
     let mut sh_reader = pio
         .reader((offset + sh_offset) / pio::SECTOR_SIZE as u32)
         .skip((offset + sh_offset) as usize % pio::SECTOR_SIZE);
 
     for _ in 0..sh_entry_count {
-        let _name = read_u32(&mut sh_reader)?;
+        let name = read_u32(&mut sh_reader)? as usize;
         let _ty = read_u32(&mut sh_reader)?;
         read_u32(&mut sh_reader)?;
         read_u32(&mut sh_reader)?;
         let offset = read_u32(&mut sh_reader)? as usize;
         let size = read_u32(&mut sh_reader)? as usize;
         max_offset = core::cmp::max(max_offset, offset + size);
+        sections.push((name, offset, size));
 
         for _ in 0..(sh_entry_size - 24) {
             sh_reader.next().unwrap()?;
         }
     }
 
+    // mkimage.sh stores the program's name in the .ros.name section
+    let mut read_bytes = |start: usize, len: usize| -> Result<Vec<u8>, pio::Error> {
+        let start = offset + start as u32;
+        pio.reader(start / pio::SECTOR_SIZE as u32)
+            .skip(start as usize % pio::SECTOR_SIZE)
+            .take(len)
+            .collect()
+    };
+    let mut name = String::new();
+    if let Some(&(_, strtab_offset, strtab_size)) = sections.get(sh_string_index as usize) {
+        let strtab = read_bytes(strtab_offset, strtab_size)?;
+        for &(name_offset, offset, size) in &sections {
+            if strtab
+                .get(name_offset..)
+                .is_some_and(|s| s.starts_with(b".ros.name\0"))
+            {
+                name = String::from_utf8_lossy(&read_bytes(offset, size)?).into_owned();
+            }
+        }
+    }
+
     Ok(Some(Elf32 {
+        name,
         start_offset: offset,
         program_headers,
         max_offset,
@@ -1,13 +1,15 @@
-use ::alloc::{collections::VecDeque, rc::Rc};
+use ::alloc::{collections::VecDeque, rc::Rc, string::String, vec::Vec};
 use alloc::alloc;
 use core::{
     cell::RefCell,
     sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
 };
+use hashbrown::HashMap;
 
 use crate::{
-    syscall::{ReadError, WriteError},
-    x86::io,
+    syscall::{ioctl, IoctlError, MouseEvent, ReadError, WinSize, WriteError},
+    util::Global,
+    x86::{interrupt, io},
 };
 
 /// A file descriptor backend.
@@ -39,6 +41,19 @@ pub trait File {
         true
     }
 
+    /// Returns the number of bytes that can currently be read without blocking.
+    /// The default implementation always returns 0.
+    fn available(&self) -> usize {
+        0
+    }
+
+    /// Performs a device-specific control operation, as requested by the 'ioctl' syscall.
+    /// The default implementation always returns IoctlError::Unsupported.
+    fn ioctl(&mut self, request: u32, arg: &mut [u8]) -> Result<(), IoctlError> {
+        let _ = (request, arg);
+        Err(IoctlError::Unsupported)
+    }
+
     /// Returns true if this file descriptor can be accessed in the given manner (read or write).
     fn can_access(&mut self, ty: AccessType) -> bool {
         match ty {
@@ -55,8 +70,21 @@ pub enum AccessType {
 }
 
 /// A ring buffer to store incoming console bytes.  We have to be kinda careful when accessing
-/// this, because it can be written asynchronously from an interrupt context.
+/// this, because it can be written asynchronously from an interrupt context. Every operation runs
+/// with interrupts disabled, so operations are serialized rather than interleaved.
+///
+/// Bytes that arrive while the buffer is full are dropped. To keep a fast sender (such as a paste
+/// into the serial terminal) from overrunning it, the buffer uses XON/XOFF flow control: once it
+/// is three-quarters full it sends XOFF over the serial port, and once readers have drained it to
+/// a quarter full it sends XON. With the `rts-cts` feature, it also deasserts and reasserts RTS.
+///
+/// While local echo is enabled, a backspace (0x7F or 0x08) is handled as a line-editing command:
+/// it removes the last byte that hasn't been read yet and erases it from the screen, and never
+/// reaches readers itself. With echo disabled, backspaces are passed through for the reader to
+/// handle.
 const CONSOLE_BUFSIZE: usize = 4096;
+const FLOW_PAUSE_LEVEL: usize = CONSOLE_BUFSIZE * 3 / 4;
+const FLOW_RESUME_LEVEL: usize = CONSOLE_BUFSIZE / 4;
 pub struct ConsoleBuffer {
     /// The buffer; an array of length CONSOLE_BUFSIZE,
     /// or null if the console has not yet been initialized.
@@ -71,29 +99,30 @@ pub struct ConsoleBuffer {
     /// The position of the writer.
     wpos: AtomicUsize,
 
-    /// True if the buffer is currently being read.
-    read_lock: AtomicBool,
+    /// The number of echoed characters that have since been erased by a backspace, and so must be
+    /// erased from the screen.
+    erase_pending: AtomicUsize,
+
+    /// True if incoming characters should be echoed to the console.
+    echo_enabled: AtomicBool,
 
-    /// True if the buffer is currently being written.
-    write_lock: AtomicBool,
+    /// True if we've sent XOFF and not yet sent XON.
+    flow_paused: AtomicBool,
 }
 pub static CONSOLE_BUFFER: ConsoleBuffer = ConsoleBuffer::new();
 
 impl ConsoleBuffer {
     /// Processes local echo.
     pub fn handle_echo(&self) {
-        assert!(
-            !self.read_lock.swap(true, Ordering::Acquire),
-            "simultaneous read from console buffer"
-        );
-        unsafe {
-            self._handle_echo();
-        }
-        self.read_lock.store(false, Ordering::Release);
+        interrupt::with_interrupts_disabled(|| unsafe { self._handle_echo() })
     }
 
     /// Initializes the console buffer.
     ///
+    /// Input interrupts that arrive before this is called see a null buffer and drop the input.
+    /// The buffer lives on the kernel heap, which every address space maps identically (see
+    /// `MemoryMapper::fork`), so it can be accessed no matter which process is running.
+    ///
     /// # Safety
     ///
     /// The caller must ensure this function is called only once.
@@ -110,11 +139,29 @@ impl ConsoleBuffer {
             rpos: AtomicUsize::new(0),
             epos: AtomicUsize::new(0),
             wpos: AtomicUsize::new(0),
-            read_lock: AtomicBool::new(false),
-            write_lock: AtomicBool::new(false),
+            erase_pending: AtomicUsize::new(0),
+            echo_enabled: AtomicBool::new(true),
+            flow_paused: AtomicBool::new(false),
         }
     }
 
+    /// Enables or disables local echo.
+    /// Characters received before the change are echoed according to the old setting; characters
+    /// received while echo is disabled are never echoed, even if echo is later re-enabled.
+    pub fn set_echo(&self, enabled: bool) {
+        interrupt::with_interrupts_disabled(|| {
+            unsafe {
+                self._handle_echo();
+            }
+            self.echo_enabled.store(enabled, Ordering::Relaxed);
+        })
+    }
+
+    /// Returns true if local echo is enabled.
+    pub fn echo_enabled(&self) -> bool {
+        self.echo_enabled.load(Ordering::Relaxed)
+    }
+
     /// Recieves an input character. This function is meant to be called from an interrupt context.
     pub fn recv_input(&self, c: u8) {
         let input_buf = self.buf.load(Ordering::Acquire);
@@ -122,28 +169,44 @@ impl ConsoleBuffer {
             return;
         }
 
-        assert!(
-            !self.write_lock.swap(true, Ordering::Acquire),
-            "simultaneous write to console buffer"
-        );
+        interrupt::with_interrupts_disabled(|| {
+            let wpos = self.wpos.load(Ordering::Relaxed);
+            let rpos = self.rpos.load(Ordering::Acquire);
+            if (c == 0x7F || c == 0x08) && self.echo_enabled.load(Ordering::Relaxed) {
+                // Backspace: remove the last unread character, if there is one.
+                if wpos != rpos {
+                    let new_wpos = wpos.checked_sub(1).unwrap_or(CONSOLE_BUFSIZE - 1);
+                    if self.epos.load(Ordering::Relaxed) == wpos {
+                        // It has already been echoed, so erase it from the screen too.
+                        self.epos.store(new_wpos, Ordering::Relaxed);
+                        self.erase_pending.fetch_add(1, Ordering::Relaxed);
+                    }
+                    self.wpos.store(new_wpos, Ordering::Release);
+                }
+                return;
+            }
+            if wpos == rpos.checked_sub(1).unwrap_or(CONSOLE_BUFSIZE - 1) {
+                // The buffer is full, ignore the character.
+                return;
+            }
 
-        let wpos = self.wpos.load(Ordering::Relaxed);
-        let rpos = self.rpos.load(Ordering::Acquire);
-        if wpos == rpos.checked_sub(1).unwrap_or(CONSOLE_BUFSIZE - 1) {
-            // The buffer is full, ignore the character.
-            self.write_lock.store(false, Ordering::Release);
-            return;
-        }
+            unsafe {
+                *input_buf.add(wpos) = c;
+            }
+            let wpos = (wpos + 1) % CONSOLE_BUFSIZE;
+            self.wpos.store(wpos, Ordering::Release);
 
-        unsafe {
-            *input_buf.add(wpos) = c;
-        }
-        self.wpos
-            .store((wpos + 1) % CONSOLE_BUFSIZE, Ordering::Release);
+            let used = (wpos + CONSOLE_BUFSIZE - rpos) % CONSOLE_BUFSIZE;
+            if used >= FLOW_PAUSE_LEVEL && !self.flow_paused.swap(true, Ordering::Relaxed) {
+                io::serial::Serial::<{ io::serial::COM1_BASE }>::set_input_paused(true);
+            }
+        });
 
-        self.write_lock.store(false, Ordering::Release);
+        // Make sure a process blocked on console input notices the new character.
+        super::scheduler::wake();
     }
 
+    /// Echoes pending input. Must be called with interrupts disabled.
     unsafe fn _handle_echo(&self) {
         let input_buf = self.buf.load(Ordering::Acquire);
         if input_buf.is_null() {
@@ -153,68 +216,97 @@ impl ConsoleBuffer {
         let wpos = self.wpos.load(Ordering::Acquire);
         let mut epos = self.epos.load(Ordering::Relaxed);
 
-        let mut serial = io::serial::COM1.take().expect("serial conflict");
-        let mut cga = io::cga::CGA.take().expect("CGA conflict");
+        // If echo is disabled, just skip over the pending characters.
+        if self.echo_enabled.load(Ordering::Relaxed) {
+            let mut serial = io::serial::COM1.lock();
+            let mut cga = io::cga::CGA.lock();
 
-        while epos != wpos {
-            let c = *input_buf.add(epos);
-            serial.write_byte(c);
-            cga.write_byte(c);
-            epos = (epos + 1) % CONSOLE_BUFSIZE;
+            for _ in 0..self.erase_pending.swap(0, Ordering::Relaxed) {
+                serial.write_bytes(b"\x08 \x08");
+                cga.write_bytes(b"\x08 \x08");
+            }
+            while epos != wpos {
+                let c = *input_buf.add(epos);
+                serial.write_byte(c);
+                cga.write_byte(c);
+                epos = (epos + 1) % CONSOLE_BUFSIZE;
+            }
         }
 
-        self.epos.store(epos, Ordering::Relaxed);
+        self.epos.store(wpos, Ordering::Relaxed);
     }
 
+    /// Reads from the buffer. Multiple processes may share the console as their input, so the
+    /// read is performed with interrupts disabled to keep it from being interleaved with another
+    /// reader or with local echo.
     fn read(&self, buf: &mut [u8]) -> usize {
         let input_buf = self.buf.load(Ordering::Acquire);
         if input_buf.is_null() {
             return 0;
         }
-        assert!(
-            !self.read_lock.swap(true, Ordering::Acquire),
-            "simultaneous read from console buffer"
-        );
-        unsafe {
-            self._handle_echo();
-        }
-
-        let mut bufpos = 0;
-        let wpos = self.wpos.load(Ordering::Acquire);
-        let mut rpos = self.rpos.load(Ordering::Relaxed);
 
-        while rpos != wpos && bufpos < buf.len() {
+        interrupt::with_interrupts_disabled(|| {
             unsafe {
-                buf[bufpos] = *input_buf.add(rpos);
+                self._handle_echo();
             }
-            rpos = (rpos + 1) % CONSOLE_BUFSIZE;
-            bufpos += 1;
-        }
 
-        self.rpos.store(rpos, Ordering::Release);
-        self.read_lock.store(false, Ordering::Release);
-        bufpos
+            let mut bufpos = 0;
+            let wpos = self.wpos.load(Ordering::Acquire);
+            let mut rpos = self.rpos.load(Ordering::Relaxed);
+
+            while rpos != wpos && bufpos < buf.len() {
+                unsafe {
+                    buf[bufpos] = *input_buf.add(rpos);
+                }
+                rpos = (rpos + 1) % CONSOLE_BUFSIZE;
+                bufpos += 1;
+            }
+
+            self.rpos.store(rpos, Ordering::Release);
+
+            let used = (wpos + CONSOLE_BUFSIZE - rpos) % CONSOLE_BUFSIZE;
+            if used <= FLOW_RESUME_LEVEL && self.flow_paused.swap(false, Ordering::Relaxed) {
+                io::serial::Serial::<{ io::serial::COM1_BASE }>::set_input_paused(false);
+            }
+            bufpos
+        })
+    }
+
+    /// Discards any input waiting to be read. Pending characters are still echoed.
+    fn discard_input(&self) {
+        interrupt::with_interrupts_disabled(|| {
+            unsafe {
+                self._handle_echo();
+            }
+            self.rpos
+                .store(self.wpos.load(Ordering::Acquire), Ordering::Release);
+            if self.flow_paused.swap(false, Ordering::Relaxed) {
+                io::serial::Serial::<{ io::serial::COM1_BASE }>::set_input_paused(false);
+            }
+        })
     }
 
     fn can_read(&self) -> bool {
-        assert!(
-            !self.read_lock.swap(true, Ordering::Acquire),
-            "simultaneous read from console buffer"
-        );
-        let can_read = self.rpos.load(Ordering::Relaxed) != self.wpos.load(Ordering::Acquire);
-        self.read_lock.store(false, Ordering::Release);
-        can_read
+        interrupt::with_interrupts_disabled(|| {
+            self.rpos.load(Ordering::Relaxed) != self.wpos.load(Ordering::Acquire)
+        })
+    }
+
+    /// Returns the number of bytes waiting to be read.
+    fn available(&self) -> usize {
+        interrupt::with_interrupts_disabled(|| {
+            let rpos = self.rpos.load(Ordering::Relaxed);
+            let wpos = self.wpos.load(Ordering::Acquire);
+            (wpos + CONSOLE_BUFSIZE - rpos) % CONSOLE_BUFSIZE
+        })
     }
 }
 
 pub struct Console;
 impl File for Console {
     fn write(&mut self, buf: &[u8]) -> Result<usize, WriteError> {
-        io::serial::COM1
-            .take()
-            .expect("serial conflict")
-            .write_bytes(buf);
-        io::cga::CGA.take().expect("CGA conflict").write_bytes(buf);
+        io::serial::COM1.lock().write_bytes(buf);
+        io::cga::CGA.lock().write_bytes(buf);
 
         Ok(buf.len())
     }
@@ -226,6 +318,105 @@ impl File for Console {
     fn can_read(&mut self) -> bool {
         CONSOLE_BUFFER.can_read()
     }
+
+    fn available(&self) -> usize {
+        CONSOLE_BUFFER.available()
+    }
+
+    fn ioctl(&mut self, request: u32, arg: &mut [u8]) -> Result<(), IoctlError> {
+        match (request, arg) {
+            (ioctl::CONSOLE_SET_ECHO, &mut [enabled]) if enabled <= 1 => {
+                CONSOLE_BUFFER.set_echo(enabled != 0)
+            }
+            (ioctl::CONSOLE_GET_ECHO, [enabled]) => *enabled = CONSOLE_BUFFER.echo_enabled() as u8,
+            (ioctl::CONSOLE_FLUSH_INPUT, []) => CONSOLE_BUFFER.discard_input(),
+            (ioctl::CONSOLE_PUSH_INPUT, arg) => {
+                for &c in arg.iter() {
+                    CONSOLE_BUFFER.recv_input(c);
+                }
+            }
+            (ioctl::CONSOLE_GET_WINSIZE, arg) if arg.len() == WinSize::SIZE => {
+                let size = WinSize {
+                    cols: io::cga::CGA_WIDTH as u16,
+                    rows: io::cga::CGA_HEIGHT as u16,
+                };
+                arg.copy_from_slice(&size.to_bytes());
+            }
+            (
+                ioctl::CONSOLE_SET_ECHO
+                | ioctl::CONSOLE_GET_ECHO
+                | ioctl::CONSOLE_FLUSH_INPUT
+                | ioctl::CONSOLE_GET_WINSIZE,
+                _,
+            ) => return Err(IoctlError::InvalidArgument),
+            _ => return Err(IoctlError::Unsupported),
+        }
+        Ok(())
+    }
+}
+
+/// The raw keyboard device, which reads scancodes exactly as the keyboard sends them.
+pub struct RawKeyboard(());
+impl RawKeyboard {
+    pub fn open() -> Self {
+        io::keyboard::KEYBOARD.lock().open_raw();
+        RawKeyboard(())
+    }
+}
+impl Drop for RawKeyboard {
+    fn drop(&mut self) {
+        io::keyboard::KEYBOARD.lock().close_raw();
+    }
+}
+impl File for RawKeyboard {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ReadError> {
+        Ok(io::keyboard::KEYBOARD.lock().read_raw(buf))
+    }
+
+    fn can_read(&mut self) -> bool {
+        self.available() != 0
+    }
+
+    fn available(&self) -> usize {
+        io::keyboard::KEYBOARD.lock().raw_available()
+    }
+}
+
+/// The mouse device, which reads encoded `MouseEvent`s.
+pub struct Mouse(());
+impl Mouse {
+    pub fn open() -> Self {
+        io::mouse::MOUSE.lock().open();
+        Mouse(())
+    }
+}
+impl Drop for Mouse {
+    fn drop(&mut self) {
+        io::mouse::MOUSE.lock().close();
+    }
+}
+impl File for Mouse {
+    /// Reads as many whole events as fit in the buffer.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ReadError> {
+        let mut mouse = io::mouse::MOUSE.lock();
+        let mut len = 0;
+        for chunk in buf.chunks_exact_mut(MouseEvent::SIZE) {
+            match mouse.pop_event() {
+                Some(event) => chunk.copy_from_slice(&event.to_bytes()),
+                None => break,
+            }
+            len += MouseEvent::SIZE;
+        }
+        Ok(len)
+    }
+
+    fn can_read(&mut self) -> bool {
+        self.available() != 0
+    }
+
+    fn available(&self) -> usize {
+        io::mouse::MOUSE.lock().events_available() * MouseEvent::SIZE
+    }
 }
 
 pub struct Null;
@@ -241,61 +432,241 @@ impl File for Null {
 
 // Pipes
 // A pipe has two files associated with it: a read half and a write half. Both halves share a
-// buffer using reference counting; the reference count of the buffer is therefore always 2 unless
-// one half is closed.
+// PipeState, which counts the open halves of each kind. A half is shared between processes (and
+// between descriptors after 'dup2') through the file table's Rc, so it is only dropped -- and the
+// count decremented -- once the last descriptor referring to it is closed or its process exits.
 
-/// The maximum buffer size for a pipe. If the buffer is full, writes will block.
+/// The default buffer size for a pipe. If the buffer is full, writes will block.
 pub const PIPE_BUF_LEN: usize = 1 << 16;
 
+/// The largest buffer size that may be requested for a pipe.
+pub const PIPE_BUF_MAX: usize = 1 << 20;
+
+/// The state shared by the halves of a pipe.
+#[derive(Default)]
+struct PipeState {
+    buf: VecDeque<u8>,
+    /// The number of open read halves.
+    readers: usize,
+    /// The number of open write halves.
+    writers: usize,
+}
+
 struct PipeRead {
-    buf: Rc<RefCell<VecDeque<u8>>>,
+    state: Rc<RefCell<PipeState>>,
+}
+impl PipeRead {
+    fn new(state: Rc<RefCell<PipeState>>) -> PipeRead {
+        state.borrow_mut().readers += 1;
+        PipeRead { state }
+    }
+}
+impl Drop for PipeRead {
+    fn drop(&mut self) {
+        self.state.borrow_mut().readers -= 1;
+    }
 }
 impl File for PipeRead {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, ReadError> {
-        let mut src = self.buf.borrow_mut();
-        if src.is_empty() && Rc::strong_count(&self.buf) == 1 {
+        let mut state = self.state.borrow_mut();
+        if state.buf.is_empty() && state.writers == 0 {
             // The write half is closed and the buffer is empty, EOF.
-            src.shrink_to_fit();
+            state.buf.shrink_to_fit();
             Ok(0)
         } else {
-            let count = core::cmp::min(buf.len(), src.len());
+            let count = core::cmp::min(buf.len(), state.buf.len());
             buf.iter_mut()
-                .zip(src.drain(0..count))
+                .zip(state.buf.drain(0..count))
                 .for_each(|(a, b)| *a = b);
             Ok(count)
         }
     }
 
     fn can_read(&mut self) -> bool {
-        !self.buf.borrow().is_empty() || Rc::strong_count(&self.buf) == 1
+        let state = self.state.borrow();
+        !state.buf.is_empty() || state.writers == 0
+    }
+
+    fn available(&self) -> usize {
+        self.state.borrow().buf.len()
     }
 }
 
 struct PipeWrite {
-    buf: Rc<RefCell<VecDeque<u8>>>,
+    state: Rc<RefCell<PipeState>>,
+    /// The maximum number of bytes the buffer may hold.
+    capacity: usize,
+}
+impl PipeWrite {
+    fn new(state: Rc<RefCell<PipeState>>, capacity: usize) -> PipeWrite {
+        state.borrow_mut().writers += 1;
+        PipeWrite { state, capacity }
+    }
+}
+impl Drop for PipeWrite {
+    fn drop(&mut self) {
+        self.state.borrow_mut().writers -= 1;
+    }
 }
 impl File for PipeWrite {
     fn write(&mut self, buf: &[u8]) -> Result<usize, WriteError> {
-        let mut dst = self.buf.borrow_mut();
-        if Rc::strong_count(&self.buf) == 1 {
+        let mut state = self.state.borrow_mut();
+        if state.readers == 0 {
             // The read half is closed, just discard everything.
-            *dst = VecDeque::new(); // clear the buffer
+            state.buf = VecDeque::new(); // clear the buffer
             Ok(buf.len())
         } else {
-            let count = core::cmp::min(buf.len(), PIPE_BUF_LEN - dst.len());
-            buf[0..count].iter().for_each(|&x| dst.push_back(x));
+            let count = core::cmp::min(buf.len(), self.capacity - state.buf.len());
+            buf[0..count].iter().for_each(|&x| state.buf.push_back(x));
             Ok(count)
         }
     }
 
     fn can_write(&mut self) -> bool {
-        self.buf.borrow().len() != PIPE_BUF_LEN || Rc::strong_count(&self.buf) == 1
+        let state = self.state.borrow();
+        state.buf.len() != self.capacity || state.readers == 0
     }
 }
 
+/// Creates the two halves of a pipe with a buffer of `capacity` bytes.
+fn new_pipe(capacity: usize) -> (PipeRead, PipeWrite) {
+    let state = Rc::new(RefCell::new(PipeState::default()));
+    (
+        PipeRead::new(state.clone()),
+        PipeWrite::new(state, capacity),
+    )
+}
+
 /// Opens a new pipe, returning a read half and a write half.
 /// Data written to the write half can be read out the read half.
 pub fn pipe() -> (impl File, impl File) {
-    let buf = Rc::new(RefCell::new(VecDeque::new()));
-    (PipeRead { buf: buf.clone() }, PipeWrite { buf })
+    pipe_sized(PIPE_BUF_LEN)
+}
+
+/// Opens a new pipe with a buffer of the given size, clamped to between 1 and PIPE_BUF_MAX bytes.
+pub fn pipe_sized(capacity: usize) -> (impl File, impl File) {
+    new_pipe(capacity.clamp(1, PIPE_BUF_MAX))
+}
+
+// Named pipes
+// A FIFO lets processes that don't share file descriptors communicate through a pipe. Opening a
+// FIFO creates a new pipe and returns one half; the other half is kept in the FIFO until a process
+// opens it from the other direction. Each reader is therefore connected to exactly one writer,
+// and data written before the reader arrives is buffered in the pipe.
+
+/// A named pipe.
+#[derive(Default)]
+pub struct Fifo {
+    /// The half of a pipe waiting for a process to open the FIFO from the other direction.
+    pending: Option<FifoHalf>,
+}
+
+enum FifoHalf {
+    Read(PipeRead),
+    Write(PipeWrite),
+}
+
+/// The named pipes, keyed by name.
+pub static FIFOS: Global<HashMap<String, Fifo>> = Global::lazy(HashMap::new);
+
+impl Fifo {
+    /// Opens the FIFO for reading or writing. Returns None if a process has already opened the
+    /// FIFO in the same direction and is still waiting for the other end to be opened.
+    pub fn open(&mut self, access_type: AccessType) -> Option<Rc<RefCell<dyn File>>> {
+        let file: Rc<RefCell<dyn File>> = match (self.pending.take(), access_type) {
+            (Some(FifoHalf::Read(read)), AccessType::Read) => Rc::new(RefCell::new(read)),
+            (Some(FifoHalf::Write(write)), AccessType::Write) => Rc::new(RefCell::new(write)),
+            (None, _) => {
+                let (read, write) = new_pipe(PIPE_BUF_LEN);
+                match access_type {
+                    AccessType::Read => {
+                        self.pending = Some(FifoHalf::Write(write));
+                        Rc::new(RefCell::new(read))
+                    }
+                    AccessType::Write => {
+                        self.pending = Some(FifoHalf::Read(read));
+                        Rc::new(RefCell::new(write))
+                    }
+                }
+            }
+            (pending, _) => {
+                self.pending = pending;
+                return None;
+            }
+        };
+        Some(file)
+    }
+}
+
+/// One end of a bidirectional pipe, made up of the read half of one pipe and the write half of
+/// another.
+struct DuplexPipe {
+    read: PipeRead,
+    write: PipeWrite,
+}
+impl File for DuplexPipe {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ReadError> {
+        self.read.read(buf)
+    }
+
+    fn can_read(&mut self) -> bool {
+        self.read.can_read()
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, WriteError> {
+        self.write.write(buf)
+    }
+
+    fn can_write(&mut self) -> bool {
+        self.write.can_write()
+    }
+
+    fn available(&self) -> usize {
+        self.read.available()
+    }
+}
+
+/// Opens a pair of connected bidirectional pipes.
+/// Data written to either end can be read out the other end.
+pub fn socketpair() -> (impl File, impl File) {
+    let (a_read, a_write) = new_pipe(PIPE_BUF_LEN);
+    let (b_read, b_write) = new_pipe(PIPE_BUF_LEN);
+    (
+        DuplexPipe {
+            read: a_read,
+            write: b_write,
+        },
+        DuplexPipe {
+            read: b_read,
+            write: a_write,
+        },
+    )
+}
+
+/// A file that duplicates everything written to it into several other files.
+pub struct Tee {
+    targets: Vec<Rc<RefCell<dyn File>>>,
+}
+impl Tee {
+    pub fn new(targets: Vec<Rc<RefCell<dyn File>>>) -> Tee {
+        Tee { targets }
+    }
+}
+impl File for Tee {
+    /// Writes the buffer to each target, returning the smallest number of bytes written to any of
+    /// them. Note that if some targets accept fewer bytes than others, the remainder of the data
+    /// will be written to those targets more than once if the caller retries the write.
+    fn write(&mut self, buf: &[u8]) -> Result<usize, WriteError> {
+        let mut written = buf.len();
+        for target in &self.targets {
+            written = core::cmp::min(written, target.borrow_mut().write(buf)?);
+        }
+        Ok(written)
+    }
+
+    fn can_write(&mut self) -> bool {
+        self.targets
+            .iter()
+            .all(|target| target.borrow_mut().can_write())
+    }
 }
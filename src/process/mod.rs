@@ -1,3 +1,4 @@
 pub mod elfloader;
 pub mod fd;
+pub mod percpu;
 pub mod scheduler;
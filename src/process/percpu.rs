@@ -0,0 +1,88 @@
+//! Per-CPU scheduler state.
+//!
+//! Each CPU gets its own `Cpu` structure holding the state that describes what that CPU is doing:
+//! the process it's running, its kernel stack (via its task state segment), and whether its
+//! preemption timer fired while it was in kernelspace.
+//!
+//! Only the boot CPU is started for now, so `current` always returns CPU 0. Everything else is
+//! still single-core-only: the scheduler's process map and run queue are shared by all CPUs behind
+//! the `SCHEDULER` global, and `Global<T>` does not yet provide mutual exclusion between CPUs.
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use crate::{process::scheduler::Pid, util::Global, x86::env::TaskStateSegment};
+
+/// The maximum number of CPUs the kernel supports.
+pub const MAX_CPUS: usize = 1;
+
+/// State belonging to a single CPU.
+pub struct Cpu {
+    /// The process running on this CPU.
+    current_pid: AtomicU32,
+
+    /// Set if the preemption timer fired while this CPU was in kernelspace.
+    timer_fired: AtomicBool,
+
+    /// The number of side effects performed by syscalls on this CPU, used to check that
+    /// restartable syscalls don't block after performing one.
+    #[cfg(debug_assertions)]
+    side_effects: AtomicUsize,
+
+    /// This CPU's task state segment, which holds the kernel stack pointer used on entry from
+    /// userspace.
+    pub tss: Global<TaskStateSegment>,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+static CPUS: [Cpu; MAX_CPUS] = {
+    const CPU: Cpu = Cpu::new();
+    [CPU; MAX_CPUS]
+};
+
+/// Returns the ID of the executing CPU.
+pub fn id() -> usize {
+    0
+}
+
+/// Returns the per-CPU state of the executing CPU.
+pub fn current() -> &'static Cpu {
+    &CPUS[id()]
+}
+
+impl Cpu {
+    const fn new() -> Cpu {
+        Cpu {
+            current_pid: AtomicU32::new(0),
+            timer_fired: AtomicBool::new(false),
+            #[cfg(debug_assertions)]
+            side_effects: AtomicUsize::new(0),
+            tss: Global::lazy_default(),
+        }
+    }
+
+    /// Returns the PID of the process running on this CPU.
+    pub fn current_pid(&self) -> Pid {
+        self.current_pid.load(Ordering::Relaxed)
+    }
+
+    pub fn set_current_pid(&self, pid: Pid) {
+        self.current_pid.store(pid, Ordering::Relaxed)
+    }
+
+    /// Records whether the preemption timer fired while this CPU was in kernelspace, returning the
+    /// previous value.
+    pub fn swap_timer_fired(&self, fired: bool) -> bool {
+        self.timer_fired.swap(fired, Ordering::Relaxed)
+    }
+
+    /// Records that the running syscall has performed a side effect.
+    #[cfg(debug_assertions)]
+    pub fn note_side_effect(&self) {
+        self.side_effects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of side effects performed by syscalls on this CPU.
+    #[cfg(debug_assertions)]
+    pub fn side_effects(&self) -> usize {
+        self.side_effects.load(Ordering::Relaxed)
+    }
+}
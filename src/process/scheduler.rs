@@ -1,10 +1,16 @@
 use crate::{
-    process::fd,
-    syscall::Fd,
+    process::{fd, percpu},
+    syscall::{
+        DebugError, DebugEvent, ExitStatus, Fd, ForkError, GetRegsError, KillError, NewFdError,
+        SchedStats, Signal, SpawnThreadError, MAX_FDS,
+    },
     util::Global,
-    x86::{self, env::Env, interrupt::InterruptFrame},
+    x86::{
+        self, debug_regs::DebugRegisters, env::Env, fpu::FpuState, interrupt::InterruptFrame,
+        mmu::mmap::MappingFlags,
+    },
 };
-use alloc::rc::Rc;
+use alloc::{boxed::Box, rc::Rc, string::String, vec::Vec};
 use core::{
     arch::asm,
     cell::RefCell,
@@ -18,10 +24,32 @@ pub type Pid = u32;
 /// A simple round-robin scheduler.
 pub struct Scheduler {
     processes: HashMap<Pid, Process>,
+    /// The number of processes using each address space, keyed by page directory address.
+    /// Threads share their parent's address space, which is destroyed once all of them exit.
+    address_spaces: HashMap<usize, usize>,
+    /// The processes waiting on each futex, keyed by physical address, in the order they waited.
+    futex_waiters: HashMap<usize, Vec<Pid>>,
     first: Option<Pid>,
     next: Option<Pid>,
-    current_process: Pid,
     next_pid: Pid,
+    /// The process group that owns the console.
+    foreground_pgid: Pid,
+    /// The parent of each terminated process and how it exited, until it is reaped by 'wait' or
+    /// 'try_wait'. Entries are only kept while the parent is running, and never for threads.
+    exit_statuses: HashMap<Pid, (Pid, ExitStatus)>,
+    /// The parent and faulting register state of each process killed by a fault, until it is
+    /// reaped. Like `exit_statuses`, entries are only kept while the parent is running.
+    fault_frames: HashMap<Pid, (Pid, InterruptFrame)>,
+    /// Counters for performance analysis. The per-process fields are unused; see
+    /// `Process::switches` and `Process::cpu_time`.
+    stats: SchedStats,
+    /// The timestamp counter value when the current process was last scheduled.
+    scheduled_at: u64,
+    /// Whether the debug registers hold a process's breakpoints, which must be cleared when
+    /// switching to a process without any.
+    debug_regs_loaded: bool,
+    /// The process whose floating-point state is in the FPU registers, or 0 if none.
+    fpu_owner: Pid,
 }
 
 /// The reason a process is blocked.
@@ -31,10 +59,21 @@ pub enum BlockReason {
 
     /// The process is waiting for another process to exit
     Process(Pid),
+
+    /// The process is waiting on the futex at the given physical address
+    Futex(usize),
+
+    /// The process was stopped by a single step or breakpoint, and waits for its parent to resume
+    /// it
+    Stopped(DebugEvent),
+
+    /// The process is waiting for a child being debugged to stop or exit
+    Debuggee(Pid),
 }
 
-/// The global scheduler.
-pub static SCHEDULER: Global<Option<Scheduler>> = Global::new(None);
+/// The global scheduler. The timer interrupt uses it to preempt userspace, so it must only be held
+/// with interrupts disabled.
+pub static SCHEDULER: Global<Option<Scheduler>> = Global::new(None).interrupt_sensitive();
 
 struct Process {
     env: Env,
@@ -42,7 +81,31 @@ struct Process {
     prev: Option<Pid>,
     fdtable: HashMap<Fd, Rc<RefCell<dyn fd::File>>>,
     block: Option<Block>,
-    next_fd: Fd,
+    /// For threads, the address and page count of the stack allocated by `spawn_thread`.
+    stack: Option<(usize, usize)>,
+    /// The userspace signal entry point and handler function, if a handler is registered.
+    signal_handler: Option<(usize, usize)>,
+    /// Signals waiting to be delivered to the signal handler.
+    pending_signals: Vec<Signal>,
+    /// The trap frame to restore when the running signal handler returns.
+    signal_frame: Option<InterruptFrame>,
+    /// The timer tick at which to send the process `Signal::Alarm`.
+    alarm: Option<u64>,
+    /// The process group, used to signal a whole pipeline at once.
+    pgid: Pid,
+    /// The process that forked or spawned this one, or 0 for processes started by the kernel.
+    parent: Pid,
+    /// Whether the parent has resumed this process with 'debug_resume' or set a breakpoint in it,
+    /// so debug exceptions stop it rather than killing it.
+    traced: bool,
+    /// The current working directory, as a normalized absolute path.
+    cwd: String,
+    /// The address and length of the environment array placed on the stack by 'exec'.
+    environ: (usize, usize),
+    /// The number of times the scheduler has switched to this process.
+    switches: u64,
+    /// The timestamp counter cycles this process has spent running, excluding its current run.
+    cpu_time: u64,
 }
 
 struct Block {
@@ -50,8 +113,44 @@ struct Block {
     continuation: fn(&mut InterruptFrame),
 }
 
-/// Set if the preemption timer fires during kernelspace.
-static TIMER_FIRED: AtomicBool = AtomicBool::new(false);
+impl Process {
+    /// Returns whether the process is stopped by a single step or breakpoint.
+    fn is_stopped(&self) -> bool {
+        matches!(
+            self.block,
+            Some(Block {
+                reason: BlockReason::Stopped(_),
+                ..
+            })
+        )
+    }
+}
+
+/// Set if an interrupt handler may have made a blocked process runnable.
+static WAKEUP: AtomicBool = AtomicBool::new(false);
+
+/// Whether to switch the FPU registers on every context switch, rather than when a process first
+/// uses them after one. See the `fpu` module.
+const EAGER_FPU: bool = cfg!(feature = "eager-fpu");
+
+/// Whether the timer preempts userspace. See `set_preemption`.
+static PREEMPTION: AtomicBool = AtomicBool::new(true);
+
+/// Enables or disables timer preemption, for deterministic scheduling in tests.
+///
+/// While preemption is disabled, the timer keeps counting ticks but never schedules: processes only
+/// switch at explicit yield points ('yield_cpu', blocking syscalls, and exit), so a run with the
+/// same inputs always interleaves the same way. A process that never yields monopolizes the CPU.
+/// Alarms are checked only when the scheduler runs, so they may be delivered late.
+pub fn set_preemption(enabled: bool) {
+    PREEMPTION.store(enabled, Ordering::Relaxed);
+}
+
+/// Notifies the scheduler that a blocked process may now be able to run (for instance, because
+/// console input arrived). This function is meant to be called from an interrupt context.
+pub fn wake() {
+    WAKEUP.store(true, Ordering::Release);
+}
 
 impl Scheduler {
     /// The timeslice interval.
@@ -59,6 +158,9 @@ impl Scheduler {
 
     /// Creates a new process using an initial environment.
     pub fn new(init_process: Env) -> Scheduler {
+        percpu::current().set_current_pid(1);
+        let mut address_spaces = HashMap::new();
+        address_spaces.insert(init_process.cr3, 1);
         let mut processes = HashMap::new();
         processes.insert(
             1,
@@ -68,35 +170,58 @@ impl Scheduler {
                 prev: None,
                 fdtable: HashMap::new(),
                 block: None,
-                next_fd: 0,
+                stack: None,
+                signal_handler: None,
+                pending_signals: Vec::new(),
+                signal_frame: None,
+                alarm: None,
+                pgid: 1,
+                parent: 0,
+                traced: false,
+                cwd: String::from("/"),
+                environ: (0, 0),
+                switches: 0,
+                cpu_time: 0,
             },
         );
 
         Scheduler {
             processes,
+            address_spaces,
+            futex_waiters: HashMap::new(),
             first: Some(1),
             next: Some(1),
-            current_process: 1,
             next_pid: 2,
+            foreground_pgid: 1,
+            exit_statuses: HashMap::new(),
+            fault_frames: HashMap::new(),
+            stats: SchedStats::default(),
+            scheduled_at: 0,
+            debug_regs_loaded: false,
+            fpu_owner: 0,
         }
     }
 
     /// Starts the scheduler.
     pub fn run(mut self) -> ! {
-        let mut global_scheduler_ref = SCHEDULER.take().expect("a scheduler is already running");
+        // Interrupts are re-enabled when we enter userspace.
+        x86::interrupt::cli();
+
+        let mut global_scheduler_ref = SCHEDULER
+            .try_take()
+            .expect("a scheduler is already running");
         assert!(
             global_scheduler_ref.is_none(),
             "a scheduler is already running"
         );
 
         // Create TSS
-        let mut tss = x86::env::TaskStateSegment::default();
-        *x86::mmu::MMU.take().unwrap().gdt.last_mut().unwrap() =
-            x86::mmu::segment::SegmentDescriptor::new()
-                .with_segment_type(0b1001)
-                .with_base(core::ptr::addr_of!(tss) as usize)
-                .with_limit(core::mem::size_of_val(&tss) - 1)
-                .with_present(true);
+        let tss = percpu::current()
+            .tss
+            .take_and_leak()
+            .expect("a scheduler is already running on this CPU");
+        x86::mmu::MMU.lock().gdt[x86::mmu::SegmentId::TaskState as usize >> 3] =
+            x86::mmu::segment::SegmentDescriptor::task_state(tss);
         unsafe {
             asm!("ltr {:x}", in(reg) x86::mmu::SegmentId::TaskState as u16, options(nomem, nostack))
         }
@@ -112,14 +237,7 @@ impl Scheduler {
         tss.ss0 = x86::mmu::SegmentId::KernelData as u16;
 
         // Set up the preemption timer for 100 Hz
-        x86::interrupt::pit::PIT
-            .take()
-            .unwrap()
-            .set_divisor((x86::interrupt::pit::Pit::RATE / Self::PREEMPT_RATE) as u16);
-        x86::interrupt::pic::PIC
-            .take()
-            .unwrap()
-            .unmask(x86::interrupt::pit::Pit::IRQ);
+        x86::interrupt::timer::start(Self::PREEMPT_RATE);
         unsafe {
             call_user(core::ptr::addr_of_mut!(tss.esp0), trap_frame);
         }
@@ -155,36 +273,215 @@ impl Scheduler {
     pub fn schedule(&mut self, trap_frame: &mut InterruptFrame) -> fn(&mut InterruptFrame) {
         // Run high-priority kernel tasks first
         self.run_kernel_tasks();
+        if let Some(continuation) = self.check_alarms(trap_frame) {
+            return continuation;
+        }
         self.save_current_process(trap_frame);
         self.load_next_process(trap_frame)
     }
 
     /// Forks the current process, returning the child's PID.
-    /// The MMU environment and all file descriptors are copied.
-    pub fn fork(&mut self, trap_frame: &InterruptFrame) -> Pid {
+    /// The MMU environment, all file descriptors, and the working directory are copied.
+    ///
+    /// The child starts from `trap_frame`, just after the 'fork' syscall, and is never blocked: a
+    /// process can only fork while it is running, and a process that blocked in an earlier
+    /// syscall had its block cleared before that syscall was re-run and returned. Pending
+    /// signals and alarms are not inherited.
+    pub fn fork(&mut self, trap_frame: &InterruptFrame) -> Result<Pid, ForkError> {
         let new_cr3 = {
-            let mut mmu = x86::mmu::MMU.take().unwrap();
+            let mut mmu = x86::mmu::MMU.lock();
             let mmu = mmu.deref_mut();
-            mmu.mapper.fork(&mut mmu.allocator)
+            mmu.mapper
+                .fork(&mut mmu.allocator)
+                .map_err(|_| ForkError::OutOfMemory)?
         };
 
-        // Use the new MMU env for the old process, because that requires one less MMU switch.
-        let current_process = self.processes.get_mut(&self.current_pid()).unwrap();
-        let new_cr3 = core::mem::replace(&mut current_process.env.cr3, new_cr3);
+        let pid = self.current_pid();
+        let old_cr3 = self.processes[&pid].env.cr3;
+        let new_cr3 = if self.address_space_users(old_cr3) > 1 {
+            // Other threads are still using the old MMU env, so the child gets the new one.
+            unsafe { x86::mmu::MMU.lock().mapper.set_cr3(old_cr3) };
+            new_cr3
+        } else {
+            // Use the new MMU env for the old process, because that requires one less MMU switch.
+            self.processes.get_mut(&pid).unwrap().env.cr3 = new_cr3;
+            self.release_address_space(old_cr3);
+            self.share_address_space(new_cr3);
+            old_cr3
+        };
+        let fpu = self.fpu_state(pid);
+        let current_process = &self.processes[&pid];
         let new_fdtable = current_process.fdtable.clone();
+        let signal_handler = current_process.signal_handler;
+        let pgid = current_process.pgid;
+        let cwd = current_process.cwd.clone();
+        let environ = current_process.environ;
+        let (brk_start, brk) = (current_process.env.brk_start, current_process.env.brk);
+        // Only a running process can fork, and running processes are never blocked.
         assert!(
             current_process.block.is_none(),
             "cannot fork a blocked process"
         );
 
+        let parent = self.current_pid();
         let new_pid = self.add_process(Env {
             trap_frame: trap_frame.clone(),
             cr3: new_cr3,
+            brk_start,
+            brk,
+            debug_regs: DebugRegisters::default(),
+            fpu,
         });
-        // Copy file descriptors
-        self.processes.get_mut(&new_pid).unwrap().fdtable = new_fdtable;
+        // Copy file descriptors and the signal handler
+        let child = self.processes.get_mut(&new_pid).unwrap();
+        child.fdtable = new_fdtable;
+        child.signal_handler = signal_handler;
+        child.pgid = pgid;
+        child.parent = parent;
+        child.cwd = cwd;
+        child.environ = environ;
 
-        new_pid
+        Ok(new_pid)
+    }
+
+    /// Creates a thread: a new process sharing the current process's MMU env, running on a fresh
+    /// stack of `stack_size` bytes. The thread starts at `entry`, called with `arg` as its only
+    /// argument. File descriptors are copied.
+    pub fn spawn_thread(
+        &mut self,
+        trap_frame: &InterruptFrame,
+        entry: usize,
+        arg: usize,
+        stack_size: usize,
+    ) -> Result<Pid, SpawnThreadError> {
+        let pages = x86::mmu::page_align_up(stack_size)
+            .filter(|&size| size > 0)
+            .ok_or(SpawnThreadError::InvalidStackSize)?
+            / x86::mmu::PAGE_SIZE;
+        let stack = {
+            let mut mmu = x86::mmu::MMU.lock();
+            let mmu = mmu.deref_mut();
+            let stack = mmu
+                .mapper
+                .find_unused_userspace_high(pages)
+                .ok_or(SpawnThreadError::OutOfMemory)?;
+            mmu.mapper.map_zeroed(
+                &mut mmu.allocator,
+                stack,
+                pages,
+                MappingFlags::new()
+                    .with_writable(true)
+                    .with_user_accessible(true),
+            );
+            stack
+        };
+
+        // Push the argument and a null return address, keeping the stack 16-byte aligned as of
+        // the call.
+        let esp = stack + pages * x86::mmu::PAGE_SIZE - 20;
+        unsafe { *((esp + 4) as *mut usize) = arg };
+
+        let parent = self.current_pid();
+        let current_process = &self.processes[&parent];
+        let env = Env {
+            trap_frame: InterruptFrame {
+                eip: entry,
+                user_esp: esp,
+                ..trap_frame.clone()
+            },
+            cr3: current_process.env.cr3,
+            brk_start: current_process.env.brk_start,
+            brk: current_process.env.brk,
+            debug_regs: DebugRegisters::default(),
+            fpu: FpuState::new(),
+        };
+        let new_fdtable = current_process.fdtable.clone();
+        let signal_handler = current_process.signal_handler;
+        let pgid = current_process.pgid;
+        let cwd = current_process.cwd.clone();
+        let environ = current_process.environ;
+
+        let new_pid = self.add_process(env);
+        let thread = self.processes.get_mut(&new_pid).unwrap();
+        thread.fdtable = new_fdtable;
+        thread.stack = Some((stack, pages));
+        thread.signal_handler = signal_handler;
+        thread.pgid = pgid;
+        thread.parent = parent;
+        thread.cwd = cwd;
+        thread.environ = environ;
+
+        Ok(new_pid)
+    }
+
+    /// Sets the program break of every process sharing the MMU env `cr3`.
+    fn set_brk(&mut self, cr3: usize, brk_start: usize, brk: usize) {
+        for process in self.processes.values_mut() {
+            if process.env.cr3 == cr3 {
+                process.env.brk_start = brk_start;
+                process.env.brk = brk;
+            }
+        }
+    }
+
+    /// Resets the current process's program break, e.g. after loading a new executable.
+    pub fn reset_brk(&mut self, brk: usize) {
+        let cr3 = self.processes[&self.current_pid()].env.cr3;
+        self.set_brk(cr3, brk, brk);
+    }
+
+    /// Moves the current process's program break by `increment` bytes, mapping or unmapping heap
+    /// pages as needed. Returns the previous break, or None if the heap would shrink below its
+    /// initial break or grow into memory that is already mapped.
+    pub fn sbrk(&mut self, increment: isize) -> Option<usize> {
+        let env = &mut self.processes.get_mut(&self.current_pid()).unwrap().env;
+        let old_brk = env.brk;
+        let new_brk = if increment >= 0 {
+            old_brk.checked_add(increment as usize)?
+        } else {
+            old_brk.checked_sub(increment.unsigned_abs())?
+        };
+        if new_brk < env.brk_start || new_brk > x86::mmu::KERNEL_RELOC_BASE {
+            return None;
+        }
+
+        // The heap occupies every page from brk_start up to the page containing the break.
+        let old_end = x86::mmu::page_align_up(old_brk)?;
+        let new_end = x86::mmu::page_align_up(new_brk)?;
+
+        let mut mmu = x86::mmu::MMU.lock();
+        let mmu = mmu.deref_mut();
+        if new_end > old_end {
+            // Make sure we won't run into the stack or another mapping.
+            if (old_end..new_end)
+                .step_by(x86::mmu::PAGE_SIZE)
+                .any(|vaddr| mmu.mapper.get_mapping(vaddr).is_some())
+            {
+                return None;
+            }
+            mmu.mapper.map_zeroed(
+                &mut mmu.allocator,
+                old_end,
+                (new_end - old_end) / x86::mmu::PAGE_SIZE,
+                MappingFlags::new()
+                    .with_writable(true)
+                    .with_user_accessible(true),
+            );
+        } else {
+            for vaddr in (new_end..old_end).step_by(x86::mmu::PAGE_SIZE) {
+                if let Some(mapping) = mmu.mapper.get_mapping(vaddr) {
+                    unsafe {
+                        mmu.allocator
+                            .free(mapping.physaddr() as usize, &mut mmu.mapper)
+                    };
+                }
+                mmu.mapper.unmap(&mut mmu.allocator, vaddr);
+            }
+        }
+
+        let (cr3, brk_start) = (env.cr3, env.brk_start);
+        self.set_brk(cr3, brk_start, new_brk);
+        Some(old_brk)
     }
 
     fn run_kernel_tasks(&mut self) {
@@ -192,23 +489,34 @@ impl Scheduler {
     }
 
     fn save_current_process(&mut self, trap_frame: &InterruptFrame) {
-        let process = self.processes.get_mut(&self.current_process).unwrap();
+        let process = self.processes.get_mut(&self.current_pid()).unwrap();
         process.env.trap_frame.clone_from(trap_frame);
+        // Charge the process for the time it actually ran, which may be much less than a
+        // timeslice if it blocked, yielded, or was rescheduled early.
+        process.cpu_time += x86::tsc::rdtsc() - self.scheduled_at;
     }
 
     #[must_use]
     fn load_next_process(&mut self, trap_frame: &mut InterruptFrame) -> fn(&mut InterruptFrame) {
+        let previous_pid = self.current_pid();
+        // The number of blocked processes we've seen in a row.
+        let mut blocked_count = 0;
         loop {
             if let Some(pid) = self.next {
                 let process = self.processes.get(&pid).unwrap();
                 let mut continuation: fn(&mut InterruptFrame) = |_| {};
+                let mut interrupted = false;
 
                 // Is this process blocked?
                 let blocked = if let Some(block) = process.block.as_ref() {
-                    if self.can_unblock(process, &block.reason) {
+                    if self.can_unblock(pid, process, &block.reason) {
                         // The process is no longer blocked.
                         continuation = block.continuation;
                         false
+                    } else if Self::can_interrupt(process) {
+                        // The process has a signal to handle, which interrupts the syscall.
+                        interrupted = true;
+                        false
                     } else {
                         true
                     }
@@ -226,20 +534,53 @@ impl Scheduler {
                     self.run_kernel_tasks();
                 }
 
-                if !blocked {
+                if blocked {
+                    blocked_count += 1;
+                    if blocked_count >= self.processes.len() {
+                        // Every process is blocked; halt until an interrupt might unblock one,
+                        // rather than spinning.
+                        Self::idle();
+                        blocked_count = 0;
+                    }
+                } else {
                     // This process is not blcoed; schedule it now.
                     let process = self.processes.get_mut(&pid).unwrap();
-                    process.block = None;
+                    let block = process.block.take();
+                    let resuming = block.is_some() && !interrupted;
+                    if pid != previous_pid {
+                        process.switches += 1;
+                        self.stats.context_switches += 1;
+                    }
+                    self.scheduled_at = x86::tsc::rdtsc();
 
-                    self.current_process = pid;
+                    percpu::current().set_current_pid(pid);
                     unsafe {
-                        x86::mmu::MMU
-                            .take()
-                            .unwrap()
-                            .mapper
-                            .set_cr3(process.env.cr3);
+                        x86::mmu::MMU.lock().mapper.set_cr3(process.env.cr3);
+                    }
+                    let debug_regs = &process.env.debug_regs;
+                    if debug_regs.any_enabled() || self.debug_regs_loaded {
+                        // Breakpoints only watch userspace, which the kernel is prepared for.
+                        unsafe { debug_regs.load() };
+                        self.debug_regs_loaded = debug_regs.any_enabled();
                     }
                     trap_frame.clone_from(&process.env.trap_frame);
+                    if pid != self.fpu_owner {
+                        if EAGER_FPU && x86::fpu::supported() {
+                            self.switch_fpu();
+                        } else {
+                            // Switch the FPU registers when the process first uses them.
+                            x86::fpu::set_task_switched(true);
+                        }
+                    } else {
+                        x86::fpu::set_task_switched(false);
+                    }
+                    if interrupted {
+                        self.interrupt_syscall(pid, block.unwrap().reason, trap_frame);
+                    }
+                    // Signals wait for a blocked syscall to resume, unless they interrupted it.
+                    if !resuming {
+                        self.deliver_signal(pid, trap_frame);
+                    }
 
                     // We've found a process; we're done.
                     break continuation;
@@ -250,22 +591,89 @@ impl Scheduler {
         }
     }
 
-    fn can_unblock(&self, process: &Process, reason: &BlockReason) -> bool {
+    /// Halts the CPU until an interrupt arrives, unless a wakeup is already pending.
+    ///
+    /// Syscalls run with interrupts disabled, so this is the only point at which the kernel
+    /// handles interrupts. Any timer tick taken here only sets `timer_fired`, since the frame it
+    /// interrupts is in kernelspace.
+    fn idle() {
+        x86::interrupt::with_interrupts_disabled(|| {
+            // Check for a wakeup with interrupts disabled, so that one arriving after the check
+            // cannot be lost. 'sti' takes effect only after the following instruction, so no
+            // interrupt can be handled between it and the 'hlt'.
+            if !WAKEUP.swap(false, Ordering::Acquire) {
+                unsafe { asm!("sti; hlt; cli", options(nostack)) }
+            }
+        })
+    }
+
+    /// Returns whether any process other than `current_pid` is ready to run.
+    pub fn has_other_runnable(&self, current_pid: Pid) -> bool {
+        self.processes.iter().any(|(&pid, process)| {
+            pid != current_pid
+                && process
+                    .block
+                    .as_ref()
+                    .is_none_or(|block| self.can_unblock(pid, process, &block.reason))
+        })
+    }
+
+    fn can_unblock(&self, pid: Pid, process: &Process, reason: &BlockReason) -> bool {
         match reason {
             BlockReason::File { fd, access_type } => {
                 process.fdtable[fd].borrow_mut().can_access(*access_type)
             }
-            BlockReason::Process(pid) => !self.processes.contains_key(pid),
+            BlockReason::Process(child) => !self.processes.contains_key(child),
+            BlockReason::Futex(key) => {
+                !matches!(self.futex_waiters.get(key), Some(waiters) if waiters.contains(&pid))
+            }
+            // A stopped process whose debugger has exited would otherwise never run again.
+            BlockReason::Stopped(_) => !self.processes.contains_key(&process.parent),
+            BlockReason::Debuggee(child) => self
+                .processes
+                .get(child)
+                .is_none_or(|child| child.is_stopped()),
+        }
+    }
+
+    /// Returns whether a blocked process has a signal to handle, which interrupts its syscall. A
+    /// stopped process is not in a syscall, and waits for its parent to resume it.
+    fn can_interrupt(process: &Process) -> bool {
+        !process.is_stopped()
+            && process.signal_handler.is_some()
+            && process.signal_frame.is_none()
+            && !process.pending_signals.is_empty()
+    }
+
+    /// Abandons the syscall that process `pid` (whose state is loaded in `trap_frame`) is blocked
+    /// in, so that the process issues it again when it next runs.
+    fn interrupt_syscall(
+        &mut self,
+        pid: Pid,
+        reason: BlockReason,
+        trap_frame: &mut InterruptFrame,
+    ) {
+        if let BlockReason::Futex(key) = reason {
+            if let Some(waiters) = self.futex_waiters.get_mut(&key) {
+                waiters.retain(|&waiter| waiter != pid);
+                if waiters.is_empty() {
+                    self.futex_waiters.remove(&key);
+                }
+            }
         }
+
+        // Back up over the 'int 0x40' instruction.
+        trap_frame.eip -= 2;
     }
 
     /// Returns the process ID of the currently executing process.
     pub fn current_pid(&self) -> Pid {
-        self.current_process
+        percpu::current().current_pid()
     }
 
     /// Adds a new process to the scheduler.
     pub fn add_process(&mut self, env: Env) -> Pid {
+        let env_cr3 = env.cr3;
         let new_pid = self.next_pid;
         self.next_pid += 1;
 
@@ -277,9 +685,21 @@ impl Scheduler {
                 prev: None,
                 fdtable: HashMap::new(),
                 block: None,
-                next_fd: 0,
+                stack: None,
+                signal_handler: None,
+                pending_signals: Vec::new(),
+                signal_frame: None,
+                alarm: None,
+                pgid: new_pid,
+                parent: 0,
+                traced: false,
+                cwd: String::from("/"),
+                environ: (0, 0),
+                switches: 0,
+                cpu_time: 0,
             },
         );
+        self.share_address_space(env_cr3);
 
         if let Some(process) = self.first {
             self.processes.get_mut(&process).unwrap().prev = Some(new_pid);
@@ -290,8 +710,17 @@ impl Scheduler {
     }
 
     /// Removes a process from the scheduler.
+    ///
+    /// If `pid` is the current process, no process is current until the caller loads another one
+    /// with `load_next_process`, so that nothing can mistake the removed process for a live one.
     pub fn remove_process(&mut self, pid: Pid) -> Env {
         let process = self.processes.remove(&pid).unwrap();
+        if pid == self.current_pid() {
+            percpu::current().set_current_pid(0);
+        }
+        if pid == self.fpu_owner {
+            self.fpu_owner = 0;
+        }
 
         if self.first == Some(pid) {
             self.first = process.next;
@@ -305,26 +734,538 @@ impl Scheduler {
         if let Some(prev) = process.prev {
             self.processes.get_mut(&prev).unwrap().next = process.next
         }
+        for waiters in self.futex_waiters.values_mut() {
+            waiters.retain(|&waiter| waiter != pid);
+        }
 
+        #[cfg(debug_assertions)]
+        self.check_process_list();
         process.env
     }
 
+    /// Panics if the process list is inconsistent: every process must be reachable from `first`
+    /// exactly once, with matching `prev` links, and `next` must be a live process.
+    #[cfg(debug_assertions)]
+    fn check_process_list(&self) {
+        let mut count = 0;
+        let mut prev = None;
+        let mut cursor = self.first;
+        while let Some(pid) = cursor {
+            let process = &self.processes[&pid];
+            assert_eq!(process.prev, prev, "process {} has a stale prev link", pid);
+            count += 1;
+            assert!(count <= self.processes.len(), "process list has a cycle");
+            prev = cursor;
+            cursor = process.next;
+        }
+        assert_eq!(
+            count,
+            self.processes.len(),
+            "process list is missing processes"
+        );
+        match self.next {
+            Some(next) => assert!(
+                self.processes.contains_key(&next),
+                "next process has been removed"
+            ),
+            None => assert!(self.first.is_none(), "next process is missing"),
+        }
+    }
+
     /// Returns true if the specified PID corresponds to a running process.
     pub fn process_exists(&self, pid: Pid) -> bool {
         self.processes.contains_key(&pid)
     }
 
-    /// Terminates the current process and schedules a new process in its place.
+    /// Removes the exit status of a terminated process, returning None if the process has not
+    /// terminated (or has already been reaped).
+    pub fn reap(&mut self, pid: Pid) -> Option<ExitStatus> {
+        self.fault_frames.remove(&pid);
+        self.exit_statuses.remove(&pid).map(|(_, status)| status)
+    }
+
+    /// Records how `pid`, a child of `parent`, terminated (and its registers, if it faulted), and
+    /// discards the exit statuses and fault frames of its own children, which can no longer be
+    /// reaped. Nothing is recorded for threads, or if the parent has already exited.
+    fn record_exit(
+        &mut self,
+        pid: Pid,
+        parent: Pid,
+        thread: bool,
+        status: ExitStatus,
+        fault_frame: Option<&InterruptFrame>,
+    ) {
+        self.exit_statuses
+            .retain(|_, &mut (child_parent, _)| child_parent != pid);
+        self.fault_frames
+            .retain(|_, &mut (child_parent, _)| child_parent != pid);
+        if !thread && self.processes.contains_key(&parent) {
+            self.exit_statuses.insert(pid, (parent, status));
+            if let Some(frame) = fault_frame {
+                self.fault_frames.insert(pid, (parent, frame.clone()));
+            }
+        }
+    }
+
+    /// Returns the saved registers of `pid`, which must be a child of the current process: its
+    /// state when it was last scheduled out if it is still running, or its state when it faulted
+    /// if it was killed by a fault and has not been reaped.
+    pub fn child_registers(&self, pid: Pid) -> Result<InterruptFrame, GetRegsError> {
+        let (parent, frame) = match self.processes.get(&pid) {
+            Some(process) => (process.parent, &process.env.trap_frame),
+            None => {
+                let (parent, frame) = self
+                    .fault_frames
+                    .get(&pid)
+                    .ok_or(GetRegsError::NoSuchProcess)?;
+                (*parent, frame)
+            }
+        };
+        if parent != self.current_pid() {
+            return Err(GetRegsError::NotAChild);
+        }
+        Ok(frame.clone())
+    }
+
+    /// Terminates the current process, schedules a new process in its place, and frees the old
+    /// process's memory (or, if other threads are still using it, just the thread's stack).
     ///
-    /// Returns the MMU environment of the old process, and a continuation function that must be
-    /// invoked before returning to userspace (see the documentation for `schedule`).
+    /// Returns a continuation function that must be invoked before returning to userspace (see the
+    /// documentation for `schedule`).
     #[must_use]
     pub fn kill_current_process(
         &mut self,
         trap_frame: &mut InterruptFrame,
-    ) -> (Env, fn(&mut InterruptFrame)) {
-        let env = self.remove_process(self.current_pid());
-        (env, self.load_next_process(trap_frame))
+        status: ExitStatus,
+    ) -> fn(&mut InterruptFrame) {
+        let pid = self.current_pid();
+        let stack = self.processes[&pid].stack;
+        let parent = self.processes[&pid].parent;
+        let env = self.remove_process(pid);
+        let fault_frame = (status == ExitStatus::Faulted).then_some(&*trap_frame);
+        self.record_exit(pid, parent, stack.is_some(), status, fault_frame);
+        let continuation = self.load_next_process(trap_frame);
+        self.release_env(&env, stack);
+        continuation
+    }
+
+    /// Terminates a process other than the current one, and frees its memory.
+    fn terminate(&mut self, pid: Pid, status: ExitStatus) {
+        assert_ne!(
+            pid,
+            self.current_pid(),
+            "cannot terminate the current process"
+        );
+        let stack = self.processes[&pid].stack;
+        let parent = self.processes[&pid].parent;
+        let env = self.remove_process(pid);
+        self.record_exit(pid, parent, stack.is_some(), status, None);
+        self.release_env(&env, stack);
+    }
+
+    /// Returns the number of processes using the MMU env `cr3`.
+    fn address_space_users(&self, cr3: usize) -> usize {
+        self.address_spaces.get(&cr3).copied().unwrap_or(0)
+    }
+
+    /// Records that another process is using the MMU env `cr3`.
+    fn share_address_space(&mut self, cr3: usize) {
+        *self.address_spaces.entry(cr3).or_insert(0) += 1;
+    }
+
+    /// Records that a process has stopped using the MMU env `cr3`, returning the number of
+    /// processes still using it. Once this reaches 0, the caller is responsible for destroying the
+    /// env; until then, it must not be destroyed.
+    fn release_address_space(&mut self, cr3: usize) -> usize {
+        let users = self
+            .address_spaces
+            .get_mut(&cr3)
+            .expect("address space is not in use");
+        *users -= 1;
+        let users = *users;
+        if users == 0 {
+            self.address_spaces.remove(&cr3);
+        }
+        users
+    }
+
+    /// Frees the memory of a removed process: its whole MMU env if no other threads are using it,
+    /// or otherwise just its thread stack. `env` must not be the only user of the active MMU env.
+    fn release_env(&mut self, env: &Env, stack: Option<(usize, usize)>) {
+        let threads = self.release_address_space(env.cr3);
+
+        // Both cases need the process's MMU env to be active.
+        let mut mmu = x86::mmu::MMU.lock();
+        let mmu = mmu.deref_mut();
+        let active_cr3 = mmu.mapper.cr3();
+        unsafe { mmu.mapper.set_cr3(env.cr3) };
+
+        if threads > 0 {
+            if let Some((stack, pages)) = stack {
+                for vaddr in (stack..).step_by(x86::mmu::PAGE_SIZE).take(pages) {
+                    if let Some(mapping) = mmu.mapper.get_mapping(vaddr) {
+                        unsafe {
+                            mmu.allocator
+                                .free(mapping.physaddr() as usize, &mut mmu.mapper)
+                        };
+                    }
+                    mmu.mapper.unmap(&mut mmu.allocator, vaddr);
+                }
+            }
+            unsafe { mmu.mapper.set_cr3(active_cr3) };
+        } else {
+            // The framebuffer mapping is torn down with the environment.
+            x86::io::vga::VGA.lock().leave_graphics(env.cr3);
+
+            // destroy_env tears down the active environment, then switches back.
+            unsafe { mmu.mapper.destroy_env(&mut mmu.allocator, active_cr3) };
+        }
+    }
+
+    /// Resumes `pid`, a child of the current process, if it is stopped. If `step` is true, sets the
+    /// trap flag so that it stops again after one instruction; otherwise, clears it.
+    pub fn debug_resume(&mut self, pid: Pid, step: bool) -> Result<(), DebugError> {
+        let current_pid = self.current_pid();
+        let process = self
+            .processes
+            .get_mut(&pid)
+            .ok_or(DebugError::NoSuchProcess)?;
+        if process.parent != current_pid {
+            return Err(DebugError::NotAChild);
+        }
+        process.traced = true;
+        if step {
+            process.env.trap_frame.eflags |= InterruptFrame::TRAP_FLAG;
+        } else {
+            process.env.trap_frame.eflags &= !InterruptFrame::TRAP_FLAG;
+        }
+        if process.is_stopped() {
+            process.block = None;
+        }
+        Ok(())
+    }
+
+    /// Returns the debug event that `pid`, a child of the current process, is waiting to report,
+    /// or None if it is still running.
+    pub fn debug_event(&self, pid: Pid) -> Result<Option<DebugEvent>, DebugError> {
+        match self.processes.get(&pid) {
+            Some(process) if process.parent != self.current_pid() => Err(DebugError::NotAChild),
+            Some(Process {
+                block:
+                    Some(Block {
+                        reason: BlockReason::Stopped(event),
+                        ..
+                    }),
+                ..
+            }) => Ok(Some(*event)),
+            Some(_) => Ok(None),
+            None if self.exit_statuses.contains_key(&pid) => Ok(Some(DebugEvent::Exited)),
+            None => Err(DebugError::NoSuchProcess),
+        }
+    }
+
+    /// Handles a debug exception raised in userspace by the trap flag or a hardware breakpoint. A
+    /// process being debugged is stopped until its parent resumes it; any other process is killed,
+    /// since it set the trap flag itself.
+    ///
+    /// Returns a continuation function that must be invoked before returning to userspace (see the
+    /// documentation for `schedule`).
+    #[must_use]
+    pub fn handle_debug_exception(
+        &mut self,
+        trap_frame: &mut InterruptFrame,
+    ) -> fn(&mut InterruptFrame) {
+        let pid = self.current_pid();
+        let dr6 = x86::debug_regs::read_dr6();
+        x86::debug_regs::clear_dr6();
+        trap_frame.eflags &= !InterruptFrame::TRAP_FLAG;
+        let event = match dr6 & x86::debug_regs::DR6_BREAKPOINTS {
+            0 => DebugEvent::Stopped,
+            hits => {
+                // Don't hit an execute breakpoint again on the same instruction when resuming.
+                trap_frame.eflags |= InterruptFrame::RESUME_FLAG;
+                DebugEvent::Breakpoint(hits.trailing_zeros())
+            }
+        };
+        if self.processes[&pid].traced {
+            self.block(pid, BlockReason::Stopped(event), |_| {});
+            self.schedule(trap_frame)
+        } else {
+            crate::kwarn!("terminating process {} due to a stray debug exception", pid);
+            self.kill_current_process(trap_frame, ExitStatus::Faulted)
+        }
+    }
+
+    /// Returns the breakpoints of `pid`, a child of the current process, for its parent to modify.
+    /// Debug exceptions in the child stop it from then on.
+    pub fn debug_registers(&mut self, pid: Pid) -> Result<&mut DebugRegisters, DebugError> {
+        let current_pid = self.current_pid();
+        let process = self
+            .processes
+            .get_mut(&pid)
+            .ok_or(DebugError::NoSuchProcess)?;
+        if process.parent != current_pid {
+            return Err(DebugError::NotAChild);
+        }
+        process.traced = true;
+        Ok(&mut process.env.debug_regs)
+    }
+
+    /// Gives the FPU to the current process, saving the registers of its previous owner and loading
+    /// the current process's. Called on the first floating-point instruction after a context
+    /// switch, which faults because 'load_next_process' set CR0.TS, or during every context switch
+    /// with the `eager-fpu` feature.
+    pub fn switch_fpu(&mut self) {
+        let pid = self.current_pid();
+        x86::fpu::set_task_switched(false);
+        if let Some(owner) = self.processes.get_mut(&self.fpu_owner) {
+            unsafe { owner.env.fpu.save() };
+        }
+        unsafe { self.processes[&pid].env.fpu.restore() };
+        self.fpu_owner = pid;
+    }
+
+    /// Returns a copy of the floating-point registers of `pid`, including any changes not yet
+    /// saved from the FPU.
+    fn fpu_state(&mut self, pid: Pid) -> Box<FpuState> {
+        let current_pid = self.current_pid();
+        let process = self.processes.get_mut(&pid).unwrap();
+        if pid == self.fpu_owner {
+            x86::fpu::set_task_switched(false);
+            unsafe { process.env.fpu.save() };
+            x86::fpu::set_task_switched(pid != current_pid);
+        }
+        process.env.fpu.clone()
+    }
+
+    /// Resets the current process's floating-point registers, for 'exec'.
+    pub fn reset_fpu(&mut self) {
+        let pid = self.current_pid();
+        self.processes.get_mut(&pid).unwrap().env.fpu = FpuState::new();
+        if pid == self.fpu_owner {
+            self.fpu_owner = 0;
+            x86::fpu::set_task_switched(true);
+        }
+    }
+
+    /// Registers the current process's signal handler, as a (userspace entry point, handler
+    /// function) pair passed to the entry point. If None, signals terminate the process.
+    pub fn set_signal_handler(&mut self, handler: Option<(usize, usize)>) {
+        let pid = self.current_pid();
+        self.processes.get_mut(&pid).unwrap().signal_handler = handler;
+    }
+
+    /// Moves process `pid` (or the current process, if 0) into process group `pgid` (or a new
+    /// group named after the process, if 0).
+    pub fn set_pgid(&mut self, pid: Pid, pgid: Pid) -> Result<(), KillError> {
+        let pid = if pid == 0 { self.current_pid() } else { pid };
+        let process = self
+            .processes
+            .get_mut(&pid)
+            .ok_or(KillError::NoSuchProcess)?;
+        process.pgid = if pgid == 0 { pid } else { pgid };
+        Ok(())
+    }
+
+    /// Returns the process group of the current process.
+    pub fn pgid(&self) -> Pid {
+        self.processes[&self.current_pid()].pgid
+    }
+
+    /// Returns the current process's working directory.
+    pub fn cwd(&self) -> &str {
+        &self.processes[&self.current_pid()].cwd
+    }
+
+    /// Returns the address and length of the current process's environment array.
+    pub fn environ(&self) -> (usize, usize) {
+        self.processes[&self.current_pid()].environ
+    }
+
+    /// Records the location of the environment array placed on the current process's stack.
+    pub fn set_environ(&mut self, environ: (usize, usize)) {
+        let pid = self.current_pid();
+        self.processes.get_mut(&pid).unwrap().environ = environ;
+    }
+
+    /// Changes the current process's working directory, which must be a normalized absolute path.
+    pub fn set_cwd(&mut self, cwd: String) {
+        let pid = self.current_pid();
+        self.processes.get_mut(&pid).unwrap().cwd = cwd;
+    }
+
+    /// Returns the process group that owns the console.
+    pub fn foreground_pgid(&self) -> Pid {
+        self.foreground_pgid
+    }
+
+    /// Gives the console to process group `pgid`.
+    pub fn set_foreground_pgid(&mut self, pgid: Pid) {
+        self.foreground_pgid = pgid;
+    }
+
+    /// Sends a signal to every process in a process group, as if by `kill`.
+    pub fn kill_group(
+        &mut self,
+        pgid: Pid,
+        signal: Signal,
+        trap_frame: &mut InterruptFrame,
+    ) -> Result<fn(&mut InterruptFrame), KillError> {
+        let current_pid = self.current_pid();
+        let mut members: Vec<Pid> = self
+            .processes
+            .iter()
+            .filter(|(_, process)| process.pgid == pgid)
+            .map(|(&pid, _)| pid)
+            .collect();
+        if members.is_empty() {
+            return Err(KillError::NoSuchProcess);
+        }
+
+        // Signal the current process last, since killing it schedules a new process.
+        members.sort_by_key(|&pid| pid == current_pid);
+        let mut continuation: fn(&mut InterruptFrame) = |_| {};
+        for pid in members {
+            continuation = self.kill(pid, signal, trap_frame)?;
+        }
+        Ok(continuation)
+    }
+
+    /// Sends a signal to a process. If the process has a signal handler (and the signal can be
+    /// handled), the signal is queued and delivered the next time the process is scheduled. A
+    /// process blocked in a syscall is woken to run the handler, and issues the syscall again once
+    /// the handler returns. Otherwise, the process is terminated immediately.
+    ///
+    /// Returns a continuation function that must be invoked before returning to userspace (see the
+    /// documentation for `schedule`), since the current process may have been killed.
+    pub fn kill(
+        &mut self,
+        pid: Pid,
+        signal: Signal,
+        trap_frame: &mut InterruptFrame,
+    ) -> Result<fn(&mut InterruptFrame), KillError> {
+        let current_pid = self.current_pid();
+        let process = self
+            .processes
+            .get_mut(&pid)
+            .ok_or(KillError::NoSuchProcess)?;
+
+        if process.signal_handler.is_some() && signal != Signal::Kill {
+            if !process.pending_signals.contains(&signal) {
+                process.pending_signals.push(signal);
+            }
+            if pid == current_pid && process.block.is_none() {
+                self.deliver_signal(pid, trap_frame);
+            }
+            Ok(|_| {})
+        } else {
+            crate::kinfo!("Process {} killed by {:?}.", pid, signal);
+            if pid == current_pid {
+                Ok(self.kill_current_process(trap_frame, ExitStatus::Signaled(signal)))
+            } else {
+                self.terminate(pid, ExitStatus::Signaled(signal));
+                Ok(|_| {})
+            }
+        }
+    }
+
+    /// If the process `pid` (whose state is loaded in `trap_frame` and whose MMU env is active)
+    /// has a pending signal and is not already running its signal handler, redirects it to the
+    /// handler.
+    fn deliver_signal(&mut self, pid: Pid, trap_frame: &mut InterruptFrame) {
+        let process = self.processes.get_mut(&pid).unwrap();
+        let (entry, handler) = match process.signal_handler {
+            Some(handler) if process.signal_frame.is_none() => handler,
+            _ => return,
+        };
+        if process.pending_signals.is_empty() {
+            return;
+        }
+        let signal = process.pending_signals.remove(0);
+
+        // Call entry(handler, signal) with a null return address, keeping the stack 16-byte
+        // aligned as of the call.
+        let esp = ((trap_frame.user_esp - 8) & !0xf) - 4;
+        let valid = {
+            let mmu = x86::mmu::MMU.lock();
+            mmu.mapper.validate_range(
+                &mmu.allocator,
+                esp,
+                12,
+                MappingFlags::new()
+                    .with_writable(true)
+                    .with_user_accessible(true),
+            )
+        };
+        if !valid {
+            crate::kwarn!(
+                "Process {} has an invalid stack; dropping {:?}.",
+                pid,
+                signal
+            );
+            return;
+        }
+        unsafe {
+            let stack = esp as *mut usize;
+            stack.write(0);
+            stack.add(1).write(handler);
+            stack.add(2).write(signal as usize);
+        }
+
+        process.signal_frame = Some(trap_frame.clone());
+        trap_frame.eip = entry;
+        trap_frame.user_esp = esp;
+    }
+
+    /// Sets the current process's alarm to go off in `secs` seconds, replacing any previous alarm.
+    /// If `secs` is 0, the alarm is cancelled.
+    pub fn set_alarm(&mut self, secs: u32) {
+        let pid = self.current_pid();
+        let timer = x86::interrupt::timer::frequency() as u64;
+        self.processes.get_mut(&pid).unwrap().alarm = if secs == 0 {
+            None
+        } else {
+            Some(x86::interrupt::timer::ticks() + secs as u64 * timer)
+        };
+    }
+
+    /// Sends `Signal::Alarm` to each process whose alarm has expired. Alarms are only checked when
+    /// the scheduler runs, so they may be delayed while the system is idle.
+    ///
+    /// If this kills the current process, returns the continuation for the newly scheduled
+    /// process (see the documentation for `schedule`).
+    fn check_alarms(&mut self, trap_frame: &mut InterruptFrame) -> Option<fn(&mut InterruptFrame)> {
+        let now = x86::interrupt::timer::ticks();
+        let current_pid = self.current_pid();
+        let mut expired: Vec<Pid> = self
+            .processes
+            .iter()
+            .filter(|(_, process)| matches!(process.alarm, Some(deadline) if deadline <= now))
+            .map(|(&pid, _)| pid)
+            .collect();
+
+        // Signal the current process last, since killing it schedules a new process.
+        expired.sort_by_key(|&pid| pid == current_pid);
+        for pid in expired {
+            self.processes.get_mut(&pid).unwrap().alarm = None;
+            let continuation = self
+                .kill(pid, Signal::Alarm, trap_frame)
+                .expect("process disappeared");
+            if pid == current_pid && self.current_pid() != current_pid {
+                return Some(continuation);
+            }
+        }
+        None
+    }
+
+    /// Returns from the current process's signal handler, restoring the state from before the
+    /// signal was delivered.
+    pub fn sigreturn(&mut self, trap_frame: &mut InterruptFrame) {
+        let pid = self.current_pid();
+        if let Some(frame) = self.processes.get_mut(&pid).unwrap().signal_frame.take() {
+            *trap_frame = frame;
+            self.deliver_signal(pid, trap_frame);
+        }
     }
 
     /// Returns a reference to the file object for a given process and file descriptor.
@@ -334,24 +1275,33 @@ impl Scheduler {
             .and_then(|process| process.fdtable.get(&fd))
     }
 
-    /// Sets the file object for a given process and file descriptor.
-    pub fn set_fd(&mut self, pid: Pid, fd: Fd, file: Option<Rc<RefCell<dyn fd::File>>>) {
+    /// Sets the file object for a given process and file descriptor, which must be less than
+    /// `MAX_FDS`. Returns whether the descriptor was previously open.
+    pub fn set_fd(&mut self, pid: Pid, fd: Fd, file: Option<Rc<RefCell<dyn fd::File>>>) -> bool {
         let process = self.processes.get_mut(&pid).expect("invalid process");
         if let Some(file) = file {
-            process.fdtable.insert(fd, file);
+            assert!(fd < MAX_FDS, "file descriptor out of range");
+            // If the descriptor was already open, this drops our reference to the old file.
+            process.fdtable.insert(fd, file).is_some()
         } else {
-            process.fdtable.remove(&fd);
+            process.fdtable.remove(&fd).is_some()
         }
-        process.next_fd = core::cmp::max(process.next_fd, fd + 1);
     }
 
-    /// Creates a new file descriptor for the given file.
-    pub fn new_fd(&mut self, pid: Pid, file: Rc<RefCell<dyn fd::File>>) -> Fd {
+    /// Returns the number of file descriptors the given process can still open.
+    pub fn free_fds(&self, pid: Pid) -> usize {
+        let process = self.processes.get(&pid).expect("invalid process");
+        MAX_FDS as usize - process.fdtable.len()
+    }
+
+    /// Creates a new file descriptor for the given file, using the lowest unused descriptor.
+    pub fn new_fd(&mut self, pid: Pid, file: Rc<RefCell<dyn fd::File>>) -> Result<Fd, NewFdError> {
         let process = self.processes.get_mut(&pid).expect("invalid process");
-        let fd = process.next_fd;
-        process.next_fd += 1;
+        let fd = (0..MAX_FDS)
+            .find(|fd| !process.fdtable.contains_key(fd))
+            .ok_or(NewFdError::TooManyFiles)?;
         process.fdtable.insert(fd, file);
-        fd
+        Ok(fd)
     }
 
     /// Blocks a process on the given file descriptor.
@@ -365,30 +1315,79 @@ impl Scheduler {
         });
     }
 
+    /// Blocks the current process until another process wakes the futex at physical address `key`.
+    /// Unlike other blocking operations, the syscall is not restarted once the process is woken.
+    pub fn futex_wait(&mut self, key: usize) {
+        let pid = self.current_pid();
+        self.futex_waiters.entry(key).or_default().push(pid);
+        self.block(pid, BlockReason::Futex(key), |_| {});
+    }
+
+    /// Wakes up to `count` processes waiting on the futex at physical address `key`, returning the
+    /// number of processes woken.
+    pub fn futex_wake(&mut self, key: usize, count: usize) -> usize {
+        let waiters = match self.futex_waiters.get_mut(&key) {
+            Some(waiters) => waiters,
+            None => return 0,
+        };
+        let count = core::cmp::min(count, waiters.len());
+        waiters.drain(..count);
+        if waiters.is_empty() {
+            self.futex_waiters.remove(&key);
+        }
+        count
+    }
+
     /// Handles an incoming timer interrupt.
     pub fn handle_interrupt(frame: &mut InterruptFrame) {
+        if !PREEMPTION.load(Ordering::Relaxed) {
+            return;
+        }
         if frame.is_userspace() {
-            TIMER_FIRED.store(false, Ordering::Relaxed);
+            percpu::current().swap_timer_fired(false);
             // // Preempt the user process.
-            let continuation = SCHEDULER
-                .take()
-                .expect("scheduler conflict in userspace?")
-                .as_mut()
-                .expect("no scheduler in userspace?")
-                .schedule(frame);
+            let continuation = {
+                let mut scheduler = SCHEDULER
+                    .try_take()
+                    .expect("scheduler conflict in userspace?");
+                let scheduler = scheduler.as_mut().expect("no scheduler in userspace?");
+                scheduler.stats.preemptions += 1;
+                scheduler.schedule(frame)
+            };
             continuation(frame);
         } else {
-            TIMER_FIRED.store(true, Ordering::Relaxed);
+            percpu::current().swap_timer_fired(true);
         }
     }
 
     /// If the preemption timer fired while we were in kernelspace, schedules a new processs now.
     pub fn preempt_if_needed(&mut self, frame: &mut InterruptFrame) {
-        if TIMER_FIRED.load(Ordering::Relaxed) {
+        if percpu::current().swap_timer_fired(false) {
             // Preempt the user process.
-            TIMER_FIRED.store(false, Ordering::Relaxed);
+            self.stats.preemptions += 1;
             let continuation = self.schedule(frame);
             continuation(frame);
         }
     }
+
+    /// Counts a voluntary 'yield_cpu' for the scheduler's statistics.
+    pub fn count_yield(&mut self) {
+        self.stats.yields += 1;
+    }
+
+    /// Returns the scheduler's counters, with `process_switches` counted for `pid` (or the current
+    /// process, if 0). Returns None if the process does not exist.
+    pub fn stats(&self, pid: Pid) -> Option<SchedStats> {
+        let pid = if pid == 0 { self.current_pid() } else { pid };
+        let process = self.processes.get(&pid)?;
+        let mut cpu_time = process.cpu_time;
+        if pid == self.current_pid() {
+            cpu_time += x86::tsc::rdtsc() - self.scheduled_at;
+        }
+        Some(SchedStats {
+            process_switches: process.switches,
+            process_cpu_time: cpu_time,
+            ..self.stats
+        })
+    }
 }
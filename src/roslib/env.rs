@@ -0,0 +1,32 @@
+//! Environment variables, passed to the program by 'exec'.
+
+use crate::syscall;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+static VARS: AtomicPtr<&str> = AtomicPtr::new(core::ptr::null_mut());
+static VARS_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Looks up the environment placed on the stack by 'exec'. Called before `main`.
+pub(crate) fn init() {
+    let (ptr, len) = syscall::environ();
+    VARS.store(ptr as *mut &str, Ordering::Relaxed);
+    VARS_LEN.store(len, Ordering::Relaxed);
+}
+
+/// Returns the environment, as `KEY=VALUE` strings.
+pub fn vars() -> &'static [&'static str] {
+    let ptr = VARS.load(Ordering::Relaxed);
+    if ptr.is_null() {
+        &[]
+    } else {
+        unsafe { core::slice::from_raw_parts(ptr, VARS_LEN.load(Ordering::Relaxed)) }
+    }
+}
+
+/// Returns the value of the environment variable `key`, if it is set.
+pub fn getenv(key: &str) -> Option<&'static str> {
+    vars().iter().find_map(|var| match var.split_once('=') {
+        Some((k, value)) if k == key => Some(value),
+        _ => None,
+    })
+}
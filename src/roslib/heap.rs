@@ -0,0 +1,146 @@
+//! Userland heap allocator
+//!
+//! A simple first-fit allocator backed by the `sbrk` syscall, allowing ROS programs to use
+//! collection & smart pointer types from the `alloc` standard library crate.
+//!
+//! Free memory is kept on a singly-linked list of blocks sorted by address, so that adjacent free
+//! blocks can be merged back together when memory is deallocated. Every block's address and size
+//! are a multiple of `BLOCK_ALIGN`, which is large enough to hold a freelist entry. When no free
+//! block is large enough for an allocation, we grow the heap with `sbrk` (a few pages at a time)
+//! and add the new memory to the freelist. Memory is never returned to the kernel.
+//!
+//! Threads share the heap, so the freelist is protected by a lock. The lock is 0 when unlocked, 1
+//! when locked, and 2 when locked with other threads waiting for it on its futex.
+
+use crate::syscall;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cell::UnsafeCell,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+/// The granularity of allocations.
+const BLOCK_ALIGN: usize = core::mem::size_of::<FreeBlock>();
+
+/// The minimum number of bytes to request from the kernel at once.
+const GROW_SIZE: usize = 0x4000;
+
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+pub struct HeapAllocator {
+    lock: AtomicU32,
+    freelist: UnsafeCell<*mut FreeBlock>,
+}
+
+// The freelist is only accessed while holding the lock.
+unsafe impl Sync for HeapAllocator {}
+
+#[global_allocator]
+static ALLOCATOR: HeapAllocator = HeapAllocator::new();
+
+impl HeapAllocator {
+    const fn new() -> Self {
+        HeapAllocator {
+            lock: AtomicU32::new(0),
+            freelist: UnsafeCell::new(core::ptr::null_mut()),
+        }
+    }
+
+    /// Runs `f` while holding the allocator lock.
+    fn with_lock<T>(&self, f: impl FnOnce() -> T) -> T {
+        if self
+            .lock
+            .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.lock.swap(2, Ordering::Acquire) != 0 {
+                syscall::futex_wait(&self.lock, 2);
+            }
+        }
+        let result = f();
+        if self.lock.swap(0, Ordering::Release) == 2 {
+            syscall::futex_wake(&self.lock, 1);
+        }
+        result
+    }
+
+    /// Returns the size of the block used to hold an allocation.
+    fn block_size(layout: Layout) -> usize {
+        (core::cmp::max(layout.size(), 1) + BLOCK_ALIGN - 1) & !(BLOCK_ALIGN - 1)
+    }
+
+    unsafe fn allocate(&self, layout: Layout) -> *mut u8 {
+        let size = Self::block_size(layout);
+        let align = core::cmp::max(layout.align(), BLOCK_ALIGN);
+
+        loop {
+            // Look for the first free block with enough room.
+            let mut link: *mut *mut FreeBlock = self.freelist.get();
+            while !(*link).is_null() {
+                let block = *link;
+                let start = block as usize;
+                let end = start + (*block).size;
+                let aligned = (start + align - 1) & !(align - 1);
+                if aligned + size <= end {
+                    // Take the block off the freelist, and give back the parts we're not using.
+                    *link = (*block).next;
+                    self.insert(start, aligned - start);
+                    self.insert(aligned + size, end - (aligned + size));
+                    return aligned as *mut u8;
+                }
+                link = &mut (*block).next;
+            }
+
+            // Nothing was big enough; grow the heap and try again.
+            let grow = (core::cmp::max(size + align, GROW_SIZE) + GROW_SIZE - 1) & !(GROW_SIZE - 1);
+            match syscall::sbrk(grow as isize) {
+                Some(start) => self.insert(start as usize, grow),
+                None => return core::ptr::null_mut(),
+            }
+        }
+    }
+
+    /// Adds a block to the freelist, merging it with its neighbors if they are also free.
+    unsafe fn insert(&self, addr: usize, size: usize) {
+        if size == 0 {
+            return;
+        }
+
+        // Find the free blocks immediately before and after this one.
+        let mut prev: *mut FreeBlock = core::ptr::null_mut();
+        let mut next = *self.freelist.get();
+        while !next.is_null() && (next as usize) < addr {
+            prev = next;
+            next = (*next).next;
+        }
+
+        let block = addr as *mut FreeBlock;
+        block.write(FreeBlock { size, next });
+        if !next.is_null() && addr + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+
+        if prev.is_null() {
+            *self.freelist.get() = block;
+        } else if prev as usize + (*prev).size == addr {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            (*prev).next = block;
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for HeapAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.with_lock(|| self.allocate(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.with_lock(|| self.insert(ptr as usize, Self::block_size(layout)));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::syscall;
+use alloc::vec::Vec;
 use core::fmt::{self, Write};
 
 pub type Fd = u32;
@@ -37,14 +38,24 @@ impl File {
     /// Attemts to read from the file until `buf` is full.
     /// Note that the buffer still will not be filled if the end-of-file is reached.
     pub fn read_all(&mut self, buf: &mut [u8]) -> Result<usize, syscall::ReadError> {
-        let mut i = 0;
-        while i < buf.len() {
-            match syscall::read(self.fd, &mut buf[i..])? {
-                0 => break,
-                n => i += n,
+        syscall::read_all(self.fd, buf)
+    }
+
+    /// Reads bytes into `line` until a newline (which is included) or the end-of-file is reached,
+    /// returning the number of bytes read. Returns 0 if the end-of-file was already reached.
+    pub fn read_line(&mut self, line: &mut Vec<u8>) -> Result<usize, syscall::ReadError> {
+        let start = line.len();
+        loop {
+            let mut c = 0u8;
+            if self.read(core::slice::from_mut(&mut c))? == 0 {
+                break;
+            }
+            line.push(c);
+            if c == b'\n' {
+                break;
             }
         }
-        Ok(i)
+        Ok(line.len() - start)
     }
 
     /// Writes up to 'buf.len()' bytes to the file, returning the number of bytes written.
@@ -54,16 +65,20 @@ impl File {
     }
 
     /// Writes the entire buffer to the file.
+    /// Fails with `WriteError::BrokenPipe` if the file stops accepting data partway through.
     pub fn write_all(&mut self, buf: &[u8]) -> Result<(), syscall::WriteError> {
-        let mut i = 0;
-        while i < buf.len() {
-            i += syscall::write(self.fd, &buf[i..])?;
-        }
+        syscall::write_all(self.fd, buf)?;
         Ok(())
     }
 
     /// Closes the file.
     pub fn close(self) {
+        syscall::close(self.fd);
+    }
+
+    /// Closes the file, returning false if the file descriptor was not open (for instance, because
+    /// it was already closed).
+    pub fn close_checked(self) -> bool {
         syscall::close(self.fd)
     }
 }
@@ -2,7 +2,11 @@
 //!
 //! Defines data structures and functions ROS programs can use to communicate with the kernel.
 
+pub mod env;
+pub mod heap;
 pub mod io;
+pub mod prelude;
+pub mod time;
 
 pub use crate::syscall;
 
@@ -13,9 +17,16 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
     syscall::exit()
 }
 
+/// Userland allocation failure handler
+#[alloc_error_handler]
+fn alloc_error(layout: core::alloc::Layout) -> ! {
+    panic!("allocation failed: {:#08x?}", layout)
+}
+
 /// Rust runtime entry point, equivalent to the '_start' function on a Unix-like operating system
 #[lang = "start"]
 fn lang_start<T>(main: fn() -> T, _argc: isize, _argv: *const *const u8, _sigpipe: u8) -> isize {
+    env::init();
     main();
     syscall::exit()
 }
@@ -0,0 +1,13 @@
+//! ROS userland prelude module
+//!
+//! Bundle up the printing macros and common heap-allocated types so that they don't have to be
+//! manually imported every time they're needed.
+
+pub use crate::{eprint, eprintln, print, println};
+pub use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
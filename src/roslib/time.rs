@@ -0,0 +1,19 @@
+use crate::syscall;
+use core::arch::asm;
+
+/// Reads the CPU's timestamp counter, which counts cycles at the rate returned by `tsc_hz`.
+pub fn rdtsc() -> u64 {
+    let (lo, hi): (u32, u32);
+    unsafe { asm!("rdtsc", out("eax") lo, out("edx") hi, options(nomem, nostack)) };
+    (hi as u64) << 32 | lo as u64
+}
+
+/// Returns the frequency of the timestamp counter, in Hz.
+pub fn tsc_hz() -> u64 {
+    syscall::tsc_hz()
+}
+
+/// Converts a number of timestamp counter cycles into nanoseconds.
+pub fn cycles_to_ns(cycles: u64) -> u64 {
+    (cycles as u128 * 1_000_000_000 / tsc_hz() as u128) as u64
+}
@@ -12,11 +12,58 @@ pub enum SyscallId {
     Wait,
     Dup2,
     NullFd,
+    SetEcho,
+    FdPending,
+    SocketPair,
+    PipeSized,
+    Tee,
+    ReadAll,
+    WriteAll,
+    Readv,
+    Writev,
+    TscHz,
+    Time,
+    Sbrk,
+    SpawnThread,
+    FutexWait,
+    FutexWake,
+    Signal,
+    Kill,
+    SigReturn,
+    Alarm,
+    SetPgid,
+    GetPgid,
+    SetForeground,
+    KillGroup,
+    TryWait,
+    Mkfifo,
+    OpenFifo,
+    UnlinkFifo,
+    ExecPath,
+    ListPrograms,
+    SetLogLevel,
+    OpenRawKeyboard,
+    OpenMouse,
+    SetVideoMode,
+    Ioctl,
+    Chdir,
+    Getcwd,
+    Environ,
+    SchedStats,
+    SetPreemption,
+    GetRegs,
+    DebugResume,
+    DebugWait,
+    SetBreakpoint,
 }
 
 pub type Fd = u32;
 pub type Pid = u32;
 
+/// The maximum number of file descriptors a process may have open at once. Valid file descriptors
+/// are less than this value.
+pub const MAX_FDS: Fd = 256;
+
 /// An argument to the 'read' syscall.
 pub struct ReadArg<'a> {
     pub fd: Fd,
@@ -29,6 +76,259 @@ pub struct WriteArg<'a> {
     pub buf: &'a [u8],
 }
 
+/// An argument to the 'read_all' syscall.
+pub struct ReadAllArg<'a> {
+    pub fd: Fd,
+    pub buf: &'a mut [u8],
+    /// The number of bytes read so far, which should be initialized to 0. The kernel updates this
+    /// as the read progresses, so that it can pick up where it left off after blocking.
+    pub progress: &'a mut usize,
+}
+
+/// An argument to the 'write_all' syscall.
+pub struct WriteAllArg<'a> {
+    pub fd: Fd,
+    pub buf: &'a [u8],
+    /// The number of bytes written so far, which should be initialized to 0. The kernel updates
+    /// this as the write progresses, so that it can pick up where it left off after blocking.
+    pub progress: &'a mut usize,
+}
+
+/// A buffer for vectored writes.
+pub type IoVec<'a> = &'a [u8];
+
+/// A buffer for vectored reads.
+pub type IoVecMut<'a> = &'a mut [u8];
+
+/// An argument to the 'readv' syscall.
+pub struct ReadvArg<'a, 'b> {
+    pub fd: Fd,
+    pub bufs: &'a mut [IoVecMut<'b>],
+}
+
+/// An argument to the 'writev' syscall.
+pub struct WritevArg<'a> {
+    pub fd: Fd,
+    pub bufs: &'a [IoVec<'a>],
+}
+
+/// An argument to the 'exec' and 'exec_path' syscalls.
+pub struct ExecArg<'a, 'b, T> {
+    /// The executable to run: an index for 'exec', or a name for 'exec_path'.
+    pub program: T,
+    /// The new program's environment, as `KEY=VALUE` strings. The total size of the strings, plus
+    /// that of the slice itself, must not exceed `MAX_ENV_SIZE`.
+    pub env: &'a [&'b str],
+}
+
+/// The maximum size of the environment passed to 'exec', in bytes.
+pub const MAX_ENV_SIZE: usize = 4096;
+
+/// An argument to the 'spawn_thread' syscall.
+pub struct SpawnThreadArg {
+    /// The address of the thread's entry function, which takes `arg` as its only argument.
+    pub entry: usize,
+    pub arg: usize,
+    pub stack_size: usize,
+}
+
+/// An argument to the 'futex_wait' syscall.
+pub struct FutexWaitArg<'a> {
+    pub futex: &'a core::sync::atomic::AtomicU32,
+    pub expected: u32,
+}
+
+/// What access to an address triggers a hardware breakpoint.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakpointKind {
+    /// Executing the instruction at the address.
+    Execute,
+    /// Writing to the address.
+    Write,
+    /// Reading from or writing to the address.
+    ReadWrite,
+}
+
+/// An argument to the 'set_breakpoint' syscall.
+pub struct SetBreakpointArg {
+    /// The child process to set the breakpoint in.
+    pub pid: Pid,
+    /// Which of the 4 hardware breakpoints to set.
+    pub index: u32,
+    /// The userspace address to watch, or 0 to clear the breakpoint.
+    pub addr: usize,
+    pub kind: BreakpointKind,
+    /// The number of bytes to watch: 1, 2, or 4, with `addr` aligned to it. Execute breakpoints
+    /// must have a length of 1.
+    pub len: u32,
+}
+
+/// An argument to the 'ioctl' syscall.
+pub struct IoctlArg<'a> {
+    pub fd: Fd,
+    /// The operation to perform; one of the constants in the `ioctl` module.
+    pub request: u32,
+    /// The request's input and output, whose layout depends on the request.
+    pub arg: &'a mut [u8],
+}
+
+/// Device control requests for the 'ioctl' syscall.
+pub mod ioctl {
+    /// Console: enables local echo if `arg[0]` is 1, or disables it if `arg[0]` is 0.
+    pub const CONSOLE_SET_ECHO: u32 = 0x100;
+    /// Console: sets `arg[0]` to 1 if local echo is enabled, or 0 if it is disabled.
+    pub const CONSOLE_GET_ECHO: u32 = 0x101;
+    /// Console: discards any input that has been received but not yet read. `arg` must be empty.
+    pub const CONSOLE_FLUSH_INPUT: u32 = 0x102;
+    /// Console: handles the bytes in `arg` as if they had been typed, including local echo and
+    /// line editing. Together with `CONSOLE_FLUSH_INPUT`, this lets a line editor replace the
+    /// pending input.
+    pub const CONSOLE_PUSH_INPUT: u32 = 0x103;
+    /// Console: stores the console's dimensions in `arg`, encoded as a `WinSize`.
+    pub const CONSOLE_GET_WINSIZE: u32 = 0x104;
+}
+
+/// The dimensions of a terminal, in characters.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WinSize {
+    pub cols: u16,
+    pub rows: u16,
+}
+impl WinSize {
+    /// The size of an encoded `WinSize`.
+    pub const SIZE: usize = 4;
+
+    /// Encodes the dimensions as they are returned by the `CONSOLE_GET_WINSIZE` ioctl.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let [c0, c1] = self.cols.to_le_bytes();
+        let [r0, r1] = self.rows.to_le_bytes();
+        [c0, c1, r0, r1]
+    }
+
+    /// Decodes dimensions returned by the `CONSOLE_GET_WINSIZE` ioctl.
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        Self {
+            cols: u16::from_le_bytes([bytes[0], bytes[1]]),
+            rows: u16::from_le_bytes([bytes[2], bytes[3]]),
+        }
+    }
+}
+
+/// A mouse movement or button change, read from a file descriptor opened with 'open_mouse'.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// Horizontal movement, positive to the right.
+    pub dx: i16,
+    /// Vertical movement, positive upward.
+    pub dy: i16,
+    /// The buttons held down: bit 0 is left, bit 1 is right, and bit 2 is middle.
+    pub buttons: u8,
+}
+impl MouseEvent {
+    /// The size of an encoded event.
+    pub const SIZE: usize = 5;
+
+    /// Encodes the event as it is read from the mouse device.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let [dx0, dx1] = self.dx.to_le_bytes();
+        let [dy0, dy1] = self.dy.to_le_bytes();
+        [dx0, dx1, dy0, dy1, self.buttons]
+    }
+
+    /// Decodes an event read from the mouse device.
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        Self {
+            dx: i16::from_le_bytes([bytes[0], bytes[1]]),
+            dy: i16::from_le_bytes([bytes[2], bytes[3]]),
+            buttons: bytes[4],
+        }
+    }
+}
+
+/// A display mode that can be selected with the 'set_video_mode' syscall.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoMode {
+    /// The 80x25 text console.
+    Text,
+    /// 320x200 pixels, one byte per pixel in the form `RRRGGGBB`.
+    Graphics,
+}
+
+/// A signal that can be sent to a process with the 'kill' syscall.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// Terminates the process. Cannot be handled.
+    Kill,
+    /// Asks the process to terminate.
+    Term,
+    /// Sent when an alarm set by the 'alarm' syscall expires.
+    Alarm,
+}
+
+/// The severity of a kernel log message, from most to least severe.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    /// Something went wrong in the kernel.
+    Error,
+    /// Something went wrong, but the kernel can keep going (for instance, a process crashed).
+    Warn,
+    /// Normal progress messages, such as those printed during boot.
+    Info,
+    /// Detailed messages about what processes are doing.
+    Debug,
+}
+
+/// An argument to the 'signal' syscall.
+pub struct SignalArg {
+    /// The address of the function that receives signals, which is called with `handler` and the
+    /// signal and must finish with the 'sigreturn' syscall.
+    pub entry: usize,
+    /// The signal handler, or 0 to restore the default action.
+    pub handler: usize,
+}
+
+/// Scheduler counters returned by the 'sched_stats' syscall. The counts start at boot.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SchedStats {
+    /// The number of times the CPU switched from one process to another.
+    pub context_switches: u64,
+    /// The number of times the timer preempted a process.
+    pub preemptions: u64,
+    /// The number of times a process called 'yield_cpu'.
+    pub yields: u64,
+    /// The number of times the requested process was switched to.
+    pub process_switches: u64,
+    /// The CPU time the requested process has used, in timestamp counter cycles (see 'tsc_hz').
+    pub process_cpu_time: u64,
+}
+
+/// A process's saved user-mode register state, returned by the 'get_regs' syscall.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Regs {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+    pub esi: u32,
+    pub edi: u32,
+    pub ebp: u32,
+    pub esp: u32,
+    pub eip: u32,
+    pub eflags: u32,
+    pub cs: u32,
+    pub ss: u32,
+    pub ds: u32,
+    pub es: u32,
+    pub fs: u32,
+    pub gs: u32,
+}
+
 /// An error returned by the 'read' syscall.
 #[derive(Debug)]
 pub enum ReadError {
@@ -44,6 +344,103 @@ pub enum WriteError {
     BadFd,
     /// The file descriptor does not support writing.
     Unsupported,
+    /// The file stopped accepting data before the whole buffer was written.
+    BrokenPipe,
+}
+
+/// An error returned by the 'fd_pending' syscall.
+#[derive(Debug)]
+pub enum FdPendingError {
+    /// The file descriptor does not exist.
+    BadFd,
+}
+
+/// An error returned by the 'tee' syscall.
+#[derive(Debug)]
+pub enum TeeError {
+    /// One of the file descriptors does not exist.
+    BadFd,
+    /// The process already has `MAX_FDS` file descriptors open.
+    TooManyFiles,
+}
+
+/// An error returned by the 'mkfifo' syscall.
+#[derive(Debug)]
+pub enum MkfifoError {
+    /// A FIFO with the given name already exists.
+    AlreadyExists,
+}
+
+/// An error returned by the 'open_fifo' syscall.
+#[derive(Debug)]
+pub enum OpenFifoError {
+    /// No FIFO with the given name exists.
+    NotFound,
+    /// Another process has already opened the FIFO in the same direction, and is waiting for the
+    /// other end to be opened.
+    Busy,
+    /// The process already has `MAX_FDS` file descriptors open.
+    TooManyFiles,
+}
+
+/// An error returned by the 'unlink_fifo' syscall.
+#[derive(Debug)]
+pub enum UnlinkFifoError {
+    /// No FIFO with the given name exists.
+    NotFound,
+}
+
+/// An error returned by the 'fork' syscall.
+#[derive(Debug)]
+pub enum ForkError {
+    /// There is not enough memory to copy the process.
+    OutOfMemory,
+}
+
+/// An error returned by the 'spawn_thread' syscall.
+#[derive(Debug)]
+pub enum SpawnThreadError {
+    /// The stack size is zero or too large.
+    InvalidStackSize,
+    /// There is not enough memory for the thread's stack.
+    OutOfMemory,
+}
+
+/// An error returned by the 'kill' syscall.
+#[derive(Debug)]
+pub enum KillError {
+    /// The process does not exist.
+    NoSuchProcess,
+}
+
+/// How a process terminated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// The process called 'exit'.
+    Exited,
+    /// The process was killed by a signal.
+    Signaled(Signal),
+    /// The process was killed by the kernel after a fault, such as an invalid memory access.
+    Faulted,
+}
+
+/// Why 'debug_wait' returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugEvent {
+    /// The child stopped after a single step, and waits for 'debug_resume'.
+    Stopped,
+    /// The child stopped at the hardware breakpoint with the given index, and waits for
+    /// 'debug_resume'.
+    Breakpoint(u32),
+    /// The child terminated. Its exit status can still be collected with 'wait'.
+    Exited,
+}
+
+/// An error returned by the 'try_wait' syscall.
+#[derive(Debug)]
+pub enum WaitError {
+    /// The process does not exist, or its exit status has already been collected.
+    NoSuchProcess,
 }
 
 /// An error returned by the 'exec' syscall.
@@ -54,4 +451,93 @@ pub enum ExecError {
 
     /// Could not read the executable from disk.
     IoError,
+
+    /// The environment is larger than `MAX_ENV_SIZE`.
+    EnvTooLarge,
+}
+
+/// An error returned by the 'set_video_mode' syscall.
+#[derive(Debug)]
+pub enum SetVideoModeError {
+    /// Another process is using graphics mode.
+    Busy,
+    /// There is no room in the address space to map the framebuffer.
+    OutOfMemory,
+}
+
+/// An error returned by the 'ioctl' syscall.
+#[derive(Debug)]
+pub enum IoctlError {
+    /// The file descriptor does not exist.
+    BadFd,
+    /// The file descriptor does not support the request.
+    Unsupported,
+    /// The argument has the wrong size or an invalid value for the request.
+    InvalidArgument,
+}
+
+/// An error returned by the 'chdir' syscall.
+#[derive(Debug)]
+pub enum ChdirError {
+    /// Nothing exists at the given path.
+    NotFound,
+    /// The path names a file rather than a directory.
+    NotADirectory,
+}
+
+/// An error returned by the 'dup2' syscall.
+#[derive(Debug)]
+pub enum Dup2Error {
+    /// The source file descriptor does not exist, or the destination is not below `MAX_FDS`.
+    BadFd,
+}
+
+/// An error returned by syscalls that open new file descriptors, such as 'pipe' or 'null_fd'.
+#[derive(Debug)]
+pub enum NewFdError {
+    /// The process already has `MAX_FDS` file descriptors open.
+    TooManyFiles,
+}
+
+/// An error returned by the 'time' syscall.
+#[derive(Debug)]
+pub enum TimeError {
+    /// The real-time clock reports an impossible date or time.
+    InvalidClock,
+}
+
+/// An error returned by the 'sched_stats' syscall.
+#[derive(Debug)]
+pub enum SchedStatsError {
+    /// The process does not exist.
+    NoSuchProcess,
+}
+
+/// An error returned by the 'get_regs' syscall.
+#[derive(Debug)]
+pub enum GetRegsError {
+    /// The process does not exist, or has exited without faulting.
+    NoSuchProcess,
+    /// The process is not a child of the caller.
+    NotAChild,
+}
+
+/// An error returned by the 'debug_resume' and 'debug_wait' syscalls.
+#[derive(Debug)]
+pub enum DebugError {
+    /// The process does not exist.
+    NoSuchProcess,
+    /// The process is not a child of the caller.
+    NotAChild,
+}
+
+/// An error returned by the 'set_breakpoint' syscall.
+#[derive(Debug)]
+pub enum SetBreakpointError {
+    /// The process does not exist.
+    NoSuchProcess,
+    /// The process is not a child of the caller.
+    NotAChild,
+    /// The index, address, or length is invalid.
+    InvalidBreakpoint,
 }
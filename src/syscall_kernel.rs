@@ -1,15 +1,16 @@
 //! Kernel-side syscall handlers
 
 use core::cell::RefCell;
-use core::ops::Deref;
+use core::ops::DerefMut;
+use core::sync::atomic::{AtomicU32, Ordering};
 
-use alloc::rc::Rc;
+use alloc::{rc::Rc, string::String, vec::Vec};
 
 use crate::syscall_common::*;
 use crate::{
-    kprintln,
+    kdebug, kwarn,
     process::{self, fd, scheduler},
-    x86::{interrupt, mmu},
+    x86::{self, debug_regs, interrupt, mmu},
 };
 
 /// Syscall interrupt handler
@@ -17,27 +18,104 @@ pub fn syscall(frame: &mut interrupt::InterruptFrame) {
     // al == syscall number
     let matched = match_syscall(frame, SyscallId::Exit, |frame, _: ()| exit(frame))
         || match_syscall(frame, SyscallId::YieldCpu, |frame, _: ()| yield_cpu(frame))
-        || match_syscall_blocking(frame, SyscallId::Read, read)
-        || match_syscall_blocking(frame, SyscallId::Write, write)
+        || match_syscall_blocking(frame, SyscallId::Read, Restart::Restartable, read)
+        || match_syscall_blocking(frame, SyscallId::Write, Restart::Restartable, write)
         || match_syscall(frame, SyscallId::Close, close)
-        || match_syscall(frame, SyscallId::Pipe, |_, _: ()| pipe())
+        || match_syscall(frame, SyscallId::Pipe, |_, _: ()| pipe(fd::PIPE_BUF_LEN))
+        || match_syscall(frame, SyscallId::PipeSized, |_, capacity| pipe(capacity))
         || match_syscall(frame, SyscallId::Fork, |frame, _: ()| fork(frame))
         || match_syscall_args(frame, SyscallId::Exec, exec)
-        || match_syscall_blocking(frame, SyscallId::Wait, wait)
+        || match_syscall_args(frame, SyscallId::ExecPath, exec_path)
+        || match_syscall(frame, SyscallId::ListPrograms, |_, buf| list_programs(buf))
+        || match_syscall(frame, SyscallId::Chdir, |_, path| chdir(path))
+        || match_syscall(frame, SyscallId::Getcwd, |_, buf| getcwd(buf))
+        || match_syscall(frame, SyscallId::Environ, |_, _: ()| environ())
+        || match_syscall(frame, SyscallId::SchedStats, |_, pid| sched_stats(pid))
+        || match_syscall(frame, SyscallId::SetPreemption, |_, enabled| {
+            scheduler::set_preemption(enabled)
+        })
+        || match_syscall(frame, SyscallId::GetRegs, |_, pid| get_regs(pid))
+        || match_syscall(frame, SyscallId::DebugResume, |_, arg| debug_resume(arg))
+        || match_syscall_blocking(
+            frame,
+            SyscallId::DebugWait,
+            Restart::Restartable,
+            debug_wait,
+        )
+        || match_syscall(frame, SyscallId::SetBreakpoint, |_, arg| {
+            set_breakpoint(arg)
+        })
+        || match_syscall(frame, SyscallId::SetLogLevel, |_, level: LogLevel| {
+            crate::util::kprint::LOG_LEVEL.store(level as u8, Ordering::Relaxed)
+        })
+        || match_syscall_blocking(frame, SyscallId::Wait, Restart::Restartable, wait)
         || match_syscall(frame, SyscallId::Dup2, dup2)
-        || match_syscall(frame, SyscallId::NullFd, |_, _: ()| null_fd());
+        || match_syscall(frame, SyscallId::NullFd, |_, _: ()| null_fd())
+        || match_syscall(frame, SyscallId::OpenRawKeyboard, |_, _: ()| {
+            open_raw_keyboard()
+        })
+        || match_syscall(frame, SyscallId::OpenMouse, |_, _: ()| open_mouse())
+        || match_syscall(frame, SyscallId::SetVideoMode, |_, mode| {
+            set_video_mode(mode)
+        })
+        || match_syscall(frame, SyscallId::SetEcho, |_, enabled| {
+            fd::CONSOLE_BUFFER.set_echo(enabled)
+        })
+        || match_syscall(frame, SyscallId::FdPending, fd_pending)
+        || match_syscall(frame, SyscallId::Ioctl, |_, arg| ioctl(arg))
+        || match_syscall(frame, SyscallId::SocketPair, |_, _: ()| socketpair())
+        || match_syscall(frame, SyscallId::Tee, tee)
+        || match_syscall_blocking(frame, SyscallId::ReadAll, Restart::Resumable, read_all)
+        || match_syscall_blocking(frame, SyscallId::WriteAll, Restart::Resumable, write_all)
+        || match_syscall_blocking(frame, SyscallId::Readv, Restart::Restartable, readv)
+        || match_syscall_blocking(frame, SyscallId::Writev, Restart::Restartable, writev)
+        || match_syscall(frame, SyscallId::TscHz, |_, _: ()| *x86::tsc::TSC_HZ.get())
+        || match_syscall(frame, SyscallId::Time, |_, _: ()| time())
+        || match_syscall(frame, SyscallId::Sbrk, |_, increment| sbrk(increment))
+        || match_syscall(frame, SyscallId::SpawnThread, spawn_thread)
+        || match_syscall_args(frame, SyscallId::FutexWait, futex_wait)
+        || match_syscall(frame, SyscallId::FutexWake, futex_wake)
+        || match_syscall(frame, SyscallId::Signal, |_, arg| signal(arg))
+        || match_syscall_args(frame, SyscallId::Kill, kill)
+        || match_syscall(frame, SyscallId::SigReturn, |frame, _: ()| sigreturn(frame))
+        || match_syscall(frame, SyscallId::Alarm, |_, secs| alarm(secs))
+        || match_syscall(frame, SyscallId::SetPgid, |_, arg| set_pgid(arg))
+        || match_syscall(frame, SyscallId::GetPgid, |_, _: ()| get_pgid())
+        || match_syscall(frame, SyscallId::SetForeground, |_, pgid| {
+            set_foreground(pgid)
+        })
+        || match_syscall_args(frame, SyscallId::KillGroup, kill_group)
+        || match_syscall(frame, SyscallId::TryWait, |_, pid| try_wait(pid))
+        || match_syscall(frame, SyscallId::Mkfifo, |_, name| mkfifo(name))
+        || match_syscall(frame, SyscallId::OpenFifo, |_, arg| open_fifo(arg))
+        || match_syscall(frame, SyscallId::UnlinkFifo, |_, name| unlink_fifo(name));
 
-    // If no syscall matched, panic
-    // TODO: kill userspace process instead
-    assert!(matched, "invalid syscall");
+    if !matched {
+        kill_caller(frame, "an invalid syscall number");
+    }
+}
+
+/// Terminates the calling process for passing an invalid syscall number or argument.
+fn kill_caller(frame: &mut interrupt::InterruptFrame, what: &str) {
+    let continuation = {
+        let mut scheduler = scheduler::SCHEDULER.lock();
+        let scheduler = scheduler.as_mut().unwrap();
+        kwarn!(
+            "terminating process {}, which passed {}",
+            scheduler.current_pid(),
+            what
+        );
+        scheduler.kill_current_process(frame, ExitStatus::Faulted)
+    };
+    continuation(frame);
 }
 
 fn exit(frame: &mut interrupt::InterruptFrame) {
     let continuation = {
-        let mut scheduler = scheduler::SCHEDULER.take().unwrap();
+        let mut scheduler = scheduler::SCHEDULER.lock();
         let scheduler = scheduler.as_mut().unwrap();
-        kprintln!("Process {} exited.", scheduler.current_pid());
-        scheduler.kill_current_process(frame).1
+        kdebug!("Process {} exited.", scheduler.current_pid());
+        scheduler.kill_current_process(frame, ExitStatus::Exited)
     };
 
     continuation(frame);
@@ -45,8 +123,14 @@ fn exit(frame: &mut interrupt::InterruptFrame) {
 
 fn yield_cpu(frame: &mut interrupt::InterruptFrame) {
     let continuation = {
-        let mut scheduler = scheduler::SCHEDULER.take().unwrap();
+        let mut scheduler = scheduler::SCHEDULER.lock();
         let scheduler = scheduler.as_mut().unwrap();
+        // With nothing else to run, a reschedule would just switch back to us.
+        // The timer still preempts us to run kernel tasks and deliver alarms.
+        scheduler.count_yield();
+        if !scheduler.has_other_runnable(scheduler.current_pid()) {
+            return;
+        }
         scheduler.schedule(frame)
     };
     continuation(frame);
@@ -56,13 +140,13 @@ fn read(
     _frame: &mut interrupt::InterruptFrame,
     arg: ReadArg,
 ) -> Blocking<Result<usize, ReadError>> {
-    let mut scheduler = scheduler::SCHEDULER.take().unwrap();
+    let mut scheduler = scheduler::SCHEDULER.lock();
     let scheduler = scheduler.as_mut().unwrap();
 
     if let Some(fd) = scheduler.get_fd(scheduler.current_pid(), arg.fd) {
         let mut fd = fd.borrow_mut();
         if fd.can_read() {
-            Ok(fd.read(arg.buf))
+            Ok(read_file(&mut *fd, arg.buf))
         } else {
             block(scheduler::BlockReason::File {
                 fd: arg.fd,
@@ -78,13 +162,13 @@ fn write(
     _frame: &mut interrupt::InterruptFrame,
     arg: WriteArg,
 ) -> Blocking<Result<usize, WriteError>> {
-    let mut scheduler = scheduler::SCHEDULER.take().unwrap();
+    let mut scheduler = scheduler::SCHEDULER.lock();
     let scheduler = scheduler.as_mut().unwrap();
 
     if let Some(fd) = scheduler.get_fd(scheduler.current_pid(), arg.fd) {
         let mut fd = fd.borrow_mut();
         if fd.can_write() {
-            Ok(fd.write(arg.buf))
+            Ok(write_file(&mut *fd, arg.buf))
         } else {
             block(scheduler::BlockReason::File {
                 fd: arg.fd,
@@ -96,75 +180,756 @@ fn write(
     }
 }
 
-fn close(_frame: &mut interrupt::InterruptFrame, fd: Fd) {
-    let mut scheduler = scheduler::SCHEDULER.take().unwrap();
+fn read_all(
+    _frame: &mut interrupt::InterruptFrame,
+    arg: ReadAllArg,
+) -> Blocking<Result<usize, ReadError>> {
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    let scheduler = scheduler.as_mut().unwrap();
+
+    let fd = match scheduler.get_fd(scheduler.current_pid(), arg.fd) {
+        Some(fd) => fd,
+        None => return Ok(Err(ReadError::BadFd)),
+    };
+    let mut fd = fd.borrow_mut();
+
+    // Keep reading until the buffer is full or we reach the end-of-file. Since the syscall is
+    // restarted from the beginning if it blocks, record our progress in userspace as we go.
+    while let Some(buf) = arg
+        .buf
+        .get_mut(*arg.progress..)
+        .filter(|buf| !buf.is_empty())
+    {
+        if !fd.can_read() {
+            return block(scheduler::BlockReason::File {
+                fd: arg.fd,
+                access_type: fd::AccessType::Read,
+            });
+        }
+        match read_file(&mut *fd, buf) {
+            Ok(0) => break,
+            Ok(n) => *arg.progress += n,
+            Err(e) => return Ok(Err(e)),
+        }
+    }
+    Ok(Ok(*arg.progress))
+}
+
+fn write_all(
+    _frame: &mut interrupt::InterruptFrame,
+    arg: WriteAllArg,
+) -> Blocking<Result<usize, WriteError>> {
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    let scheduler = scheduler.as_mut().unwrap();
+
+    let fd = match scheduler.get_fd(scheduler.current_pid(), arg.fd) {
+        Some(fd) => fd,
+        None => return Ok(Err(WriteError::BadFd)),
+    };
+    let mut fd = fd.borrow_mut();
+
+    // Keep writing until the whole buffer is consumed. Since the syscall is restarted from the
+    // beginning if it blocks, record our progress in userspace as we go.
+    while let Some(buf) = arg.buf.get(*arg.progress..).filter(|buf| !buf.is_empty()) {
+        if !fd.can_write() {
+            return block(scheduler::BlockReason::File {
+                fd: arg.fd,
+                access_type: fd::AccessType::Write,
+            });
+        }
+        match write_file(&mut *fd, buf) {
+            Ok(0) => return Ok(Err(WriteError::BrokenPipe)),
+            Ok(n) => *arg.progress += n,
+            Err(e) => return Ok(Err(e)),
+        }
+    }
+    Ok(Ok(*arg.progress))
+}
+
+fn readv(
+    _frame: &mut interrupt::InterruptFrame,
+    arg: ReadvArg,
+) -> Blocking<Result<usize, ReadError>> {
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    let scheduler = scheduler.as_mut().unwrap();
+
+    let fd = match scheduler.get_fd(scheduler.current_pid(), arg.fd) {
+        Some(fd) => fd,
+        None => return Ok(Err(ReadError::BadFd)),
+    };
+    let mut fd = fd.borrow_mut();
+    if !fd.can_read() {
+        return block(scheduler::BlockReason::File {
+            fd: arg.fd,
+            access_type: fd::AccessType::Read,
+        });
+    }
+
+    // Fill each buffer in turn, stopping once we'd have to block.
+    let mut total = 0;
+    for buf in arg.bufs.iter_mut() {
+        let count = match read_file(&mut *fd, buf) {
+            Ok(count) => count,
+            Err(e) if total == 0 => return Ok(Err(e)),
+            Err(_) => break,
+        };
+        total += count;
+        if count < buf.len() || !fd.can_read() {
+            break;
+        }
+    }
+    Ok(Ok(total))
+}
+
+fn writev(
+    _frame: &mut interrupt::InterruptFrame,
+    arg: WritevArg,
+) -> Blocking<Result<usize, WriteError>> {
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    let scheduler = scheduler.as_mut().unwrap();
+
+    let fd = match scheduler.get_fd(scheduler.current_pid(), arg.fd) {
+        Some(fd) => fd,
+        None => return Ok(Err(WriteError::BadFd)),
+    };
+    let mut fd = fd.borrow_mut();
+    if !fd.can_write() {
+        return block(scheduler::BlockReason::File {
+            fd: arg.fd,
+            access_type: fd::AccessType::Write,
+        });
+    }
+
+    // Write each buffer in turn, stopping once we'd have to block.
+    let mut total = 0;
+    for buf in arg.bufs {
+        let count = match write_file(&mut *fd, buf) {
+            Ok(count) => count,
+            Err(e) if total == 0 => return Ok(Err(e)),
+            Err(_) => break,
+        };
+        total += count;
+        if count < buf.len() || !fd.can_write() {
+            break;
+        }
+    }
+    Ok(Ok(total))
+}
+
+/// Reads from a file on behalf of a blocking syscall.
+fn read_file(fd: &mut dyn fd::File, buf: &mut [u8]) -> Result<usize, ReadError> {
+    #[cfg(debug_assertions)]
+    process::percpu::current().note_side_effect();
+    fd.read(buf)
+}
+
+/// Writes to a file on behalf of a blocking syscall.
+fn write_file(fd: &mut dyn fd::File, buf: &[u8]) -> Result<usize, WriteError> {
+    #[cfg(debug_assertions)]
+    process::percpu::current().note_side_effect();
+    fd.write(buf)
+}
+
+fn close(_frame: &mut interrupt::InterruptFrame, fd: Fd) -> bool {
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    let scheduler = scheduler.as_mut().unwrap();
+    scheduler.set_fd(scheduler.current_pid(), fd, None)
+}
+
+fn pipe(capacity: usize) -> Result<(Fd, Fd), NewFdError> {
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    let scheduler = scheduler.as_mut().unwrap();
+    let (read, write) = fd::pipe_sized(capacity);
+    let pid = scheduler.current_pid();
+    if scheduler.free_fds(pid) < 2 {
+        return Err(NewFdError::TooManyFiles);
+    }
+    Ok((
+        scheduler.new_fd(pid, Rc::new(RefCell::new(read)))?,
+        scheduler.new_fd(pid, Rc::new(RefCell::new(write)))?,
+    ))
+}
+
+fn socketpair() -> Result<(Fd, Fd), NewFdError> {
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    let scheduler = scheduler.as_mut().unwrap();
+    let (a, b) = fd::socketpair();
+    let pid = scheduler.current_pid();
+    if scheduler.free_fds(pid) < 2 {
+        return Err(NewFdError::TooManyFiles);
+    }
+    Ok((
+        scheduler.new_fd(pid, Rc::new(RefCell::new(a)))?,
+        scheduler.new_fd(pid, Rc::new(RefCell::new(b)))?,
+    ))
+}
+
+fn mkfifo(name: &str) -> Result<(), MkfifoError> {
+    let mut fifos = fd::FIFOS.lock();
+    if fifos.contains_key(name) {
+        Err(MkfifoError::AlreadyExists)
+    } else {
+        fifos.insert(name.into(), fd::Fifo::default());
+        Ok(())
+    }
+}
+
+fn open_fifo(arg: (&str, bool)) -> Result<Fd, OpenFifoError> {
+    let (name, write) = arg;
+    let access_type = if write {
+        fd::AccessType::Write
+    } else {
+        fd::AccessType::Read
+    };
+
+    let mut scheduler = scheduler::SCHEDULER.lock();
     let scheduler = scheduler.as_mut().unwrap();
-    scheduler.set_fd(scheduler.current_pid(), fd, None);
+    let pid = scheduler.current_pid();
+    // Check for a free descriptor first, since the other end may pair with ours as soon as it's
+    // opened.
+    if scheduler.free_fds(pid) < 1 {
+        return Err(OpenFifoError::TooManyFiles);
+    }
+    let file = fd::FIFOS
+        .lock()
+        .get_mut(name)
+        .ok_or(OpenFifoError::NotFound)?
+        .open(access_type)
+        .ok_or(OpenFifoError::Busy)?;
+
+    scheduler
+        .new_fd(pid, file)
+        .map_err(|NewFdError::TooManyFiles| OpenFifoError::TooManyFiles)
+}
+
+fn unlink_fifo(name: &str) -> Result<(), UnlinkFifoError> {
+    fd::FIFOS
+        .lock()
+        .remove(name)
+        .map(|_| ())
+        .ok_or(UnlinkFifoError::NotFound)
 }
 
-fn pipe() -> (Fd, Fd) {
-    let mut scheduler = scheduler::SCHEDULER.take().unwrap();
+fn tee(_frame: &mut interrupt::InterruptFrame, fds: &[Fd]) -> Result<Fd, TeeError> {
+    let mut scheduler = scheduler::SCHEDULER.lock();
     let scheduler = scheduler.as_mut().unwrap();
-    let (read, write) = fd::pipe();
     let pid = scheduler.current_pid();
-    (
-        scheduler.new_fd(pid, Rc::new(RefCell::new(read))),
-        scheduler.new_fd(pid, Rc::new(RefCell::new(write))),
-    )
+
+    let targets = fds
+        .iter()
+        .map(|&fd| scheduler.get_fd(pid, fd).cloned().ok_or(TeeError::BadFd))
+        .collect::<Result<_, _>>()?;
+    scheduler
+        .new_fd(pid, Rc::new(RefCell::new(fd::Tee::new(targets))))
+        .map_err(|NewFdError::TooManyFiles| TeeError::TooManyFiles)
 }
 
-fn fork(frame: &mut interrupt::InterruptFrame) -> Pid {
+fn fork(frame: &mut interrupt::InterruptFrame) -> Result<Pid, ForkError> {
     // Write PID 0 into the result buffer, so it'll get returned to the child
-    unsafe { *(frame.ecx as *mut Pid) = 0 };
+    unsafe { *(frame.ecx as *mut Result<Pid, ForkError>) = Ok(0) };
 
-    let mut scheduler = scheduler::SCHEDULER.take().unwrap();
+    let mut scheduler = scheduler::SCHEDULER.lock();
     let scheduler = scheduler.as_mut().unwrap();
     scheduler.fork(frame)
 }
 
-fn exec(frame: &mut interrupt::InterruptFrame, arg: *const u32, result: *mut ExecError) {
-    fn _exec(frame: &mut interrupt::InterruptFrame, process: u32) -> Result<(), ExecError> {
-        let elf = process::elfloader::ELVES
+fn spawn_thread(
+    frame: &mut interrupt::InterruptFrame,
+    arg: SpawnThreadArg,
+) -> Result<Pid, SpawnThreadError> {
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    let scheduler = scheduler.as_mut().unwrap();
+    scheduler.spawn_thread(frame, arg.entry, arg.arg, arg.stack_size)
+}
+
+/// Returns the physical address of a futex, so that processes sharing memory agree on its identity.
+fn futex_key(futex: &AtomicU32) -> usize {
+    let vaddr = futex as *const AtomicU32 as usize;
+    let mut mmu = mmu::MMU.lock();
+    let mmu = mmu.deref_mut();
+
+    // Resolve copy-on-write now, so the futex doesn't move to a new page when it's next written.
+    mmu.mapper.cow_if_needed(&mut mmu.allocator, vaddr);
+    mmu.mapper.get_mapping(vaddr).unwrap().physaddr() as usize + vaddr % mmu::PAGE_SIZE
+}
+
+fn futex_wait(frame: &mut interrupt::InterruptFrame, arg: *const FutexWaitArg, _result: *mut ()) {
+    let arg = unsafe { arg.read() };
+    let key = futex_key(arg.futex);
+    if arg.futex.load(Ordering::SeqCst) != arg.expected {
+        return;
+    }
+
+    let continuation = {
+        let mut scheduler = scheduler::SCHEDULER.lock();
+        let scheduler = scheduler.as_mut().unwrap();
+        scheduler.futex_wait(key);
+        scheduler.schedule(frame)
+    };
+    continuation(frame);
+}
+
+fn futex_wake(_frame: &mut interrupt::InterruptFrame, arg: (&AtomicU32, usize)) -> usize {
+    let (futex, count) = arg;
+    let key = futex_key(futex);
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    scheduler.as_mut().unwrap().futex_wake(key, count)
+}
+
+fn signal(arg: SignalArg) {
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    let scheduler = scheduler.as_mut().unwrap();
+    scheduler.set_signal_handler(if arg.handler == 0 {
+        None
+    } else {
+        Some((arg.entry, arg.handler))
+    });
+}
+
+fn kill(
+    frame: &mut interrupt::InterruptFrame,
+    arg: *const (Pid, Signal),
+    result: *mut Result<(), KillError>,
+) {
+    let (pid, signal) = unsafe { arg.read() };
+    send_signal(frame, result, |scheduler, frame| {
+        scheduler.kill(pid, signal, frame)
+    })
+}
+
+fn kill_group(
+    frame: &mut interrupt::InterruptFrame,
+    arg: *const (Pid, Signal),
+    result: *mut Result<(), KillError>,
+) {
+    let (pgid, signal) = unsafe { arg.read() };
+    send_signal(frame, result, |scheduler, frame| {
+        scheduler.kill_group(pgid, signal, frame)
+    })
+}
+
+/// Sends a signal using `send`, which may kill the current process.
+fn send_signal(
+    frame: &mut interrupt::InterruptFrame,
+    result: *mut Result<(), KillError>,
+    send: impl FnOnce(
+        &mut scheduler::Scheduler,
+        &mut interrupt::InterruptFrame,
+    ) -> Result<fn(&mut interrupt::InterruptFrame), KillError>,
+) {
+    // Write the result first, since the signal may kill the current process.
+    unsafe { result.write(Ok(())) };
+    let continuation = {
+        let mut scheduler = scheduler::SCHEDULER.lock();
+        let scheduler = scheduler.as_mut().unwrap();
+        match send(scheduler, frame) {
+            Ok(continuation) => continuation,
+            Err(e) => {
+                unsafe { result.write(Err(e)) };
+                return;
+            }
+        }
+    };
+    continuation(frame);
+}
+
+fn set_pgid(arg: (Pid, Pid)) -> Result<(), KillError> {
+    let (pid, pgid) = arg;
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    scheduler.as_mut().unwrap().set_pgid(pid, pgid)
+}
+
+fn get_pgid() -> Pid {
+    let scheduler = scheduler::SCHEDULER.lock();
+    scheduler.as_ref().unwrap().pgid()
+}
+
+fn set_foreground(pgid: Pid) {
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    scheduler.as_mut().unwrap().set_foreground_pgid(pgid);
+}
+
+fn sigreturn(frame: &mut interrupt::InterruptFrame) {
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    scheduler.as_mut().unwrap().sigreturn(frame);
+}
+
+fn alarm(secs: u32) {
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    scheduler.as_mut().unwrap().set_alarm(secs);
+}
+
+fn exec(frame: &mut interrupt::InterruptFrame, arg: *const ExecArg<u32>, result: *mut ExecError) {
+    let arg = unsafe { arg.read() };
+    let elf = process::elfloader::ELVES.get().get(arg.program as usize);
+    exec_elf(frame, elf, arg.env, result)
+}
+
+fn exec_path(
+    frame: &mut interrupt::InterruptFrame,
+    arg: *const ExecArg<&str>,
+    result: *mut ExecError,
+) {
+    // Look up the name now, since it lives in the address space we're about to replace.
+    let arg = unsafe { arg.read() };
+    let elf = process::elfloader::ELVES
+        .get()
+        .iter()
+        .find(|elf| elf.name == arg.program);
+    exec_elf(frame, elf, arg.env, result)
+}
+
+fn list_programs(buf: &mut [u8]) -> usize {
+    let mut len = 0;
+    for elf in process::elfloader::ELVES.get() {
+        for &c in elf.name.as_bytes().iter().chain(b"\n") {
+            if let Some(dst) = buf.get_mut(len) {
+                *dst = c;
+            }
+            len += 1;
+        }
+    }
+    len
+}
+
+fn chdir(path: &str) -> Result<(), ChdirError> {
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    let scheduler = scheduler.as_mut().unwrap();
+    let path = crate::util::path::resolve(scheduler.cwd(), path);
+
+    // There is no directory filesystem yet, so the only directory is the root, which holds the
+    // programs.
+    if path != "/" {
+        let is_program = process::elfloader::ELVES
             .get()
-            .get(process as usize)
-            .ok_or(ExecError::BadProcess)?;
-        *frame = elf.load().map_err(|_| ExecError::IoError)?;
+            .iter()
+            .any(|elf| elf.name == path[1..]);
+        return Err(if is_program {
+            ChdirError::NotADirectory
+        } else {
+            ChdirError::NotFound
+        });
+    }
+
+    scheduler.set_cwd(path);
+    Ok(())
+}
+
+fn getcwd(buf: &mut [u8]) -> usize {
+    let scheduler = scheduler::SCHEDULER.lock();
+    let cwd = scheduler.as_ref().unwrap().cwd().as_bytes();
+    let len = core::cmp::min(buf.len(), cwd.len());
+    buf[..len].copy_from_slice(&cwd[..len]);
+    cwd.len()
+}
+
+fn exec_elf(
+    frame: &mut interrupt::InterruptFrame,
+    elf: Option<&process::elfloader::Elf32>,
+    env: &[&str],
+    result: *mut ExecError,
+) {
+    fn _exec(
+        frame: &mut interrupt::InterruptFrame,
+        elf: Option<&process::elfloader::Elf32>,
+        env: &[&str],
+    ) -> Result<(), ExecError> {
+        let elf = elf.ok_or(ExecError::BadProcess)?;
+        let env_size = env.iter().fold(core::mem::size_of_val(env), |size, var| {
+            size.saturating_add(var.len())
+        });
+        if env_size > MAX_ENV_SIZE {
+            return Err(ExecError::EnvTooLarge);
+        }
+
+        // Copy the environment out of the address space we're about to replace.
+        let env: Vec<String> = env.iter().map(|&var| String::from(var)).collect();
+        let (mut trap_frame, brk) = elf.load().map_err(|_| ExecError::IoError)?;
+        let environ = push_env(&mut trap_frame, &env);
+        *frame = trap_frame;
+
+        // The old program's signal handler no longer exists.
+        let mut scheduler = scheduler::SCHEDULER.lock();
+        let scheduler = scheduler.as_mut().unwrap();
+        scheduler.reset_brk(brk);
+        scheduler.reset_fpu();
+        scheduler.set_signal_handler(None);
+        scheduler.set_environ(environ);
         Ok(())
     }
 
     unsafe {
-        if let Err(e) = _exec(frame, *arg) {
+        if let Err(e) = _exec(frame, elf, env) {
             result.write(e);
         }
     }
 }
 
-fn wait(_frame: &mut interrupt::InterruptFrame, pid: Pid) -> Blocking<()> {
-    let scheduler = scheduler::SCHEDULER.take().unwrap();
-    if scheduler.as_ref().unwrap().process_exists(pid) {
+/// Copies the environment onto a new process's stack, just below `frame.user_esp`, and moves the
+/// stack pointer below it. Returns the address and length of the array of strings.
+fn push_env(frame: &mut interrupt::InterruptFrame, env: &[String]) -> (usize, usize) {
+    let mut sp = frame.user_esp;
+    let mut vars = Vec::with_capacity(env.len());
+    for var in env {
+        sp -= var.len();
+        unsafe {
+            core::ptr::copy_nonoverlapping(var.as_ptr(), sp as *mut u8, var.len());
+            vars.push(core::str::from_utf8_unchecked(core::slice::from_raw_parts(
+                sp as *const u8,
+                var.len(),
+            )));
+        }
+    }
+
+    sp = (sp & !(core::mem::align_of::<&str>() - 1)) - core::mem::size_of_val(&vars[..]);
+    unsafe {
+        core::ptr::copy_nonoverlapping(vars.as_ptr(), sp as *mut &str, vars.len());
+    }
+    frame.user_esp = sp & !0xF;
+    (sp, vars.len())
+}
+
+fn environ() -> (usize, usize) {
+    let scheduler = scheduler::SCHEDULER.lock();
+    scheduler.as_ref().unwrap().environ()
+}
+
+fn sched_stats(pid: Pid) -> Result<SchedStats, SchedStatsError> {
+    let scheduler = scheduler::SCHEDULER.lock();
+    scheduler
+        .as_ref()
+        .unwrap()
+        .stats(pid)
+        .ok_or(SchedStatsError::NoSuchProcess)
+}
+
+fn get_regs(pid: Pid) -> Result<Regs, GetRegsError> {
+    let scheduler = scheduler::SCHEDULER.lock();
+    let frame = scheduler.as_ref().unwrap().child_registers(pid)?;
+    Ok(Regs {
+        eax: frame.eax as u32,
+        ebx: frame.ebx as u32,
+        ecx: frame.ecx as u32,
+        edx: frame.edx as u32,
+        esi: frame.esi as u32,
+        edi: frame.edi as u32,
+        ebp: frame.ebp as u32,
+        esp: frame.user_esp as u32,
+        eip: frame.eip as u32,
+        eflags: frame.eflags as u32,
+        cs: frame.cs as u32,
+        ss: frame.user_ss as u32,
+        ds: frame.ds as u32,
+        es: frame.es as u32,
+        fs: frame.fs as u32,
+        gs: frame.gs as u32,
+    })
+}
+
+fn debug_resume(arg: (Pid, bool)) -> Result<(), DebugError> {
+    let (pid, step) = arg;
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    scheduler.as_mut().unwrap().debug_resume(pid, step)
+}
+
+fn debug_wait(
+    _frame: &mut interrupt::InterruptFrame,
+    pid: Pid,
+) -> Blocking<Result<DebugEvent, DebugError>> {
+    let scheduler = scheduler::SCHEDULER.lock();
+    match scheduler.as_ref().unwrap().debug_event(pid) {
+        Ok(None) => block(scheduler::BlockReason::Debuggee(pid)),
+        Ok(Some(event)) => Ok(Ok(event)),
+        Err(e) => Ok(Err(e)),
+    }
+}
+
+fn set_breakpoint(arg: SetBreakpointArg) -> Result<(), SetBreakpointError> {
+    let index = arg.index as usize;
+    let len = arg.len as usize;
+    let condition = match arg.kind {
+        BreakpointKind::Execute => debug_regs::Condition::Execute,
+        BreakpointKind::Write => debug_regs::Condition::Write,
+        BreakpointKind::ReadWrite => debug_regs::Condition::ReadWrite,
+    };
+    // Breakpoints may only watch userspace.
+    let valid = matches!(len, 1 | 2 | 4)
+        && (condition != debug_regs::Condition::Execute || len == 1)
+        && arg.addr & (len - 1) == 0
+        && arg.addr < mmu::KERNEL_RELOC_BASE - len;
+    if index >= debug_regs::DebugRegisters::COUNT || (arg.addr != 0 && !valid) {
+        return Err(SetBreakpointError::InvalidBreakpoint);
+    }
+
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    let regs = scheduler
+        .as_mut()
+        .unwrap()
+        .debug_registers(arg.pid)
+        .map_err(|e| match e {
+            DebugError::NoSuchProcess => SetBreakpointError::NoSuchProcess,
+            DebugError::NotAChild => SetBreakpointError::NotAChild,
+        })?;
+    if arg.addr == 0 {
+        regs.clear(index);
+    } else {
+        regs.set(index, arg.addr, condition, len);
+    }
+    Ok(())
+}
+
+fn sbrk(increment: isize) -> Option<*mut u8> {
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    let scheduler = scheduler.as_mut().unwrap();
+    scheduler.sbrk(increment).map(|brk| brk as *mut u8)
+}
+
+fn wait(frame: &mut interrupt::InterruptFrame, pid: Pid) -> Blocking<()> {
+    // The scheduler guard is dropped when this returns, before match_syscall_blocking takes the
+    // scheduler again to block the process.
+    let mut guard = scheduler::SCHEDULER.lock();
+    let scheduler = guard.as_mut().unwrap();
+    if pid == scheduler.current_pid() {
+        // A process waiting for itself would block forever.
+        kwarn!(
+            "terminating process {}, which tried to wait for itself",
+            pid
+        );
+        let continuation = scheduler.kill_current_process(frame, ExitStatus::Faulted);
+        drop(guard);
+        continuation(frame);
+        Ok(())
+    } else if scheduler.process_exists(pid) {
         block(scheduler::BlockReason::Process(pid))
     } else {
+        scheduler.reap(pid);
         Ok(())
     }
 }
 
-fn dup2(_frame: &mut interrupt::InterruptFrame, arg: (Fd, Fd)) {
+fn try_wait(pid: Pid) -> Result<Option<ExitStatus>, WaitError> {
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    let scheduler = scheduler.as_mut().unwrap();
+    if scheduler.process_exists(pid) {
+        Ok(None)
+    } else {
+        scheduler
+            .reap(pid)
+            .map(Some)
+            .ok_or(WaitError::NoSuchProcess)
+    }
+}
+
+fn dup2(_frame: &mut interrupt::InterruptFrame, arg: (Fd, Fd)) -> Result<Fd, Dup2Error> {
     let (src, dst) = arg;
-    let mut scheduler = scheduler::SCHEDULER.take().unwrap();
+    let mut scheduler = scheduler::SCHEDULER.lock();
     let scheduler = scheduler.as_mut().unwrap();
     let pid = scheduler.current_pid();
 
-    let file = scheduler.get_fd(pid, src).map(Clone::clone);
-    scheduler.set_fd(pid, dst, file);
+    let file = scheduler.get_fd(pid, src).ok_or(Dup2Error::BadFd)?.clone();
+    if dst >= MAX_FDS {
+        return Err(Dup2Error::BadFd);
+    }
+    if src != dst {
+        scheduler.set_fd(pid, dst, Some(file));
+    }
+    Ok(dst)
 }
 
-fn null_fd() -> Fd {
-    let mut scheduler = scheduler::SCHEDULER.take().unwrap();
+fn null_fd() -> Result<Fd, NewFdError> {
+    let mut scheduler = scheduler::SCHEDULER.lock();
     let scheduler = scheduler.as_mut().unwrap();
     let pid = scheduler.current_pid();
     scheduler.new_fd(pid, Rc::new(RefCell::new(fd::Null)))
 }
 
+fn open_raw_keyboard() -> Result<Fd, NewFdError> {
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    let scheduler = scheduler.as_mut().unwrap();
+    let pid = scheduler.current_pid();
+    scheduler.new_fd(pid, Rc::new(RefCell::new(fd::RawKeyboard::open())))
+}
+
+fn open_mouse() -> Result<Fd, NewFdError> {
+    let mut scheduler = scheduler::SCHEDULER.lock();
+    let scheduler = scheduler.as_mut().unwrap();
+    let pid = scheduler.current_pid();
+    scheduler.new_fd(pid, Rc::new(RefCell::new(fd::Mouse::open())))
+}
+
+fn set_video_mode(mode: VideoMode) -> Result<*mut u8, SetVideoModeError> {
+    const PAGES: usize = x86::io::vga::FRAMEBUFFER_SIZE.div_ceil(mmu::PAGE_SIZE);
+    let mut mmu = mmu::MMU.lock();
+    let mmu = mmu.deref_mut();
+    let mut vga = x86::io::vga::VGA.lock();
+    let cr3 = mmu.mapper.cr3();
+
+    match mode {
+        VideoMode::Text => match vga.leave_graphics(cr3) {
+            Some(vaddr) => {
+                for page in (vaddr..).step_by(mmu::PAGE_SIZE).take(PAGES) {
+                    mmu.mapper.unmap(&mut mmu.allocator, page);
+                }
+                Ok(core::ptr::null_mut())
+            }
+            None if vga.in_use() => Err(SetVideoModeError::Busy),
+            None => Ok(core::ptr::null_mut()),
+        },
+        VideoMode::Graphics => {
+            if let Some(vaddr) = vga.framebuffer(cr3) {
+                return Ok(vaddr as *mut u8);
+            } else if vga.in_use() {
+                return Err(SetVideoModeError::Busy);
+            }
+
+            let vaddr = mmu
+                .mapper
+                .find_unused_userspace(PAGES)
+                .ok_or(SetVideoModeError::OutOfMemory)?;
+            for i in 0..PAGES {
+                mmu.mapper.map(
+                    &mut mmu.allocator,
+                    x86::io::vga::FRAMEBUFFER_PADDR + i * mmu::PAGE_SIZE,
+                    vaddr + i * mmu::PAGE_SIZE,
+                    mmu::mmap::MappingFlags::new()
+                        .with_writable(true)
+                        .with_user_accessible(true)
+                        .with_cache_disabled(true),
+                );
+            }
+            vga.enter_graphics(cr3, vaddr);
+            Ok(vaddr as *mut u8)
+        }
+    }
+}
+
+fn fd_pending(_frame: &mut interrupt::InterruptFrame, fd: Fd) -> Result<usize, FdPendingError> {
+    let scheduler = scheduler::SCHEDULER.lock();
+    let scheduler = scheduler.as_ref().unwrap();
+
+    scheduler
+        .get_fd(scheduler.current_pid(), fd)
+        .map(|fd| fd.borrow().available())
+        .ok_or(FdPendingError::BadFd)
+}
+
+fn ioctl(arg: IoctlArg) -> Result<(), IoctlError> {
+    let scheduler = scheduler::SCHEDULER.lock();
+    let scheduler = scheduler.as_ref().unwrap();
+
+    let fd = scheduler
+        .get_fd(scheduler.current_pid(), arg.fd)
+        .ok_or(IoctlError::BadFd)?;
+    let result = fd.borrow_mut().ioctl(arg.request, arg.arg);
+    result
+}
+
+fn time() -> Result<u64, TimeError> {
+    let time = x86::io::rtc::RTC.lock().read();
+    time.map(|t| t.unix_timestamp())
+        .ok_or(TimeError::InvalidClock)
+}
+
 /// Defines a type that can be safely passed between kernelspace and userspace.
 trait Arg {
     /// Verifies that the pointer points to a valid instance of the type.
@@ -179,17 +944,30 @@ impl Arg for () {
 }
 
 /// Validates that an address range points to valid, userspace-accessible memory.
-/// If is_write is true, the memory must also be writable.
+/// If is_write is true, the memory must also be writable, and any copy-on-write pages in the range
+/// are copied immediately.
 fn validate_range(start: usize, len: usize, is_write: bool) -> bool {
-    let mmu = mmu::MMU.take().unwrap();
-    let mmu = mmu.deref();
+    let mut mmu = mmu::MMU.lock();
+    let mmu = mmu.deref_mut();
 
-    mmu.mapper.validate_range(
+    let valid = mmu.mapper.validate_range(
         &mmu.allocator,
         start,
         len,
-        mmu::mmap::MappingFlags::new().with_writable(is_write),
-    )
+        mmu::mmap::MappingFlags::new()
+            .with_writable(is_write)
+            .with_user_accessible(true),
+    );
+
+    // The page fault handler would copy the pages when the syscall writes to them, but it can't do
+    // that if the syscall is holding the MMU lock. Copying now means the buffer can be written
+    // safely at any point.
+    if valid && is_write && len != 0 {
+        for page in (mmu::page_align_down(start)..(start + len)).step_by(mmu::PAGE_SIZE) {
+            mmu.mapper.cow_if_needed(&mut mmu.allocator, page);
+        }
+    }
+    valid
 }
 // Validates that a pointer points to valid, userspace-accssible memory.
 /// If is_write is true, the memory must also be writable.
@@ -251,6 +1029,24 @@ impl Arg for usize {
         true
     }
 }
+impl Arg for isize {
+    unsafe fn validate(_arg: *const Self) -> bool {
+        // Every isize is valid
+        true
+    }
+}
+impl Arg for bool {
+    unsafe fn validate(arg: *const Self) -> bool {
+        // A bool must be either 0 or 1
+        *arg.cast::<u8>() <= 1
+    }
+}
+impl<'a, 'b, T: Arg> Arg for ExecArg<'a, 'b, T> {
+    unsafe fn validate(arg: *const Self) -> bool {
+        T::validate(core::ptr::addr_of!((*arg).program))
+            && <&[&str]>::validate(core::ptr::addr_of!((*arg).env))
+    }
+}
 impl Arg for &str {
     unsafe fn validate(arg: *const Self) -> bool {
         // A string slice is valid if the byte slice points to valid memory
@@ -259,6 +1055,55 @@ impl Arg for &str {
         <&[u8]>::validate(byte_slice) && core::str::from_utf8(*byte_slice).is_ok()
     }
 }
+impl Arg for AtomicU32 {
+    unsafe fn validate(_arg: *const Self) -> bool {
+        // Every u32 is valid
+        true
+    }
+}
+
+impl<'a> Arg for FutexWaitArg<'a> {
+    unsafe fn validate(arg: *const Self) -> bool {
+        <&AtomicU32>::validate(core::ptr::addr_of!((*arg).futex))
+    }
+}
+impl Arg for LogLevel {
+    unsafe fn validate(arg: *const Self) -> bool {
+        // A log level must be a known level
+        *arg.cast::<u8>() <= LogLevel::Debug as u8
+    }
+}
+impl Arg for VideoMode {
+    unsafe fn validate(arg: *const Self) -> bool {
+        // A video mode must be a known mode
+        *arg.cast::<u8>() <= VideoMode::Graphics as u8
+    }
+}
+impl Arg for Signal {
+    unsafe fn validate(arg: *const Self) -> bool {
+        // A signal must be a known signal number
+        *arg.cast::<u32>() <= Signal::Alarm as u32
+    }
+}
+
+impl Arg for SetBreakpointArg {
+    unsafe fn validate(arg: *const Self) -> bool {
+        // The kind must be a known kind
+        *core::ptr::addr_of!((*arg).kind).cast::<u8>() <= BreakpointKind::ReadWrite as u8
+    }
+}
+impl Arg for SignalArg {
+    unsafe fn validate(_arg: *const Self) -> bool {
+        // The entry point is only ever jumped to in userspace, so any value is valid
+        true
+    }
+}
+impl Arg for SpawnThreadArg {
+    unsafe fn validate(_arg: *const Self) -> bool {
+        // The entry point is only ever jumped to in userspace, so any value is valid
+        true
+    }
+}
 impl<'a> Arg for ReadArg<'a> {
     unsafe fn validate(arg: *const Self) -> bool {
         <&mut [u8]>::validate(core::ptr::addr_of!((*arg).buf))
@@ -269,8 +1114,48 @@ impl<'a> Arg for WriteArg<'a> {
         <&[u8]>::validate(core::ptr::addr_of!((*arg).buf))
     }
 }
+impl<'a, 'b> Arg for ReadvArg<'a, 'b> {
+    unsafe fn validate(arg: *const Self) -> bool {
+        <&mut [IoVecMut]>::validate(core::ptr::addr_of!((*arg).bufs))
+    }
+}
+impl<'a> Arg for WritevArg<'a> {
+    unsafe fn validate(arg: *const Self) -> bool {
+        <&[IoVec]>::validate(core::ptr::addr_of!((*arg).bufs))
+    }
+}
+impl<'a> Arg for IoctlArg<'a> {
+    unsafe fn validate(arg: *const Self) -> bool {
+        <&mut [u8]>::validate(core::ptr::addr_of!((*arg).arg))
+    }
+}
+impl<'a> Arg for ReadAllArg<'a> {
+    unsafe fn validate(arg: *const Self) -> bool {
+        <&mut [u8]>::validate(core::ptr::addr_of!((*arg).buf))
+            && <&mut usize>::validate(core::ptr::addr_of!((*arg).progress))
+    }
+}
+impl<'a> Arg for WriteAllArg<'a> {
+    unsafe fn validate(arg: *const Self) -> bool {
+        <&[u8]>::validate(core::ptr::addr_of!((*arg).buf))
+            && <&mut usize>::validate(core::ptr::addr_of!((*arg).progress))
+    }
+}
 
 type Blocking<T> = Result<T, scheduler::BlockReason>;
+
+/// How a blocking syscall may be safely re-invoked after it blocks.
+#[derive(PartialEq, Eq)]
+enum Restart {
+    /// The syscall performs no side effects on any path that blocks, so it can simply be run
+    /// again from the beginning.
+    Restartable,
+
+    /// The syscall may perform side effects before blocking, but records its progress in
+    /// userspace so that running it again continues where it left off.
+    Resumable,
+}
+
 fn block<T>(reason: scheduler::BlockReason) -> Blocking<T> {
     Err(reason)
 }
@@ -283,7 +1168,7 @@ fn match_syscall<A: Arg, T, F: FnOnce(&mut interrupt::InterruptFrame, A) -> T>(
     func: F,
 ) -> bool {
     // a nonblocking syscall is just a blocking syscall that doesn't block
-    match_syscall_blocking(frame, id, |f, a| Ok(func(f, a)))
+    match_syscall_blocking(frame, id, Restart::Restartable, |f, a| Ok(func(f, a)))
 }
 
 /// If the syscall ID passed by the user process in `frame` matches `id`, decodes and validates the
@@ -291,6 +1176,10 @@ fn match_syscall<A: Arg, T, F: FnOnce(&mut interrupt::InterruptFrame, A) -> T>(
 /// The syscall handler may return an error to indicate that the operation is blocked on a file
 /// descriptor. If this happens, the process will not be scheduled again until the file descriptor
 /// is ready, at which point the syscall will be re-invoked.
+///
+/// Since the syscall is re-invoked from the beginning, anything it did before blocking would be
+/// done twice. `restart` declares how the handler avoids this; in debug builds, a
+/// `Restart::Restartable` handler that reads or writes a file and then blocks panics.
 fn match_syscall_blocking<
     A: Arg,
     T,
@@ -298,19 +1187,33 @@ fn match_syscall_blocking<
 >(
     frame: &mut interrupt::InterruptFrame,
     id: SyscallId,
+    restart: Restart,
     func: F,
 ) -> bool {
     match_syscall_args(frame, id, |frame, arg_ptr: *const A, result_ptr: *mut T| {
         // Invoke the syscall with the argument
         unsafe {
+            #[cfg(debug_assertions)]
+            let side_effects = process::percpu::current().side_effects();
             let result = func(frame, arg_ptr.read());
             match result {
                 // Did it block?
                 Ok(r) => result_ptr.write(r), // No, store the result in memory and return.
                 Err(block) => {
-                    // Yes, schedule a new process.
+                    #[cfg(debug_assertions)]
+                    assert!(
+                        restart == Restart::Resumable
+                            || process::percpu::current().side_effects() == side_effects,
+                        "{} blocked after performing side effects",
+                        core::any::type_name::<F>()
+                    );
+                    #[cfg(not(debug_assertions))]
+                    let _ = restart;
+
+                    // Yes, schedule a new process. The handler has returned, so it no longer holds
+                    // the scheduler.
                     let continuation = {
-                        let mut scheduler = scheduler::SCHEDULER.take().unwrap();
+                        let mut scheduler = scheduler::SCHEDULER.lock();
                         let scheduler = scheduler.as_mut().unwrap();
                         scheduler.block(scheduler.current_pid(), block, syscall);
                         scheduler.schedule(frame)
@@ -337,14 +1240,14 @@ fn match_syscall_args<A: Arg, T, F: FnOnce(&mut interrupt::InterruptFrame, *cons
         let result_ptr = frame.ecx as *mut T;
         unsafe {
             // Ensure the argument is valid, and the result points to valid memory
-            assert!(A::validate(arg_ptr), "invalid syscall arg");
-            assert!(
-                validate_ptr(result_ptr, true),
-                "invalid syscall result buffer"
-            );
-
-            // Invoke the syscall with the arguments
-            func(frame, arg_ptr, result_ptr);
+            if !A::validate(arg_ptr) {
+                kill_caller(frame, "an invalid syscall argument");
+            } else if !validate_ptr(result_ptr, true) {
+                kill_caller(frame, "an invalid syscall result buffer");
+            } else {
+                // Invoke the syscall with the arguments
+                func(frame, arg_ptr, result_ptr);
+            }
         }
         true
     } else {
@@ -1,12 +1,13 @@
 use crate::syscall::*;
-use core::arch::asm;
+use core::{arch::asm, convert::TryInto, sync::atomic::AtomicU32};
 
 /// Terminates the current process.
 pub fn exit() -> ! {
     syscall(SyscallId::Exit, &())
 }
 
-/// Yields the CPU, transferring the current process's timeslice to another process.
+/// Yields the CPU, transferring the current process's timeslice to another process. Returns
+/// immediately if no other process is ready to run.
 pub fn yield_cpu() {
     syscall(SyscallId::YieldCpu, &())
 }
@@ -23,44 +24,389 @@ pub fn write(fd: Fd, buf: &[u8]) -> Result<usize, WriteError> {
     syscall(SyscallId::Write, &WriteArg { fd, buf })
 }
 
-/// Closes a file descriptor. If the file descriptor does not exist, this is a no-op.
-pub fn close(fd: Fd) {
+/// Reads from a file descriptor until `buf` is full or the end-of-file is reached, returning the
+/// number of bytes read. Blocks as needed.
+pub fn read_all(fd: Fd, buf: &mut [u8]) -> Result<usize, ReadError> {
+    let mut progress = 0;
+    syscall(
+        SyscallId::ReadAll,
+        &ReadAllArg {
+            fd,
+            buf,
+            progress: &mut progress,
+        },
+    )
+}
+
+/// Writes the entire buffer to a file descriptor, returning the number of bytes written. Blocks as
+/// needed; fails with `WriteError::BrokenPipe` if the file stops accepting data partway through.
+pub fn write_all(fd: Fd, buf: &[u8]) -> Result<usize, WriteError> {
+    let mut progress = 0;
+    syscall(
+        SyscallId::WriteAll,
+        &WriteAllArg {
+            fd,
+            buf,
+            progress: &mut progress,
+        },
+    )
+}
+
+/// Reads from a file descriptor into several buffers, filling each in turn. Returns the total
+/// number of bytes read. Blocks if no data is available; returns 0 if the end-of-file is reached.
+pub fn readv(fd: Fd, bufs: &mut [IoVecMut]) -> Result<usize, ReadError> {
+    syscall(SyscallId::Readv, &ReadvArg { fd, bufs })
+}
+
+/// Writes several buffers to a file descriptor in order. Returns the total number of bytes
+/// written. Blocks if no space is available.
+pub fn writev(fd: Fd, bufs: &[IoVec]) -> Result<usize, WriteError> {
+    syscall(SyscallId::Writev, &WritevArg { fd, bufs })
+}
+
+/// Closes a file descriptor, returning whether it was open. If the file descriptor does not exist,
+/// this is a no-op that returns false.
+pub fn close(fd: Fd) -> bool {
     syscall(SyscallId::Close, &fd)
 }
 
 /// Opens a pipe, returning a read half and a write half.
 /// Data written into the write half can be read out the read half.
-pub fn pipe() -> (Fd, Fd) {
+pub fn pipe() -> Result<(Fd, Fd), NewFdError> {
     syscall(SyscallId::Pipe, &())
 }
 
+/// Opens a pipe whose buffer holds `capacity` bytes. The capacity is clamped to a kernel-defined
+/// maximum.
+pub fn pipe_sized(capacity: usize) -> Result<(Fd, Fd), NewFdError> {
+    syscall(SyscallId::PipeSized, &capacity)
+}
+
+/// Opens a pair of connected bidirectional pipes.
+/// Data written into either file descriptor can be read out the other.
+pub fn socketpair() -> Result<(Fd, Fd), NewFdError> {
+    syscall(SyscallId::SocketPair, &())
+}
+
+/// Creates a file descriptor that duplicates everything written to it into each of `fds`.
+pub fn tee(fds: &[Fd]) -> Result<Fd, TeeError> {
+    syscall(SyscallId::Tee, &fds)
+}
+
+/// Creates a named pipe, which unrelated processes can connect to with `open_fifo`.
+pub fn mkfifo(name: &str) -> Result<(), MkfifoError> {
+    syscall(SyscallId::Mkfifo, &name)
+}
+
+/// Opens one end of a named pipe: the write end if `write` is true, or the read end otherwise.
+/// Each reader is connected to the next writer to open the FIFO (or vice versa), and the two
+/// ends then behave like a pipe created by `pipe`.
+pub fn open_fifo(name: &str, write: bool) -> Result<Fd, OpenFifoError> {
+    syscall(SyscallId::OpenFifo, &(name, write))
+}
+
+/// Removes a named pipe, so that the name can be reused by `mkfifo`. Pipes already connected
+/// through the FIFO are unaffected, but an end still waiting for the other end to be opened will
+/// never be connected.
+pub fn unlink_fifo(name: &str) -> Result<(), UnlinkFifoError> {
+    syscall(SyscallId::UnlinkFifo, &name)
+}
+
 /// Duplicates the current process, returning 0 to the child and the child's PID to the parent.
-pub fn fork() -> Pid {
+pub fn fork() -> Result<Pid, ForkError> {
     syscall(SyscallId::Fork, &())
 }
 
-/// Replaces the current process with a new executable.
+/// Creates a new thread sharing the current process's memory, which runs `entry` on a fresh stack
+/// of `stack_size` bytes and exits when `entry` returns. Returns the thread's PID, which can be
+/// passed to `wait`.
+pub fn spawn_thread(entry: fn(), stack_size: usize) -> Result<Pid, SpawnThreadError> {
+    extern "C" fn thread_start(entry: usize) -> ! {
+        let entry: fn() = unsafe { core::mem::transmute(entry) };
+        entry();
+        exit()
+    }
+
+    syscall(
+        SyscallId::SpawnThread,
+        &SpawnThreadArg {
+            entry: thread_start as *const () as usize,
+            arg: entry as *const () as usize,
+            stack_size,
+        },
+    )
+}
+
+/// Blocks until another process calls `futex_wake` on `futex`, unless `futex` no longer contains
+/// `expected`. May also return spuriously, so callers should re-check the condition they are
+/// waiting for.
+pub fn futex_wait(futex: &AtomicU32, expected: u32) {
+    syscall(SyscallId::FutexWait, &FutexWaitArg { futex, expected })
+}
+
+/// Wakes up to `count` processes blocked in `futex_wait` on `futex` (which may be shared with
+/// other processes), returning the number of processes woken.
+pub fn futex_wake(futex: &AtomicU32, count: usize) -> usize {
+    syscall(SyscallId::FutexWake, &(futex, count))
+}
+
+/// Registers a function to be called when the current process receives a signal, or restores the
+/// default action (terminating the process) if `handler` is None. `Signal::Kill` cannot be
+/// handled. The handler is reset by `exec`.
+pub fn signal(handler: Option<fn(Signal)>) {
+    extern "C" fn signal_entry(handler: usize, signal: Signal) -> ! {
+        let handler: fn(Signal) = unsafe { core::mem::transmute(handler) };
+        handler(signal);
+        syscall::<_, ()>(SyscallId::SigReturn, &());
+        unreachable!("sigreturn returned")
+    }
+
+    syscall(
+        SyscallId::Signal,
+        &SignalArg {
+            entry: signal_entry as *const () as usize,
+            handler: handler.map_or(0, |handler| handler as *const () as usize),
+        },
+    )
+}
+
+/// Sends a signal to a process.
+pub fn kill(pid: Pid, signal: Signal) -> Result<(), KillError> {
+    syscall(SyscallId::Kill, &(pid, signal))
+}
+
+/// Sends the current process `Signal::Alarm` after `secs` seconds, replacing any previously set
+/// alarm. If `secs` is 0, cancels the alarm instead. Unless a signal handler is registered, the
+/// alarm terminates the process.
+pub fn alarm(secs: u32) {
+    syscall(SyscallId::Alarm, &secs)
+}
+
+/// Moves process `pid` (or the current process, if 0) into process group `pgid`. If `pgid` is 0,
+/// the process starts a new group whose ID is its PID. Forked processes and threads inherit their
+/// parent's process group.
+pub fn setpgid(pid: Pid, pgid: Pid) -> Result<(), KillError> {
+    syscall(SyscallId::SetPgid, &(pid, pgid))
+}
+
+/// Returns the process group of the current process.
+pub fn getpgid() -> Pid {
+    syscall(SyscallId::GetPgid, &())
+}
+
+/// Gives ownership of the console to process group `pgid`.
+pub fn set_foreground(pgid: Pid) {
+    syscall(SyscallId::SetForeground, &pgid)
+}
+
+/// Sends a signal to every process in process group `pgid`.
+pub fn kill_group(pgid: Pid, signal: Signal) -> Result<(), KillError> {
+    syscall(SyscallId::KillGroup, &(pgid, signal))
+}
+
+/// Replaces the current process with a new executable, which inherits the current environment.
 #[must_use]
 pub fn exec(process: u32) -> ExecError {
-    syscall(SyscallId::Exec, &process)
+    exec_env(process, crate::env::vars())
+}
+
+/// Replaces the current process with the executable of the given name, which inherits the current
+/// environment.
+#[must_use]
+pub fn exec_path(name: &str) -> ExecError {
+    exec_path_env(name, crate::env::vars())
+}
+
+/// Replaces the current process with a new executable, giving it the environment `env` of
+/// `KEY=VALUE` strings.
+#[must_use]
+pub fn exec_env(process: u32, env: &[&str]) -> ExecError {
+    syscall(
+        SyscallId::Exec,
+        &ExecArg {
+            program: process,
+            env,
+        },
+    )
 }
 
-/// Blocks until the specified process terminates.
+/// Replaces the current process with the executable of the given name, giving it the environment
+/// `env` of `KEY=VALUE` strings.
+#[must_use]
+pub fn exec_path_env(name: &str, env: &[&str]) -> ExecError {
+    syscall(SyscallId::ExecPath, &ExecArg { program: name, env })
+}
+
+/// Returns the address and length of the environment array that 'exec' placed on the stack.
+/// Programs should use `env::vars` instead.
+pub fn environ() -> (usize, usize) {
+    syscall(SyscallId::Environ, &())
+}
+
+/// Returns the scheduler's counters, with `process_switches` counted for `process` (or the current
+/// process, if 0).
+pub fn sched_stats(process: Pid) -> Result<SchedStats, SchedStatsError> {
+    syscall(SyscallId::SchedStats, &process)
+}
+
+/// Enables or disables timer preemption for the whole system. With preemption disabled, the
+/// scheduler only switches processes when the running process yields, blocks, or exits, so a
+/// process that does neither keeps the CPU forever. Alarms are also only delivered at those points.
+pub fn set_preemption(enabled: bool) {
+    syscall(SyscallId::SetPreemption, &enabled)
+}
+
+/// Returns the saved registers of a child process: where it was last interrupted if it is still
+/// running, or where it faulted if it was killed by a fault and has not yet been reaped.
+pub fn get_regs(process: Pid) -> Result<Regs, GetRegsError> {
+    syscall(SyscallId::GetRegs, &process)
+}
+
+/// Resumes a child process stopped by the debugger. If `step` is true, the child executes a single
+/// instruction and stops again; use `debug_wait` to wait for it. A child that is still running
+/// will stop after its next instruction.
+pub fn debug_resume(process: Pid, step: bool) -> Result<(), DebugError> {
+    syscall(SyscallId::DebugResume, &(process, step))
+}
+
+/// Blocks until a child being single-stepped stops, or terminates.
+pub fn debug_wait(process: Pid) -> Result<DebugEvent, DebugError> {
+    syscall(SyscallId::DebugWait, &process)
+}
+
+/// Sets or clears one of a child process's hardware breakpoints. When the child hits it, the child
+/// stops and `debug_wait` reports `DebugEvent::Breakpoint`.
+pub fn set_breakpoint(arg: &SetBreakpointArg) -> Result<(), SetBreakpointError> {
+    syscall(SyscallId::SetBreakpoint, arg)
+}
+
+/// Changes the working directory. Relative paths are resolved against the current working
+/// directory. The working directory is inherited by child processes and kept across 'exec'.
+pub fn chdir(path: &str) -> Result<(), ChdirError> {
+    syscall(SyscallId::Chdir, &path)
+}
+
+/// Writes the absolute path of the working directory into `buf`, and returns the length of the
+/// complete path. If the path is longer than `buf`, it is truncated and the caller may retry with
+/// a larger buffer.
+pub fn getcwd(buf: &mut [u8]) -> usize {
+    syscall(SyscallId::Getcwd, &buf)
+}
+
+/// Writes the names of the available programs into `buf`, one per line in order of their index,
+/// and returns the length of the complete list. If the list is longer than `buf`, it is truncated
+/// and the caller may retry with a larger buffer.
+pub fn list_programs(buf: &mut [u8]) -> usize {
+    syscall(SyscallId::ListPrograms, &buf)
+}
+
+/// Blocks until the specified process terminates. A process that waits for itself would never
+/// wake up, so the kernel terminates it instead.
 pub fn wait(process: Pid) {
     syscall(SyscallId::Wait, &process)
 }
 
-/// Duplicates a file descriptor.
-pub fn dup2(src: Fd, dst: Fd) {
+/// Checks whether the specified process has terminated without blocking. Returns None if the
+/// process is still running, or its exit status otherwise. The exit status can only be collected
+/// once, by either `wait` or `try_wait`. It is discarded when the process's parent exits, and is
+/// not kept at all for threads.
+pub fn try_wait(process: Pid) -> Result<Option<ExitStatus>, WaitError> {
+    syscall(SyscallId::TryWait, &process)
+}
+
+/// Makes `dst` refer to the same file as `src`, closing whatever `dst` referred to before, and
+/// returns `dst`. If `src` does not exist or `dst` is not below `MAX_FDS`, `dst` is left unchanged; if `src` and `dst` are the
+/// same, this does nothing.
+pub fn dup2(src: Fd, dst: Fd) -> Result<Fd, Dup2Error> {
     syscall(SyscallId::Dup2, &(src, dst))
 }
 
 /// Creates and returns a null file descriptor.
 /// The file descriptor will discard any data written to it and return EOF on reads.
-pub fn null_fd() -> Fd {
+pub fn null_fd() -> Result<Fd, NewFdError> {
     syscall(SyscallId::NullFd, &())
 }
 
+/// Opens the raw keyboard device, which reads the keyboard's make and break scancodes directly.
+/// Console input is still translated and delivered as usual.
+pub fn open_raw_keyboard() -> Result<Fd, NewFdError> {
+    syscall(SyscallId::OpenRawKeyboard, &())
+}
+
+/// Opens the mouse device. Use `read_mouse` to read events from it.
+pub fn open_mouse() -> Result<Fd, NewFdError> {
+    syscall(SyscallId::OpenMouse, &())
+}
+
+/// Reads events from a mouse device, returning the number of events read. Blocks until at least
+/// one event is available.
+pub fn read_mouse(fd: Fd, events: &mut [MouseEvent]) -> Result<usize, ReadError> {
+    const MAX_EVENTS: usize = 16;
+    let mut buf = [0u8; MAX_EVENTS * MouseEvent::SIZE];
+    let count = core::cmp::min(events.len(), MAX_EVENTS);
+    let len = read(fd, &mut buf[..count * MouseEvent::SIZE])?;
+    for (event, bytes) in events
+        .iter_mut()
+        .zip(buf[..len].chunks_exact(MouseEvent::SIZE))
+    {
+        *event = MouseEvent::from_bytes(bytes.try_into().unwrap());
+    }
+    Ok(len / MouseEvent::SIZE)
+}
+
+/// Switches the display between the text console and graphics mode. Entering graphics mode maps
+/// the 320x200 framebuffer into this process and returns its address, one byte per pixel; returning
+/// to text mode unmaps it and returns null. Only one process can use graphics mode at a time, and
+/// the display returns to text mode when that process exits.
+pub fn set_video_mode(mode: VideoMode) -> Result<*mut u8, SetVideoModeError> {
+    syscall(SyscallId::SetVideoMode, &mode)
+}
+
+/// Enables or disables local echo of console input.
+pub fn set_echo(enabled: bool) {
+    syscall(SyscallId::SetEcho, &enabled)
+}
+
+/// Returns the number of bytes that can be read from a file descriptor without blocking.
+pub fn fd_pending(fd: Fd) -> Result<usize, FdPendingError> {
+    syscall(SyscallId::FdPending, &fd)
+}
+
+/// Performs a device-specific control operation on a file descriptor. `request` is one of the
+/// constants in the `ioctl` module, which also describes the expected contents of `arg`.
+pub fn ioctl(fd: Fd, request: u32, arg: &mut [u8]) -> Result<(), IoctlError> {
+    syscall(SyscallId::Ioctl, &IoctlArg { fd, request, arg })
+}
+
+/// Returns the dimensions of the terminal behind a file descriptor, or None if the file descriptor
+/// is not a terminal.
+pub fn winsize(fd: Fd) -> Option<WinSize> {
+    let mut buf = [0; WinSize::SIZE];
+    ioctl(fd, ioctl::CONSOLE_GET_WINSIZE, &mut buf).ok()?;
+    Some(WinSize::from_bytes(&buf))
+}
+
+/// Sets the most verbose level of kernel log messages that will be printed to the console.
+pub fn set_log_level(level: LogLevel) {
+    syscall(SyscallId::SetLogLevel, &level)
+}
+
+/// Returns the frequency of the timestamp counter, in Hz.
+pub fn tsc_hz() -> u64 {
+    syscall(SyscallId::TscHz, &())
+}
+
+/// Returns the current time, in seconds since 1970-01-01 00:00:00 UTC.
+pub fn time() -> Result<u64, TimeError> {
+    syscall(SyscallId::Time, &())
+}
+
+/// Grows or shrinks the program heap by `incr` bytes, returning the previous end of the heap.
+/// Returns None if the heap would shrink below its initial size or collide with other memory.
+pub fn sbrk(incr: isize) -> Option<*mut u8> {
+    syscall(SyscallId::Sbrk, &incr)
+}
+
 fn syscall<A, R>(id: SyscallId, arg: &A) -> R {
     let mut result = core::mem::MaybeUninit::<R>::uninit();
     unsafe {
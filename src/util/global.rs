@@ -14,6 +14,10 @@ use core::{
 pub struct Global<T> {
     resource: UnsafeCell<GlobalStorage<T>>,
     taken: AtomicBool,
+    /// Whether the resource is also used by interrupt handlers, and so must only be held with
+    /// interrupts disabled.
+    #[cfg(debug_assertions)]
+    interrupt_sensitive: bool,
 }
 impl<T> Global<T> {
     /// Initializes a global resource.
@@ -21,6 +25,8 @@ impl<T> Global<T> {
         Global {
             resource: UnsafeCell::new(GlobalStorage::Initialized(resource)),
             taken: AtomicBool::new(false),
+            #[cfg(debug_assertions)]
+            interrupt_sensitive: false,
         }
     }
 
@@ -29,13 +35,42 @@ impl<T> Global<T> {
         Global {
             resource: UnsafeCell::new(GlobalStorage::Initializer(initializer)),
             taken: AtomicBool::new(false),
+            #[cfg(debug_assertions)]
+            interrupt_sensitive: false,
         }
     }
 
-    /// Attempts to acquire exclusive access to this resource.
-    pub fn take(&self) -> Option<GlobalGuard<'_, T>> {
+    /// Marks this resource as used by interrupt handlers. Since an interrupt handler cannot wait
+    /// for the code it interrupted to release the resource, the resource must only be held with
+    /// interrupts disabled. In debug builds, acquiring or releasing it with interrupts enabled
+    /// panics, rather than waiting for an interrupt to arrive at the wrong time.
+    pub const fn interrupt_sensitive(#[allow(unused_mut)] mut self) -> Self {
+        #[cfg(debug_assertions)]
+        {
+            self.interrupt_sensitive = true;
+        }
+        self
+    }
+
+    /// Panics if this resource is interrupt-sensitive and interrupts are enabled.
+    #[cfg(debug_assertions)]
+    fn check_interrupts(&self) {
+        assert!(
+            !self.interrupt_sensitive || !crate::x86::interrupt::interrupts_enabled(),
+            "{} held with interrupts enabled",
+            core::any::type_name::<T>()
+        );
+    }
+
+    /// Attempts to acquire exclusive access to this resource, returning None if it is in use.
+    ///
+    /// Use this instead of `lock` in interrupt handlers, which cannot wait for the code they
+    /// interrupted to release the resource.
+    pub fn try_take(&self) -> Option<GlobalGuard<'_, T>> {
         let was_taken = self.taken.swap(true, Ordering::Acquire);
         if !was_taken {
+            #[cfg(debug_assertions)]
+            self.check_interrupts();
             unsafe {
                 (*self.resource.get()).initialize();
             }
@@ -45,8 +80,36 @@ impl<T> Global<T> {
         }
     }
 
+    /// Acquires exclusive access to this resource, spinning until it is available.
+    ///
+    /// Spinning can only succeed if the resource is held by another CPU. Spinning for too long
+    /// panics rather than hanging, since it most likely means the resource is held by code this CPU
+    /// interrupted.
+    pub fn lock(&self) -> GlobalGuard<'_, T> {
+        const DEADLOCK_SPINS: usize = 100_000_000;
+        let mut spins: usize = 0;
+        loop {
+            if let Some(guard) = self.try_take() {
+                return guard;
+            }
+
+            // Wait for the resource to be released before retrying the swap, so that waiting
+            // CPUs don't keep stealing the cache line from the owner.
+            while self.taken.load(Ordering::Relaxed) {
+                core::hint::spin_loop();
+                spins += 1;
+                assert!(
+                    spins < DEADLOCK_SPINS,
+                    "deadlock acquiring {}",
+                    core::any::type_name::<T>()
+                );
+            }
+        }
+    }
+
     /// Makes a reference to the resource permanent. Future
-    /// calls to `take` will always deny access.
+    /// calls to `try_take` will always deny access, and calls to `lock`
+    /// will panic.
     pub fn leak(guard: GlobalGuard<'_, T>) -> &mut T {
         let result = unsafe { (*guard.0.resource.get()).get_mut() };
         core::mem::forget(guard);
@@ -55,7 +118,7 @@ impl<T> Global<T> {
 
     /// Acquires a permanent reference to the resource.
     pub fn take_and_leak(&self) -> Option<&mut T> {
-        self.take().map(|guard| Self::leak(guard))
+        self.try_take().map(|guard| Self::leak(guard))
     }
 }
 impl<T: Default> Default for Global<T> {
@@ -86,6 +149,8 @@ impl<'a, T> core::ops::DerefMut for GlobalGuard<'a, T> {
 
 impl<'a, T> core::ops::Drop for GlobalGuard<'a, T> {
     fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        self.0.check_interrupts();
         self.0.taken.store(false, Ordering::Release);
     }
 }
@@ -1,6 +1,23 @@
-use core::fmt::{self, Write};
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicU8, Ordering},
+};
 
-use crate::x86::io::{cga, serial};
+use crate::{
+    syscall::LogLevel,
+    x86::{
+        interrupt,
+        io::{cga, serial},
+    },
+};
+
+/// The most verbose level of log messages that will be printed.
+pub static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+/// Returns true if log messages of the given level should be printed.
+pub fn log_enabled(level: LogLevel) -> bool {
+    level as u8 <= LOG_LEVEL.load(Ordering::Relaxed)
+}
 
 #[macro_export]
 macro_rules! kprint {
@@ -16,16 +33,46 @@ macro_rules! kprintln {
     )
 }
 
+/// Prints a line to the kernel console if `level` is enabled by `LOG_LEVEL`.
+#[macro_export]
+macro_rules! klog {
+    ($level:expr, $($arg:tt)*) => (
+        if $crate::util::kprint::log_enabled($level) {
+            $crate::kprintln!($($arg)*)
+        }
+    )
+}
+
+#[macro_export]
+macro_rules! kerror {
+    ($($arg:tt)*) => ($crate::klog!($crate::syscall::LogLevel::Error, $($arg)*))
+}
+#[macro_export]
+macro_rules! kwarn {
+    ($($arg:tt)*) => ($crate::klog!($crate::syscall::LogLevel::Warn, $($arg)*))
+}
+#[macro_export]
+macro_rules! kinfo {
+    ($($arg:tt)*) => ($crate::klog!($crate::syscall::LogLevel::Info, $($arg)*))
+}
+#[macro_export]
+macro_rules! kdebug {
+    ($($arg:tt)*) => ($crate::klog!($crate::syscall::LogLevel::Debug, $($arg)*))
+}
+
 #[doc(hidden)]
 pub fn _kprint(fmt: fmt::Arguments<'_>) {
-    serial::COM1
-        .take()
-        .expect("serial port conflict")
-        .write_fmt(fmt)
-        .expect("serial port error");
+    // The serial port's interrupt handler also uses COM1, so hold it with interrupts disabled.
+    interrupt::with_interrupts_disabled(|| {
+        serial::COM1
+            .try_take()
+            .expect("serial port conflict")
+            .write_fmt(fmt)
+            .expect("serial port error")
+    });
 
     // Also write to CGA, but ignore conflicts
-    if let Some(mut cga) = cga::CGA.take() {
+    if let Some(mut cga) = cga::CGA.try_take() {
         cga.write_fmt(fmt).expect("CGA error");
     }
 }
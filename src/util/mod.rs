@@ -1,4 +1,8 @@
 mod global;
 pub mod kprint;
+pub mod mem;
+pub mod path;
+mod ring;
 
 pub use global::{Global, Lazy};
+pub use ring::RingBuffer;
@@ -0,0 +1,28 @@
+use alloc::{string::String, vec::Vec};
+
+/// Resolves `path` relative to the absolute directory `cwd`, returning a normalized absolute path:
+/// one with no empty, `.`, or `..` components. `..` at the root stays at the root.
+pub fn resolve(cwd: &str, path: &str) -> String {
+    let base = if path.starts_with('/') { "" } else { cwd };
+
+    let mut components = Vec::new();
+    for component in base.split('/').chain(path.split('/')) {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            c => components.push(c),
+        }
+    }
+
+    let mut result = String::new();
+    for c in components {
+        result.push('/');
+        result.push_str(c);
+    }
+    if result.is_empty() {
+        result.push('/');
+    }
+    result
+}
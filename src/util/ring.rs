@@ -0,0 +1,63 @@
+/// A fixed-capacity FIFO queue, for buffering input from interrupt handlers without allocating.
+pub struct RingBuffer<T: Copy, const N: usize> {
+    buf: [T; N],
+    rpos: usize,
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+    /// Creates an empty ring buffer, using `fill` to initialize its storage.
+    pub const fn new(fill: T) -> Self {
+        Self {
+            buf: [fill; N],
+            rpos: 0,
+            len: 0,
+        }
+    }
+
+    /// Returns the number of items in the buffer.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends an item, returning false (and dropping the item) if the buffer is full.
+    pub fn push(&mut self, item: T) -> bool {
+        if self.len == N {
+            return false;
+        }
+        self.buf[(self.rpos + self.len) % N] = item;
+        self.len += 1;
+        true
+    }
+
+    /// Removes the item at the front of the buffer.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let item = self.buf[self.rpos];
+        self.rpos = (self.rpos + 1) % N;
+        self.len -= 1;
+        Some(item)
+    }
+
+    /// Removes items from the front of the buffer into `out`, returning the number removed.
+    pub fn pop_into(&mut self, out: &mut [T]) -> usize {
+        let count = core::cmp::min(out.len(), self.len);
+        for item in &mut out[..count] {
+            *item = self.buf[self.rpos];
+            self.rpos = (self.rpos + 1) % N;
+        }
+        self.len -= count;
+        count
+    }
+
+    /// Discards every item in the buffer.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
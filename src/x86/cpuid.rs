@@ -0,0 +1,132 @@
+//! CPU feature detection using the `cpuid` instruction.
+use core::{arch::asm, fmt};
+
+use crate::util::Lazy;
+use modular_bitfield::prelude::*;
+
+/// Executes `cpuid` with the given leaf, returning `(eax, ebx, ecx, edx)`.
+pub fn cpuid(leaf: u32) -> (u32, u32, u32, u32) {
+    let (eax, ebx, ecx, edx): (u32, u32, u32, u32);
+    unsafe {
+        // LLVM may reserve ebx, so save and restore it around the cpuid instruction.
+        asm!(
+            "mov {0:e}, ebx",
+            "cpuid",
+            "xchg {0:e}, ebx",
+            out(reg) ebx,
+            inout("eax") leaf => eax,
+            inout("ecx") 0 => ecx,
+            out("edx") edx,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+    (eax, ebx, ecx, edx)
+}
+
+/// Optional features supported by the CPU.
+#[bitfield]
+#[derive(Clone, Copy)]
+pub struct Features {
+    /// An x87 floating-point unit.
+    pub fpu: bool,
+    /// 4 MB pages.
+    pub pse: bool,
+    /// The timestamp counter.
+    pub tsc: bool,
+    /// Model-specific registers.
+    pub msr: bool,
+    /// Physical address extension (64-bit page table entries).
+    pub pae: bool,
+    /// A local APIC.
+    pub apic: bool,
+    /// Global pages.
+    pub pge: bool,
+    /// The page attribute table.
+    pub pat: bool,
+    /// The `fxsave` and `fxrstor` instructions.
+    pub fxsr: bool,
+    pub sse: bool,
+    pub sse2: bool,
+    pub sse3: bool,
+    /// The `rdrand` instruction.
+    pub rdrand: bool,
+    /// The no-execute page table bit (requires PAE).
+    pub nx: bool,
+    /// 64-bit long mode.
+    pub long_mode: bool,
+
+    #[skip]
+    __: B1,
+}
+
+/// The features supported by this CPU.
+pub static FEATURES: Lazy<Features> = Lazy::new(Features::detect);
+
+impl Features {
+    fn detect() -> Features {
+        let bit = |reg: u32, bit: u32| reg & (1 << bit) != 0;
+
+        let (max_leaf, _, _, _) = cpuid(0);
+        let (_, _, ecx, edx) = if max_leaf >= 1 {
+            cpuid(1)
+        } else {
+            (0, 0, 0, 0)
+        };
+        let (max_extended_leaf, _, _, _) = cpuid(0x80000000);
+        let (_, _, _, extended_edx) = if max_extended_leaf >= 0x80000001 {
+            cpuid(0x80000001)
+        } else {
+            (0, 0, 0, 0)
+        };
+
+        Features::new()
+            .with_fpu(bit(edx, 0))
+            .with_pse(bit(edx, 3))
+            .with_tsc(bit(edx, 4))
+            .with_msr(bit(edx, 5))
+            .with_pae(bit(edx, 6))
+            .with_apic(bit(edx, 9))
+            .with_pge(bit(edx, 13))
+            .with_pat(bit(edx, 16))
+            .with_fxsr(bit(edx, 24))
+            .with_sse(bit(edx, 25))
+            .with_sse2(bit(edx, 26))
+            .with_sse3(bit(ecx, 0))
+            .with_rdrand(bit(ecx, 30))
+            .with_nx(bit(extended_edx, 20))
+            .with_long_mode(bit(extended_edx, 29))
+    }
+}
+
+impl fmt::Display for Features {
+    /// Formats the names of the supported features, separated by spaces.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let features = [
+            ("fpu", self.fpu()),
+            ("pse", self.pse()),
+            ("tsc", self.tsc()),
+            ("msr", self.msr()),
+            ("pae", self.pae()),
+            ("apic", self.apic()),
+            ("pge", self.pge()),
+            ("pat", self.pat()),
+            ("fxsr", self.fxsr()),
+            ("sse", self.sse()),
+            ("sse2", self.sse2()),
+            ("sse3", self.sse3()),
+            ("rdrand", self.rdrand()),
+            ("nx", self.nx()),
+            ("lm", self.long_mode()),
+        ];
+
+        let mut first = true;
+        for (name, _) in features.iter().filter(|(_, supported)| *supported) {
+            if !first {
+                f.write_str(" ")?;
+            }
+            f.write_str(name)?;
+            first = false;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+//! The debug registers, which raise a debug exception when the CPU executes or accesses any of up
+//! to four addresses.
+use core::arch::asm;
+
+/// The bits of DR6 identifying which breakpoints were hit.
+pub const DR6_BREAKPOINTS: usize = 0xf;
+/// The bit of DR6 set when a debug exception was caused by the trap flag.
+pub const DR6_SINGLE_STEP: usize = 1 << 14;
+
+/// A breakpoint condition, as encoded in the R/W field of DR7.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Execute = 0b00,
+    Write = 0b01,
+    ReadWrite = 0b11,
+}
+
+/// The breakpoint state for one process.
+#[derive(Clone, Default)]
+pub struct DebugRegisters {
+    /// The breakpoint addresses, loaded into DR0-DR3.
+    addrs: [usize; 4],
+    /// The debug control register: which breakpoints are enabled, and their conditions and sizes.
+    dr7: usize,
+}
+
+impl DebugRegisters {
+    /// The number of hardware breakpoints.
+    pub const COUNT: usize = 4;
+
+    /// Enables breakpoint `index` on the `len` bytes (1, 2, or 4) at `addr`, which must be aligned
+    /// to `len`. Execute breakpoints must have a length of 1.
+    pub fn set(&mut self, index: usize, addr: usize, condition: Condition, len: usize) {
+        assert!(index < Self::COUNT, "invalid breakpoint index");
+        let len_bits = match len {
+            1 => 0b00,
+            2 => 0b01,
+            4 => 0b11,
+            _ => panic!("invalid breakpoint length"),
+        };
+        self.clear(index);
+        self.addrs[index] = addr;
+        self.dr7 |= (1 << (index * 2)) | ((condition as usize | len_bits << 2) << (16 + index * 4));
+    }
+
+    /// Disables breakpoint `index`.
+    pub fn clear(&mut self, index: usize) {
+        assert!(index < Self::COUNT, "invalid breakpoint index");
+        self.addrs[index] = 0;
+        self.dr7 &= !((0b11 << (index * 2)) | (0b1111 << (16 + index * 4)));
+    }
+
+    /// Returns whether any breakpoint is enabled.
+    pub fn any_enabled(&self) -> bool {
+        self.dr7 & 0xff != 0
+    }
+
+    /// Loads the breakpoints into the CPU's debug registers.
+    ///
+    /// # Safety
+    /// The breakpoints must not trigger on memory the kernel accesses without being prepared for
+    /// a debug exception.
+    pub unsafe fn load(&self) {
+        asm!("mov dr0, {}", in(reg) self.addrs[0], options(nomem, nostack));
+        asm!("mov dr1, {}", in(reg) self.addrs[1], options(nomem, nostack));
+        asm!("mov dr2, {}", in(reg) self.addrs[2], options(nomem, nostack));
+        asm!("mov dr3, {}", in(reg) self.addrs[3], options(nomem, nostack));
+        asm!("mov dr7, {}", in(reg) self.dr7, options(nomem, nostack));
+    }
+}
+
+/// Reads the debug status register, which describes the cause of the last debug exception.
+pub fn read_dr6() -> usize {
+    let dr6: usize;
+    unsafe { asm!("mov {}, dr6", out(reg) dr6, options(nomem, nostack)) };
+    dr6
+}
+
+/// Clears the status bits of DR6. The CPU never clears them itself.
+pub fn clear_dr6() {
+    let dr6 = read_dr6() & !(DR6_BREAKPOINTS | DR6_SINGLE_STEP);
+    unsafe { asm!("mov dr6, {}", in(reg) dr6, options(nomem, nostack)) };
+}
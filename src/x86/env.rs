@@ -1,9 +1,20 @@
 use crate::x86;
 
-/// A process environment, consisting of an interrupt frame and a page directory address..
+/// A process environment, consisting of an interrupt frame, a page directory address, the bounds
+/// of the program heap, the hardware breakpoints set by a debugger, and the floating-point
+/// registers.
 pub struct Env {
     pub trap_frame: x86::interrupt::InterruptFrame,
     pub cr3: usize,
+    /// The initial program break; the heap may not shrink below this address.
+    pub brk_start: usize,
+    /// The current program break (the end of the heap).
+    pub brk: usize,
+    /// The hardware breakpoints, loaded into the debug registers whenever the process runs.
+    pub debug_regs: x86::debug_regs::DebugRegisters,
+    /// The floating-point registers. While the process owns the FPU, they are out of date; see
+    /// the `fpu` module.
+    pub fpu: alloc::boxed::Box<x86::fpu::FpuState>,
 }
 
 /// An x86 task state segment.
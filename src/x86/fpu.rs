@@ -0,0 +1,104 @@
+//! Floating-point (x87 and SSE) register state.
+//!
+//! The kernel is built without floating point, so the FPU registers always belong to a process.
+//! The scheduler switches them lazily: a context switch only sets CR0.TS, and the first
+//! floating-point instruction the new process executes raises a device-not-available fault. Only
+//! then are the registers saved into their previous owner's `Env` and loaded from the new one's, so
+//! processes that never use floating point never pay for it.
+//!
+//! With the `eager-fpu` feature, the scheduler instead switches the registers during every context
+//! switch. That costs a 512-byte save and restore each time, but never takes a fault.
+use alloc::boxed::Box;
+use core::arch::asm;
+
+use crate::x86::cpuid::FEATURES;
+
+const CR0_MP: usize = 1 << 1;
+const CR0_EM: usize = 1 << 2;
+const CR0_TS: usize = 1 << 3;
+const CR0_NE: usize = 1 << 5;
+const CR4_OSFXSR: usize = 1 << 9;
+const CR4_OSXMMEXCPT: usize = 1 << 10;
+
+/// A process's saved FPU and SSE registers, in the format used by `fxsave` and `fxrstor`.
+#[repr(C, align(16))]
+#[derive(Clone)]
+pub struct FpuState([u8; 512]);
+
+impl FpuState {
+    /// Returns the register state after `fninit`, with all SSE exceptions masked.
+    pub fn new() -> Box<FpuState> {
+        let mut state = Box::new(FpuState([0; 512]));
+        // The x87 control word: all exceptions masked, 64-bit precision, round to nearest.
+        state.0[0..2].copy_from_slice(&0x37fu16.to_le_bytes());
+        // MXCSR: all exceptions masked, round to nearest.
+        state.0[24..28].copy_from_slice(&0x1f80u32.to_le_bytes());
+        state
+    }
+
+    /// Saves the FPU registers into this state.
+    ///
+    /// # Safety
+    /// The FPU must be supported, and CR0.TS must be clear.
+    pub unsafe fn save(&mut self) {
+        asm!("fxsave [{}]", in(reg) self.0.as_mut_ptr(), options(nostack, preserves_flags));
+    }
+
+    /// Loads the FPU registers from this state.
+    ///
+    /// # Safety
+    /// The FPU must be supported, and CR0.TS must be clear.
+    pub unsafe fn restore(&self) {
+        asm!("fxrstor [{}]", in(reg) self.0.as_ptr(), options(nostack, preserves_flags, readonly));
+    }
+}
+
+/// Returns whether processes may use floating point, which requires `fxsave` and `fxrstor`.
+pub fn supported() -> bool {
+    let features = FEATURES.get();
+    features.fpu() && features.fxsr()
+}
+
+/// Enables the FPU, and SSE if the CPU has it. On CPUs without `fxsave`, instead makes every
+/// floating-point instruction fault, so that processes can't share unsaved registers.
+pub fn init() {
+    let mut cr0 = read_cr0();
+    if supported() {
+        cr0 = (cr0 & !(CR0_EM | CR0_TS)) | CR0_MP | CR0_NE;
+        let mut cr4: usize;
+        unsafe {
+            asm!("mov cr0, {}", in(reg) cr0, options(nomem, nostack));
+            asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack));
+        }
+        cr4 |= CR4_OSFXSR;
+        if FEATURES.get().sse() {
+            cr4 |= CR4_OSXMMEXCPT;
+        }
+        unsafe {
+            asm!("mov cr4, {}", in(reg) cr4, options(nomem, nostack));
+            asm!("fninit", options(nomem, nostack));
+        }
+    } else {
+        cr0 |= CR0_EM;
+        unsafe { asm!("mov cr0, {}", in(reg) cr0, options(nomem, nostack)) };
+    }
+}
+
+/// Sets or clears CR0.TS. While it is set, the next floating-point instruction faults.
+pub fn set_task_switched(task_switched: bool) {
+    let cr0 = read_cr0();
+    let new_cr0 = if task_switched {
+        cr0 | CR0_TS
+    } else {
+        cr0 & !CR0_TS
+    };
+    if new_cr0 != cr0 {
+        unsafe { asm!("mov cr0, {}", in(reg) new_cr0, options(nomem, nostack)) };
+    }
+}
+
+fn read_cr0() -> usize {
+    let cr0: usize;
+    unsafe { asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack)) };
+    cr0
+}
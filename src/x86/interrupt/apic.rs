@@ -0,0 +1,238 @@
+//! The local APIC and I/O APIC, which replace the legacy PIC on modern systems.
+//!
+//! The I/O APIC routes ISA IRQ `n` to vector `IRQ_OFFSET + n`, just like the PIC does, so the
+//! interrupt handlers don't need to know which controller is active. We don't parse the ACPI
+//! tables, so we assume each ISA IRQ is wired to the I/O APIC pin with the same number, and that
+//! the I/O APIC is at its conventional address. The PIT's IRQ is typically rerouted, so the local
+//! APIC timer is used as the timer source instead.
+use core::arch::asm;
+
+use crate::{
+    util::Lazy,
+    x86::{
+        cpuid,
+        interrupt::{self, pic, pit, timer},
+        mmu,
+    },
+};
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const IOAPIC_PADDR: usize = 0xFEC00000;
+
+/// The interrupt vector used for spurious local APIC interrupts.
+pub const SPURIOUS_VECTOR: usize = 0xFF;
+
+/// The local APIC and I/O APIC of the boot CPU.
+pub struct Apic {
+    local: LocalApic,
+    io: IoApic,
+}
+
+/// The APIC, or None if the CPU does not have one.
+pub static APIC: Lazy<Option<Apic>> = Lazy::new(|| unsafe { Apic::new() });
+
+impl Apic {
+    /// Disables the PIC and initializes the APIC, with all IRQs masked.
+    unsafe fn new() -> Option<Apic> {
+        let features = cpuid::FEATURES.get();
+        if !features.apic() || !features.msr() {
+            return None;
+        }
+
+        // The PIC can still raise spurious interrupts even when fully masked, so make sure it's
+        // been remapped away from the CPU exception vectors before we mask it.
+        pic::PIC.lock().set_mask(0xffff);
+
+        const APIC_GLOBAL_ENABLE: u64 = 1 << 11;
+        let apic_base = rdmsr(IA32_APIC_BASE);
+        wrmsr(IA32_APIC_BASE, apic_base | APIC_GLOBAL_ENABLE);
+
+        let local = LocalApic {
+            base: map_mmio(apic_base as usize & mmu::PAGE_MASK),
+        };
+        let mut io = IoApic {
+            base: map_mmio(IOAPIC_PADDR),
+            pins: 0,
+        };
+        io.pins = ((io.read(IoApic::VERSION) >> 16) & 0xff) as usize + 1;
+
+        // Enable the local APIC and accept interrupts of all priorities.
+        local.write(LocalApic::TASK_PRIORITY, 0);
+        local.write(
+            LocalApic::SPURIOUS,
+            LocalApic::SPURIOUS_ENABLE | SPURIOUS_VECTOR as u32,
+        );
+
+        // Route every pin to the boot CPU, masked.
+        let destination = local.read(LocalApic::ID) >> 24;
+        for pin in 0..io.pins {
+            io.write_redirection(pin, destination, true);
+        }
+
+        Some(Apic { local, io })
+    }
+
+    /// Starts the local APIC timer, firing `hz` times per second.
+    fn start_timer(&self, hz: u32) {
+        const DIVIDE_BY_16: u32 = 0x3;
+        const MASKED: u32 = 1 << 16;
+        const PERIODIC: u32 = 1 << 17;
+        let vector = timer::VECTOR as u32;
+
+        // Count how many timer ticks elapse in 10ms.
+        let ticks_per_10ms = interrupt::with_interrupts_disabled(|| unsafe {
+            self.local.write(LocalApic::TIMER_DIVIDE, DIVIDE_BY_16);
+            self.local.write(LocalApic::LVT_TIMER, MASKED | vector);
+            self.local.write(LocalApic::TIMER_INITIAL, u32::MAX);
+            pit::PIT.lock().delay_us(10_000);
+            u32::MAX - self.local.read(LocalApic::TIMER_CURRENT)
+        });
+
+        unsafe {
+            self.local.write(LocalApic::LVT_TIMER, PERIODIC | vector);
+            self.local
+                .write(LocalApic::TIMER_INITIAL, ticks_per_10ms * 100 / hz);
+        }
+    }
+
+    /// Handles a spurious interrupt from the local APIC, which must not be acknowledged.
+    pub fn handle_spurious(_frame: &mut interrupt::InterruptFrame) {
+        // ignore
+    }
+}
+
+impl interrupt::InterruptController for Apic {
+    fn mask(&self, irq: usize) {
+        self.io.set_masked(irq, true);
+    }
+
+    fn unmask(&self, irq: usize) {
+        self.io.set_masked(irq, false);
+    }
+
+    fn eoi(&self, _irq: usize) {
+        unsafe { self.local.write(LocalApic::EOI, 0) }
+    }
+}
+
+impl timer::TimerSource for Apic {
+    fn set_frequency(&self, hz: u32) {
+        self.start_timer(hz)
+    }
+
+    fn handle_interrupt(&self) {
+        unsafe { self.local.write(LocalApic::EOI, 0) }
+    }
+}
+
+struct LocalApic {
+    base: usize,
+}
+
+impl LocalApic {
+    const ID: usize = 0x20;
+    const TASK_PRIORITY: usize = 0x80;
+    const EOI: usize = 0xB0;
+    const SPURIOUS: usize = 0xF0;
+    const LVT_TIMER: usize = 0x320;
+    const TIMER_INITIAL: usize = 0x380;
+    const TIMER_CURRENT: usize = 0x390;
+    const TIMER_DIVIDE: usize = 0x3E0;
+
+    const SPURIOUS_ENABLE: u32 = 1 << 8;
+
+    unsafe fn read(&self, reg: usize) -> u32 {
+        core::ptr::read_volatile((self.base + reg) as *const u32)
+    }
+
+    unsafe fn write(&self, reg: usize, val: u32) {
+        core::ptr::write_volatile((self.base + reg) as *mut u32, val)
+    }
+}
+
+struct IoApic {
+    base: usize,
+    pins: usize,
+}
+
+impl IoApic {
+    const VERSION: u32 = 0x01;
+    const REDIRECTION_TABLE: u32 = 0x10;
+
+    const MASKED: u32 = 1 << 16;
+
+    /// Reads an I/O APIC register. The caller must ensure this is not interrupted by another
+    /// register access.
+    unsafe fn read(&self, reg: u32) -> u32 {
+        core::ptr::write_volatile(self.base as *mut u32, reg);
+        core::ptr::read_volatile((self.base + 0x10) as *const u32)
+    }
+
+    /// Writes an I/O APIC register. The caller must ensure this is not interrupted by another
+    /// register access.
+    unsafe fn write(&self, reg: u32, val: u32) {
+        core::ptr::write_volatile(self.base as *mut u32, reg);
+        core::ptr::write_volatile((self.base + 0x10) as *mut u32, val);
+    }
+
+    /// Routes `pin` to vector `IRQ_OFFSET + pin` on the local APIC with ID `destination`,
+    /// using edge triggering and fixed delivery.
+    unsafe fn write_redirection(&self, pin: usize, destination: u32, masked: bool) {
+        let reg = Self::REDIRECTION_TABLE + 2 * pin as u32;
+        let mut lo = (interrupt::IRQ_OFFSET + pin) as u32;
+        if masked {
+            lo |= Self::MASKED;
+        }
+        self.write(reg + 1, destination << 24);
+        self.write(reg, lo);
+    }
+
+    fn set_masked(&self, pin: usize, masked: bool) {
+        assert!(pin < self.pins, "interrupt out of range");
+        let reg = Self::REDIRECTION_TABLE + 2 * pin as u32;
+        interrupt::with_interrupts_disabled(|| unsafe {
+            let lo = self.read(reg);
+            self.write(
+                reg,
+                if masked {
+                    lo | Self::MASKED
+                } else {
+                    lo & !Self::MASKED
+                },
+            );
+        })
+    }
+}
+
+/// Maps a page of memory-mapped I/O into kernel space, returning its virtual address.
+unsafe fn map_mmio(paddr: usize) -> usize {
+    let mut mmu = mmu::MMU.lock();
+    let mmu = &mut *mmu;
+    let vaddr = mmu
+        .mapper
+        .find_unused_kernelspace(1)
+        .expect("out of kernel address space");
+    mmu.mapper.map(
+        &mut mmu.allocator,
+        paddr,
+        vaddr,
+        mmu::mmap::MappingFlags::new()
+            .with_writable(true)
+            .with_cache_disabled(true),
+    );
+    vaddr
+}
+
+unsafe fn rdmsr(msr: u32) -> u64 {
+    let (lo, hi): (u32, u32);
+    asm!("rdmsr", in("ecx") msr, out("eax") lo, out("edx") hi, options(nomem, nostack));
+    (hi as u64) << 32 | lo as u64
+}
+
+unsafe fn wrmsr(msr: u32, val: u64) {
+    asm!(
+        "wrmsr",
+        in("ecx") msr, in("eax") val as u32, in("edx") (val >> 32) as u32,
+        options(nostack)
+    );
+}
@@ -0,0 +1,119 @@
+//! Double fault handling.
+//!
+//! When the kernel overflows its stack, it runs into the unmapped guard page below it (see
+//! `mmu::is_kernel_stack_guard`). The CPU can't push the page fault's interrupt frame onto the
+//! guard page either, so it raises a double fault instead. Delivering that through an ordinary
+//! interrupt gate would fault a third time and reset the machine, so the IDT routes double faults
+//! through a task gate: the CPU saves the interrupted registers into the current task state segment
+//! and switches to `TSS`, which runs `handler` on a stack of its own.
+use core::arch::asm;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::{
+    util::Global,
+    x86::{
+        env::TaskStateSegment,
+        mmu::{self, segment::SegmentDescriptor, SegmentId},
+        DescriptorTableRegister,
+    },
+};
+
+const STACK_SIZE: usize = 4 * mmu::PAGE_SIZE;
+static mut STACK: mmu::PageAligned<[u8; STACK_SIZE]> = mmu::PageAligned([0; STACK_SIZE]);
+
+/// The double fault task.
+static TSS: Global<TaskStateSegment> = Global::lazy_default();
+
+/// The page directory field of `TSS`, once it has been initialized. `set_cr3` writes through this
+/// rather than locking `TSS`, so switching page directories never waits on a lock.
+static TSS_CR3: AtomicPtr<u32> = AtomicPtr::new(core::ptr::null_mut());
+
+/// The task the CPU runs until the scheduler loads the per-CPU task state segment. A task switch
+/// needs somewhere to save the outgoing task's registers, even during boot.
+static BOOT_TSS: Global<TaskStateSegment> = Global::lazy_default();
+
+/// Installs the boot and double fault task state segments in `gdt`. Called by 'Mmu::init' before
+/// loading the GDT and task register.
+pub fn init(gdt: &mut [SegmentDescriptor; mmu::NUM_SEGMENTS]) {
+    let boot_tss = BOOT_TSS
+        .take_and_leak()
+        .expect("attempt to initialize double fault handling twice");
+    gdt[SegmentId::TaskState as usize >> 3] = SegmentDescriptor::task_state(boot_tss);
+
+    let tss = TSS
+        .take_and_leak()
+        .expect("attempt to initialize double fault handling twice");
+    unsafe {
+        asm!("mov {}, cr3", out(reg) tss.cr3, options(nomem, nostack));
+    }
+    tss.eip = handler as extern "C" fn() -> ! as usize as u32;
+    tss.esp = (core::ptr::addr_of!(STACK) as usize + STACK_SIZE) as u32;
+    tss.eflags = 0x2; // interrupts disabled (bit 1 is reserved and always set)
+    tss.cs = SegmentId::KernelCode as u16;
+    tss.ss = SegmentId::KernelData as u16;
+    tss.ds = SegmentId::KernelData as u16;
+    tss.es = SegmentId::KernelData as u16;
+    tss.fs = SegmentId::KernelData as u16;
+    tss.gs = SegmentId::KernelData as u16;
+    TSS_CR3.store(&mut tss.cr3, Ordering::Release);
+    gdt[SegmentId::DoubleFaultTask as usize >> 3] = SegmentDescriptor::task_state(tss);
+}
+
+/// Sets the page directory the double fault task runs with. Every page directory maps the kernel
+/// the same way, but the task's must not be freed, so it follows the active one.
+///
+/// This takes no lock, so it can't spin against a double fault taken while it runs; the handler
+/// doesn't touch `TSS` in any case. A double fault that interrupts the write itself sees either the
+/// old or the new page directory, both of which map the kernel.
+pub fn set_cr3(cr3: usize) {
+    let tss_cr3 = TSS_CR3.load(Ordering::Acquire);
+    if !tss_cr3.is_null() {
+        unsafe { tss_cr3.write_volatile(cr3 as u32) };
+    }
+}
+
+/// Overflows the kernel stack on purpose, to check that the overflow is reported rather than
+/// resetting the machine. Never returns.
+#[cfg(feature = "stack-overflow-test")]
+pub fn test_stack_overflow() {
+    #[inline(never)]
+    #[allow(unconditional_recursion)]
+    fn recurse(depth: usize) -> usize {
+        // Give each call a sizeable frame that the optimizer can't remove.
+        let frame = core::hint::black_box([depth as u8; 256]);
+        recurse(depth + 1) + frame[0] as usize
+    }
+
+    crate::kinfo!("Overflowing the kernel stack; this should panic with a stack overflow.");
+    recurse(0);
+    panic!("kernel stack overflow was not caught");
+}
+
+/// Runs in the double fault task, with the CPU's error code (always 0) where the return address
+/// would be.
+extern "C" fn handler() -> ! {
+    let fault_addr: usize;
+    unsafe {
+        asm!("mov {}, cr2", out(reg) fault_addr, options(nomem, nostack));
+    }
+
+    // The task switch saved the interrupted registers into the task named by our back link. Find
+    // both through the GDT, since the interrupted task could be any of them.
+    let gdt = DescriptorTableRegister::sgdt().paddr as *const SegmentDescriptor;
+    let tss_at = |selector: u16| unsafe {
+        &*((*gdt.add(selector as usize >> 3)).base() as *const TaskStateSegment)
+    };
+    let interrupted = tss_at(tss_at(SegmentId::DoubleFaultTask as u16).link);
+
+    if mmu::is_kernel_stack_guard(fault_addr) {
+        panic!(
+            "Kernel stack overflow accessing virtual address {:#010x}\neip={:08x} esp={:08x}",
+            fault_addr, interrupted.eip, interrupted.esp
+        )
+    } else {
+        panic!(
+            "Double fault (cr2={:#010x})\neip={:08x} esp={:08x}",
+            fault_addr, interrupted.eip, interrupted.esp
+        )
+    }
+}
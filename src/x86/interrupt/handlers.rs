@@ -1,22 +1,32 @@
 use crate::x86::{interrupt::*, io, mmu};
 use crate::{isr_noerr, isr_witherr};
-use crate::{kprintln, syscall};
+use crate::{kwarn, syscall};
 
 /// Populates and returns the IDT.
 pub fn default() -> InterruptDescriptorTable {
     let mut idt = InterruptDescriptorTable {
-        divide_error: Interrupt::undefined(),
-        debug_exception: Interrupt::undefined(),
+        divide_error: Interrupt::hw_interrupt(isr_noerr!(
+            HwInterruptNum::DivideError as usize,
+            divide_error
+        )),
+        debug_exception: Interrupt::hw_interrupt(isr_noerr!(
+            HwInterruptNum::DebugException as usize,
+            debug_exception
+        )),
         nmi: Interrupt::undefined(),
         breakpoint: Interrupt::undefined(),
         overflow: Interrupt::undefined(),
         bound_range: Interrupt::undefined(),
-        invalid_opcode: Interrupt::undefined(),
-        device_not_available: Interrupt::undefined(),
-        double_fault: Interrupt::hw_interrupt(isr_witherr!(
-            HwInterruptNum::DoubleFault as usize,
-            double_fault
+        invalid_opcode: Interrupt::hw_interrupt(isr_noerr!(
+            HwInterruptNum::InvalidOpcode as usize,
+            invalid_opcode
         )),
+        device_not_available: Interrupt::hw_interrupt(isr_noerr!(
+            HwInterruptNum::DeviceNotAvailable as usize,
+            device_not_available
+        )),
+        // See the double_fault module.
+        double_fault: Interrupt::task_gate(mmu::SegmentId::DoubleFaultTask),
         coprocessor_segment: Interrupt::undefined(),
         invalid_tss: Interrupt::undefined(),
         segment_not_present: Interrupt::undefined(),
@@ -30,24 +40,32 @@ pub fn default() -> InterruptDescriptorTable {
             mmu::pagefault::page_fault
         )),
         _reserved: Interrupt::undefined(),
-        math_fault: Interrupt::undefined(),
+        math_fault: Interrupt::hw_interrupt(isr_noerr!(
+            HwInterruptNum::MathFault as usize,
+            math_fault
+        )),
         alignment_check: Interrupt::undefined(),
         machine_check: Interrupt::undefined(),
-        simd_exception: Interrupt::undefined(),
+        simd_exception: Interrupt::hw_interrupt(isr_noerr!(
+            HwInterruptNum::SimdException as usize,
+            simd_exception
+        )),
         virtualization_exception: Interrupt::undefined(),
         control_protection_exception: Interrupt::undefined(),
         _reserved2: [Interrupt::undefined(); 10],
         user: [Interrupt::undefined(); 224],
     };
 
-    idt.user[pit::Pit::IRQ] = Interrupt::hw_interrupt(isr_noerr!(
-        pit::Pit::IRQ + IRQ_OFFSET,
-        pit::Pit::handle_interrupt
-    ));
+    idt.user[timer::VECTOR - IRQ_OFFSET] =
+        Interrupt::hw_interrupt(isr_noerr!(timer::VECTOR, timer::handle_interrupt));
     idt.user[io::keyboard::Keyboard::IRQ] = Interrupt::hw_interrupt(isr_noerr!(
         io::keyboard::Keyboard::IRQ + IRQ_OFFSET,
         io::keyboard::Keyboard::handle_interrupt
     ));
+    idt.user[io::mouse::Mouse::IRQ] = Interrupt::hw_interrupt(isr_noerr!(
+        io::mouse::Mouse::IRQ + IRQ_OFFSET,
+        io::mouse::Mouse::handle_interrupt
+    ));
     idt.user[io::serial::COM1_IRQ] = Interrupt::hw_interrupt(isr_noerr!(
         io::serial::COM1_IRQ + IRQ_OFFSET,
         io::serial::Serial::<{ io::serial::COM1_BASE }>::handle_interrupt
@@ -60,29 +78,85 @@ pub fn default() -> InterruptDescriptorTable {
         pic::Pic::IRQ_SPURIOUS_SLAVE + IRQ_OFFSET,
         pic::Pic::handle_spurious_slave
     ));
-    idt.user[0x20] = Interrupt::sw_trap(isr_noerr!(0x20 + IRQ_OFFSET, syscall::syscall));
+    #[cfg(feature = "apic")]
+    {
+        idt.user[apic::SPURIOUS_VECTOR - IRQ_OFFSET] = Interrupt::hw_interrupt(isr_noerr!(
+            apic::SPURIOUS_VECTOR,
+            apic::Apic::handle_spurious
+        ));
+    }
+    // Not a trap gate: syscall handlers hold resources shared with interrupt handlers, so they
+    // must run with interrupts disabled.
+    idt.user[0x20] = Interrupt::sw_interrupt(isr_noerr!(0x20 + IRQ_OFFSET, syscall::syscall));
 
     idt
 }
 
-fn general_protection_fault(frame: &mut InterruptFrame) {
+/// Terminates the current process after a fault in userspace, or panics if the fault happened in
+/// the kernel. `name` describes the fault in the diagnostic.
+fn fault(frame: &mut InterruptFrame, name: &str) {
     if frame.is_userspace() {
         let continuation = {
-            let mut scheduler = crate::process::scheduler::SCHEDULER.take().unwrap();
+            let mut scheduler = crate::process::scheduler::SCHEDULER.try_take().unwrap();
             let scheduler = scheduler.as_mut().unwrap();
-            kprintln!(
-                "terminating process {} due to general protection fault: {:#x?}",
+            kwarn!(
+                "terminating process {} due to {}: {:#x?}",
                 scheduler.current_pid(),
+                name,
                 frame
             );
-            scheduler.kill_current_process(frame).1
+            scheduler.kill_current_process(frame, crate::syscall::ExitStatus::Faulted)
         };
         continuation(frame);
     } else {
-        panic!("General protection fault {:#x?}", frame)
+        panic!("{} {:#x?}", name, frame)
+    }
+}
+
+fn divide_error(frame: &mut InterruptFrame) {
+    fault(frame, "divide error")
+}
+
+fn invalid_opcode(frame: &mut InterruptFrame) {
+    fault(frame, "invalid opcode")
+}
+
+fn math_fault(frame: &mut InterruptFrame) {
+    fault(frame, "floating-point exception")
+}
+
+fn simd_exception(frame: &mut InterruptFrame) {
+    fault(frame, "SIMD floating-point exception")
+}
+
+/// Handles the first floating-point instruction after a context switch, by switching the FPU
+/// registers to the current process.
+fn device_not_available(frame: &mut InterruptFrame) {
+    if frame.is_userspace() && crate::x86::fpu::supported() {
+        let mut scheduler = crate::process::scheduler::SCHEDULER.try_take().unwrap();
+        scheduler.as_mut().unwrap().switch_fpu();
+    } else {
+        fault(frame, "device not available")
     }
 }
 
-fn double_fault(frame: &mut InterruptFrame) {
-    panic!("Double fault: {:#x?}", frame)
+fn general_protection_fault(frame: &mut InterruptFrame) {
+    fault(frame, "general protection fault")
+}
+
+fn debug_exception(frame: &mut InterruptFrame) {
+    if frame.is_userspace() {
+        let continuation = {
+            let mut scheduler = crate::process::scheduler::SCHEDULER.try_take().unwrap();
+            scheduler.as_mut().unwrap().handle_debug_exception(frame)
+        };
+        continuation(frame);
+    } else if x86::debug_regs::read_dr6() & x86::debug_regs::DR6_BREAKPOINTS != 0 {
+        // The kernel touched memory watched by a user breakpoint, for instance while copying a
+        // syscall argument. Breakpoints only report accesses made by the process itself.
+        x86::debug_regs::clear_dr6();
+        frame.eflags |= InterruptFrame::RESUME_FLAG;
+    } else {
+        panic!("Debug exception in kernelspace: {:#x?}", frame)
+    }
 }
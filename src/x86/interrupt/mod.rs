@@ -1,14 +1,22 @@
 #![allow(clippy::identity_op)]
 #![allow(dead_code)]
 use core::arch::asm;
+use core::fmt;
 use core::marker::PhantomData;
 
-use crate::{util::Global, x86};
+use crate::{
+    util::{Global, Lazy},
+    x86,
+};
 use modular_bitfield::prelude::*;
 
+#[cfg(feature = "apic")]
+pub mod apic;
+pub mod double_fault;
 mod handlers;
 pub mod pic;
 pub mod pit;
+pub mod timer;
 
 /// Disables interrupts.
 pub fn cli() {
@@ -24,6 +32,16 @@ pub fn sti() {
     }
 }
 
+/// Returns whether interrupts are enabled.
+pub fn interrupts_enabled() -> bool {
+    const IF: u32 = 1 << 9;
+    let eflags: u32;
+    unsafe {
+        asm!("pushfd; pop {}", out(reg) eflags, options(nomem, preserves_flags));
+    }
+    eflags & IF != 0
+}
+
 /// Executes a closure with interrupts disabled.
 pub fn with_interrupts_disabled<F: FnOnce() -> T, T>(f: F) -> T {
     unsafe {
@@ -39,10 +57,38 @@ pub fn with_interrupts_disabled<F: FnOnce() -> T, T>(f: F) -> T {
 
 pub const IRQ_OFFSET: usize = 0x20;
 
+/// A hardware interrupt controller, which delivers IRQ `n` to interrupt vector `IRQ_OFFSET + n`.
+pub trait InterruptController: Sync {
+    /// Masks (disables) the specified IRQ.
+    fn mask(&self, irq: usize);
+
+    /// Unmasks (enables) the specified IRQ.
+    fn unmask(&self, irq: usize);
+
+    /// Acknowledges an IRQ. This must not block, since it is called from interrupt handlers.
+    fn eoi(&self, irq: usize);
+}
+
+static CONTROLLER: Lazy<&'static dyn InterruptController> = Lazy::new(|| {
+    #[cfg(feature = "apic")]
+    if let Some(apic) = apic::APIC.get() {
+        return apic;
+    }
+    &pic::PIC
+});
+
+/// Returns the active interrupt controller: the APIC if the kernel was built with the `apic`
+/// feature and the CPU supports it, or the legacy PIC otherwise.
+///
+/// The first call chooses and initializes the controller, so the MMU must be initialized first.
+pub fn controller() -> &'static dyn InterruptController {
+    *CONTROLLER.get()
+}
+
 pub static IDT: Global<InterruptDescriptorTable> = Global::lazy_default();
 
 #[repr(C)]
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Default)]
 pub struct InterruptFrame {
     pub ds: usize,
     pub es: usize,
@@ -70,11 +116,87 @@ pub struct InterruptFrame {
 }
 
 impl InterruptFrame {
+    /// The eflags trap flag, which raises a debug exception after each instruction.
+    pub const TRAP_FLAG: usize = 1 << 8;
+    /// The eflags resume flag, which suppresses instruction breakpoints for one instruction.
+    pub const RESUME_FLAG: usize = 1 << 16;
+
     pub fn is_userspace(&self) -> bool {
         (self.cs & 3) != 0
     }
 }
 
+/// Formats the frame as a register dump, with the flags in eflags spelled out. Formatting options
+/// such as `{:#x?}` are ignored, so that the existing panic messages get the same layout.
+impl fmt::Debug for InterruptFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Display for InterruptFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const FLAGS: [(usize, &str); 14] = [
+            (0, "CF"),
+            (2, "PF"),
+            (4, "AF"),
+            (6, "ZF"),
+            (7, "SF"),
+            (8, "TF"),
+            (9, "IF"),
+            (10, "DF"),
+            (11, "OF"),
+            (14, "NT"),
+            (16, "RF"),
+            (17, "VM"),
+            (18, "AC"),
+            (21, "ID"),
+        ];
+
+        writeln!(
+            f,
+            "interrupt {:#04x}, error code {:#010x}",
+            self.id, self.code
+        )?;
+        writeln!(
+            f,
+            "eax={:08x} ebx={:08x} ecx={:08x} edx={:08x}",
+            self.eax, self.ebx, self.ecx, self.edx
+        )?;
+        writeln!(
+            f,
+            "esi={:08x} edi={:08x} ebp={:08x}",
+            self.esi, self.edi, self.ebp
+        )?;
+        write!(f, "eip={:08x} eflags={:08x} [", self.eip, self.eflags)?;
+        let mut first = true;
+        for (bit, name) in FLAGS {
+            if self.eflags & (1 << bit) != 0 {
+                write!(f, "{}{}", if first { "" } else { " " }, name)?;
+                first = false;
+            }
+        }
+        writeln!(
+            f,
+            "{}IOPL={}]",
+            if first { "" } else { " " },
+            (self.eflags >> 12) & 3
+        )?;
+        write!(
+            f,
+            "cs={:04x} ds={:04x} es={:04x} fs={:04x} gs={:04x}",
+            self.cs, self.ds, self.es, self.fs, self.gs
+        )?;
+        // The CPU only saves the stack pointer when switching from userspace.
+        if self.is_userspace() {
+            write!(f, " ss={:04x} esp={:08x}", self.user_ss, self.user_esp)?;
+        } else {
+            write!(f, " (kernel mode)")?;
+        }
+        Ok(())
+    }
+}
+
 #[bitfield]
 #[repr(u64, align(8))]
 #[derive(Copy, Clone)]
@@ -282,6 +404,18 @@ impl<T: InterruptHandler> Interrupt<T> {
         unsafe { Self::create(isr.to_offset(), x86::cs(), 0, true) }
     }
 
+    /// Creates a task gate descriptor, which handles the interrupt by switching to the task whose
+    /// task state segment is selected by `tss`.
+    pub fn task_gate(tss: x86::mmu::SegmentId) -> Self {
+        Self(
+            InterruptGate::new()
+                .with_segment(tss as u16)
+                .with_magic(0b00101000)
+                .with_present(true),
+            PhantomData,
+        )
+    }
+
     /// Creates an interrupt gate descriptor for a software trap.
     /// The interrupt's DPL is set to 3, so it can be triggered by userspace software.
     /// The trap bit is set, so the ISR can itself be interrupted.
@@ -1,13 +1,18 @@
 #![allow(clippy::identity_op)]
 use crate::util::Global;
-use crate::x86::{
-    interrupt,
-    io::{Io, Output},
-};
+use crate::x86::{interrupt, io::Io};
 
 const PIC_MASTER: u16 = 0x20;
 const PIC_SLAVE: u16 = 0xA0;
 
+/// ICW4 bits: 8086 mode, and automatic EOI.
+const ICW4_8086: u8 = 0x1;
+const ICW4_AUTO_EOI: u8 = 0x2;
+/// The master acknowledges its own interrupts as soon as the CPU receives them; the slave waits
+/// for an EOI command.
+const MASTER_ICW4: u8 = ICW4_8086 | ICW4_AUTO_EOI;
+const SLAVE_ICW4: u8 = ICW4_8086;
+
 /// The x86 programmable interrupt controller.
 pub struct Pic {
     master: PicIo<PIC_MASTER>,
@@ -37,9 +42,9 @@ impl Pic {
         self.mask = 0xffff;
         unsafe {
             self.master
-                .initialize(interrupt::IRQ_OFFSET as u8, 1 << IRQ_SLAVE, 0x3);
+                .initialize(interrupt::IRQ_OFFSET as u8, 1 << IRQ_SLAVE, MASTER_ICW4);
             self.slave
-                .initialize(interrupt::IRQ_OFFSET as u8 + 8, IRQ_SLAVE, 0x1);
+                .initialize(interrupt::IRQ_OFFSET as u8 + 8, IRQ_SLAVE, SLAVE_ICW4);
         }
         self.unmask(2); // unmask slave interrupt
                         // acknowledge any pending interrupts
@@ -61,16 +66,23 @@ impl Pic {
 
     /// Masks (disables) the specified interrupt number.
     pub fn mask(&mut self, interrupt: usize) {
-        assert!(interrupt < 16, "interrupt out of range");
-        self.mask |= 1 << interrupt;
-        self.write_mask();
+        self.with_masks(|m| m.mask(interrupt))
     }
 
     /// Unmasks (enables) the specified interrupt number.
     pub fn unmask(&mut self, interrupt: usize) {
-        assert!(interrupt < 16, "interrupt out of range");
-        self.mask &= !(1 << interrupt);
-        self.write_mask();
+        self.with_masks(|m| m.unmask(interrupt))
+    }
+
+    /// Runs a closure that masks or unmasks any number of interrupts, writing the resulting mask
+    /// to the PIC once the closure returns.
+    pub fn with_masks<F: FnOnce(&mut PicMasks) -> T, T>(&mut self, f: F) -> T {
+        let mut masks = PicMasks(self.mask);
+        let result = f(&mut masks);
+        if masks.0 != self.mask {
+            self.set_mask(masks.0);
+        }
+        result
     }
 
     /// Acknowledges an interrupt to the PIC.
@@ -84,19 +96,74 @@ impl Pic {
         }
     }
 
-    pub fn handle_spurious_master(_frame: interrupt::InterruptFrame) {
-        // ignore
+    /// Reads the combined in-service register of both PICs.
+    /// Bit `n` is set if IRQ `n` is currently being serviced.
+    pub fn read_isr() -> u16 {
+        unsafe {
+            let master = PicIo::<PIC_MASTER>::default().read_isr();
+            let slave = PicIo::<PIC_SLAVE>::default().read_isr();
+            (slave as u16) << 8 | master as u16
+        }
+    }
+
+    pub fn handle_spurious_master(_frame: &mut interrupt::InterruptFrame) {
+        // In automatic EOI mode, the master clears the in-service bit as soon as the CPU receives
+        // the interrupt, so the ISR can't tell a real IRQ 7 from a spurious one; but neither needs
+        // an EOI.
+        if MASTER_ICW4 & ICW4_AUTO_EOI != 0 {
+            return;
+        }
+        // IRQ 7 may be a real interrupt; only acknowledge it if the PIC says it's in service
+        if Self::read_isr() & (1 << Self::IRQ_SPURIOUS_MASTER) != 0 {
+            Self::eoi(Self::IRQ_SPURIOUS_MASTER);
+        }
+    }
+
+    pub fn handle_spurious_slave(_frame: &mut interrupt::InterruptFrame) {
+        // The slave is never in automatic EOI mode, so its ISR is accurate.
+        if Self::read_isr() & (1 << Self::IRQ_SPURIOUS_SLAVE) != 0 {
+            Self::eoi(Self::IRQ_SPURIOUS_SLAVE);
+        } else if MASTER_ICW4 & ICW4_AUTO_EOI == 0 {
+            // acknowledge the cascade interrupt on the master only
+            Self::eoi(Self::IRQ_SPURIOUS_MASTER);
+        }
+    }
+}
+
+/// A pending PIC interrupt mask, used by `Pic::with_masks`.
+pub struct PicMasks(u16);
+
+impl PicMasks {
+    /// Masks (disables) the specified interrupt number.
+    pub fn mask(&mut self, interrupt: usize) {
+        assert!(interrupt < 16, "interrupt out of range");
+        self.0 |= 1 << interrupt;
+    }
+
+    /// Unmasks (enables) the specified interrupt number.
+    pub fn unmask(&mut self, interrupt: usize) {
+        assert!(interrupt < 16, "interrupt out of range");
+        self.0 &= !(1 << interrupt);
+    }
+}
+
+impl interrupt::InterruptController for Global<Pic> {
+    fn mask(&self, irq: usize) {
+        self.lock().mask(irq)
     }
 
-    pub fn handle_spurious_slave(_frame: interrupt::InterruptFrame) {
-        // acknowledge the spurious interrupt on the master only
-        Self::eoi(Self::IRQ_SPURIOUS_MASTER);
+    fn unmask(&self, irq: usize) {
+        self.lock().unmask(irq)
+    }
+
+    fn eoi(&self, irq: usize) {
+        Pic::eoi(irq)
     }
 }
 
 #[derive(Default)]
 struct PicIo<const BASE: u16> {
-    command: Output<u8, BASE, 0>,
+    command: Io<u8, BASE, 0>,
     data: Io<u8, BASE, 1>,
 }
 
@@ -111,4 +178,11 @@ impl<const BASE: u16> PicIo<BASE> {
         self.data.write(slave_mask);
         self.data.write(icw4);
     }
+
+    unsafe fn read_isr(&mut self) -> u8 {
+        self.command.write(0x0B); // OCW3: read ISR
+        let isr = self.command.read();
+        self.command.write(0x0A); // OCW3: go back to reading IRR
+        isr
+    }
 }
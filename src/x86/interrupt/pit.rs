@@ -17,6 +17,9 @@ pub struct Pit {
     channel_1: Io<u8, PIT_BASE, 1>,
     channel_2: Io<u8, PIT_BASE, 2>,
     command: Output<Command, PIT_BASE, 3>,
+    /// Controls channel 2's gate, and reports the state of its output.
+    channel_2_control: Io<u8, 0x61, 0>,
+    divisor: u16,
 }
 pub static PIT: Global<Pit> = Global::lazy(|| unsafe { Pit::new() });
 
@@ -33,6 +36,8 @@ impl Pit {
             channel_1: Default::default(),
             channel_2: Default::default(),
             command: Default::default(),
+            channel_2_control: Default::default(),
+            divisor: 0,
         };
         pit.set_divisor(u16::MAX);
         pit
@@ -49,11 +54,77 @@ impl Pit {
             self.channel_0.write(divisor as u8);
             self.channel_0.write((divisor >> 8) as u8);
         }
+        self.divisor = divisor;
     }
 
-    pub fn handle_interrupt(frame: &mut interrupt::InterruptFrame) {
-        interrupt::pic::Pic::eoi(Self::IRQ);
-        crate::process::scheduler::Scheduler::handle_interrupt(frame);
+    /// Returns the divisor channel 0 is currently programmed with.
+    pub fn divisor(&self) -> u16 {
+        self.divisor
+    }
+
+    /// Reads the current value of channel 0's counter, which counts down from the divisor.
+    pub fn read_count(&mut self) -> u16 {
+        unsafe {
+            self.command.write(
+                Command::new().with_channel(0).with_access_mode(0), // latch count
+            );
+
+            let lo = self.channel_0.read();
+            let hi = self.channel_0.read();
+            (hi as u16) << 8 | lo as u16
+        }
+    }
+
+    /// Starts a one-shot countdown of `ticks` PIT ticks on channel 2, leaving channel 0 (the
+    /// preemption timer) untouched. Use `oneshot_done` to check whether the countdown has elapsed.
+    pub fn oneshot(&mut self, ticks: u16) {
+        const GATE: u8 = 1 << 0;
+        const SPEAKER: u8 = 1 << 1;
+        unsafe {
+            // Hold the gate low (and keep the PC speaker off) while programming the counter.
+            let control = self.channel_2_control.read() & !(GATE | SPEAKER);
+            self.channel_2_control.write(control);
+
+            self.command.write(
+                Command::new()
+                    .with_channel(2)
+                    .with_mode(0) // interrupt on terminal count
+                    .with_access_mode(3), // write lo/hi
+            );
+            self.channel_2.write(ticks as u8);
+            self.channel_2.write((ticks >> 8) as u8);
+
+            // Raise the gate to start counting.
+            self.channel_2_control.write(control | GATE);
+        }
+    }
+
+    /// Returns true if the countdown started by `oneshot` has elapsed.
+    pub fn oneshot_done(&mut self) -> bool {
+        const OUTPUT: u8 = 1 << 5;
+        unsafe { self.channel_2_control.read() & OUTPUT != 0 }
+    }
+
+    /// Busy-waits for the given number of microseconds, using channel 2.
+    pub fn delay_us(&mut self, us: u32) {
+        let mut ticks = us as u64 * Self::RATE as u64 / 1_000_000;
+        while ticks > 0 {
+            let count = core::cmp::min(ticks, u16::MAX as u64);
+            self.oneshot(count as u16);
+            while !self.oneshot_done() {}
+            ticks -= count;
+        }
+    }
+}
+
+impl interrupt::timer::TimerSource for Global<Pit> {
+    fn set_frequency(&self, hz: u32) {
+        self.lock().set_divisor((Pit::RATE / hz) as u16);
+        interrupt::controller().unmask(Pit::IRQ);
+    }
+
+    fn handle_interrupt(&self) {
+        interrupt::controller().eoi(Pit::IRQ);
     }
 }
 
@@ -0,0 +1,61 @@
+//! The periodic timer interrupt, used for preemption and timekeeping.
+//!
+//! The timer hardware is abstracted behind the `TimerSource` trait, so the rest of the kernel only
+//! sees a tick counter and a tick rate. Every timer source delivers its interrupt to `VECTOR`.
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::{
+    util::Lazy,
+    x86::interrupt::{self, pit},
+};
+
+/// The interrupt vector timer sources must deliver their interrupts to.
+pub const VECTOR: usize = interrupt::IRQ_OFFSET + pit::Pit::IRQ;
+
+/// A hardware timer capable of raising a periodic interrupt.
+pub trait TimerSource: Sync {
+    /// Programs the timer to interrupt `hz` times per second, and enables its interrupt.
+    fn set_frequency(&self, hz: u32);
+
+    /// Called at the start of each timer interrupt, e.g. to acknowledge it.
+    fn handle_interrupt(&self);
+}
+
+static TIMER: Lazy<&'static dyn TimerSource> = Lazy::new(|| {
+    #[cfg(feature = "apic")]
+    if let Some(apic) = interrupt::apic::APIC.get() {
+        return apic;
+    }
+    &pit::PIT
+});
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+static FREQUENCY: AtomicU32 = AtomicU32::new(0);
+
+/// Returns the active timer source: the APIC timer if the APIC is the active interrupt
+/// controller, or the PIT otherwise.
+pub fn source() -> &'static dyn TimerSource {
+    *TIMER.get()
+}
+
+/// Starts the timer interrupt at `hz` times per second.
+pub fn start(hz: u32) {
+    FREQUENCY.store(hz, Ordering::Relaxed);
+    source().set_frequency(hz);
+}
+
+/// Returns the number of timer interrupts since the timer was started.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Returns the number of timer interrupts per second, or 0 if the timer has not been started.
+pub fn frequency() -> u32 {
+    FREQUENCY.load(Ordering::Relaxed)
+}
+
+pub fn handle_interrupt(frame: &mut interrupt::InterruptFrame) {
+    source().handle_interrupt();
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    crate::process::scheduler::Scheduler::handle_interrupt(frame);
+}
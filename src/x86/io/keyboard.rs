@@ -1,7 +1,7 @@
 #![allow(clippy::identity_op)]
 use super::{Input, Io, IoRwConvertible, Output};
 use crate::{
-    util::Global,
+    util::{Global, RingBuffer},
     x86::interrupt::{self, InterruptFrame},
 };
 use modular_bitfield::prelude::*;
@@ -14,9 +14,22 @@ pub struct Keyboard {
     left_shift: bool,
     right_shift: bool,
     escape: bool,
-    release: bool,
+    caps_lock: bool,
+    num_lock: bool,
+    led_command: LedCommand,
+    /// Set if the lock state changed while an LED command was in flight.
+    leds_dirty: bool,
+    /// Scancodes waiting to be read from the raw keyboard device.
+    raw: RingBuffer<u8, RAW_BUFSIZE>,
+    /// The number of open raw keyboard devices. Scancodes are only buffered while this is nonzero.
+    raw_readers: usize,
 }
-pub static KEYBOARD: Global<Keyboard> = Global::lazy(|| unsafe { Keyboard::new() });
+
+/// The number of scancodes that can be buffered for the raw keyboard device.
+const RAW_BUFSIZE: usize = 256;
+
+pub static KEYBOARD: Global<Keyboard> =
+    Global::lazy(|| unsafe { Keyboard::new() }).interrupt_sensitive();
 
 impl Keyboard {
     pub const IRQ: usize = 1;
@@ -24,8 +37,17 @@ impl Keyboard {
     const DISABLE_PORT0: u8 = 0xAD;
     const ENABLE_PORT0: u8 = 0xAE;
     const DISABLE_PORT1: u8 = 0xA7;
-    const READ_CONFIG: u8 = 0x20;
-    const WRITE_CONFIG: u8 = 0x60;
+    pub(super) const READ_CONFIG: u8 = 0x20;
+    pub(super) const WRITE_CONFIG: u8 = 0x60;
+
+    const SET_LEDS: u8 = 0xED;
+    pub(super) const ACK: u8 = 0xFA;
+    const RESEND: u8 = 0xFE;
+
+    const LEFT_SHIFT: u8 = 0x2A;
+    const RIGHT_SHIFT: u8 = 0x36;
+    const CAPS_LOCK: u8 = 0x3A;
+    const NUM_LOCK: u8 = 0x45;
 
     /// Instantiates and initializes a PS/2 keyboard.
     ///
@@ -47,7 +69,12 @@ impl Keyboard {
             left_shift: false,
             right_shift: false,
             escape: false,
-            release: false,
+            caps_lock: false,
+            num_lock: false,
+            led_command: LedCommand::Idle,
+            leds_dirty: false,
+            raw: RingBuffer::new(0),
+            raw_readers: 0,
         }
     }
 
@@ -63,7 +90,7 @@ impl Keyboard {
             let old_config = Config::from(self.data.read());
 
             self.command.write(Self::WRITE_CONFIG);
-            self.command.write(
+            self.data.write(
                 old_config
                     .with_interrupt_0(true)
                     .with_interrupt_1(false)
@@ -74,12 +101,47 @@ impl Keyboard {
             self.command.write(Self::ENABLE_PORT0);
 
             // unmask the interrupt in the interrupt controller
-            interrupt::pic::PIC.take().unwrap().unmask(Self::IRQ);
+            interrupt::controller().unmask(Self::IRQ);
+        }
+    }
+
+    /// Starts buffering raw scancodes for a newly-opened raw keyboard device.
+    pub fn open_raw(&mut self) {
+        self.raw_readers += 1;
+    }
+
+    /// Stops buffering raw scancodes once the last raw keyboard device is closed.
+    pub fn close_raw(&mut self) {
+        self.raw_readers -= 1;
+        if self.raw_readers == 0 {
+            self.raw.clear();
+        }
+    }
+
+    /// Reads buffered raw scancodes, returning the number of bytes read.
+    pub fn read_raw(&mut self, buf: &mut [u8]) -> usize {
+        self.raw.pop_into(buf)
+    }
+
+    /// Returns the number of raw scancodes waiting to be read.
+    pub fn raw_available(&self) -> usize {
+        self.raw.len()
+    }
+
+    /// Buffers a scancode for the raw keyboard device, if one is open.
+    fn push_raw(&mut self, c: u8) {
+        // If nobody is listening or the buffer is full, drop the scancode.
+        if self.raw_readers != 0 && self.raw.push(c) {
+            crate::process::scheduler::wake();
         }
     }
 
     pub fn handle_interrupt(_frame: &mut InterruptFrame) {
-        KEYBOARD.take().expect("keyboard conflict").handle_input();
+        KEYBOARD
+            .try_take()
+            .expect("keyboard conflict")
+            .handle_input();
+        interrupt::controller().eoi(Self::IRQ);
     }
     pub fn handle_input(&mut self) {
         unsafe {
@@ -89,11 +151,9 @@ impl Keyboard {
                     break;
                 }
 
-                const LEFT_SHIFT: u8 = 0x12;
-                const RIGHT_SHIFT: u8 = 0x12;
                 const KEYMAP: [u8; 88] = [
                     0, 0x1B, b'1', b'2', b'3', b'4', b'5', b'6', b'7', b'8', b'9', b'0', b'-',
-                    b'=', 0, b'\t', b'q', b'w', b'e', b'r', b't', b'y', b'u', b'i', b'o', b'p',
+                    b'=', 0x7F, b'\t', b'q', b'w', b'e', b'r', b't', b'y', b'u', b'i', b'o', b'p',
                     b'[', b']', b'\n', 0, b'a', b's', b'd', b'f', b'g', b'h', b'j', b'k', b'l',
                     b';', b'\'', b'`', 0, b'\\', b'z', b'x', b'c', b'v', b'b', b'n', b'm', b',',
                     b'.', b'/', 0, b'*', 0, b' ', 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, b'7',
@@ -102,7 +162,7 @@ impl Keyboard {
                 ];
                 const SHIFTMAP: [u8; 88] = [
                     0, 0x1B, b'!', b'@', b'#', b'$', b'%', b'^', b'&', b'*', b'(', b')', b'_',
-                    b'+', 0, b'\t', b'Q', b'W', b'E', b'R', b'T', b'Y', b'U', b'I', b'O', b'P',
+                    b'+', 0x7F, b'\t', b'Q', b'W', b'E', b'R', b'T', b'Y', b'U', b'I', b'O', b'P',
                     b'{', b'}', b'\n', 0, b'A', b'S', b'D', b'F', b'G', b'H', b'J', b'K', b'L',
                     b':', b'"', b'~', 0, b'|', b'Z', b'X', b'C', b'V', b'B', b'N', b'M', b'<',
                     b'>', b'?', 0, b'*', 0, b' ', 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, b'7',
@@ -110,54 +170,150 @@ impl Keyboard {
                     0, 0,
                 ];
                 let c = self.data.read();
-                if self.release {
-                    if !self.escape {
-                        if c == LEFT_SHIFT {
-                            self.left_shift = false;
-                            continue;
-                        } else if c == RIGHT_SHIFT {
-                            self.right_shift = false;
-                            continue;
-                        }
+
+                // Responses to the LED command aren't keystrokes.
+                match c {
+                    Self::ACK => {
+                        self.handle_led_ack();
+                        continue;
                     }
-                    self.release = false;
-                    continue;
-                }
-                if c == 0xF0 {
-                    self.release = true;
-                    continue;
+                    Self::RESEND => {
+                        self.led_command = LedCommand::Idle;
+                        self.update_leds();
+                        continue;
+                    }
+                    _ => {}
                 }
+                self.push_raw(c);
 
-                if self.escape {
-                    self.escape = false;
-                    continue;
-                }
                 if c == 0xE0 {
                     self.escape = true;
                     continue;
                 }
+                let escape = core::mem::replace(&mut self.escape, false);
 
-                if c == LEFT_SHIFT {
-                    self.left_shift = true;
+                // A key's break code is its make code with the high bit set.
+                let release = c & 0x80 != 0;
+                let c = c & 0x7F;
+                if !escape {
+                    match c {
+                        Self::LEFT_SHIFT => self.left_shift = !release,
+                        Self::RIGHT_SHIFT => self.right_shift = !release,
+                        _ => {}
+                    }
+                }
+                if release {
                     continue;
-                } else if c == RIGHT_SHIFT {
-                    self.right_shift = true;
+                }
+
+                if escape {
+                    Self::send_sequence(c);
                     continue;
                 }
 
-                match KEYMAP.get(c as usize) {
+                match c {
+                    Self::CAPS_LOCK => {
+                        self.caps_lock = !self.caps_lock;
+                        self.update_leds();
+                        continue;
+                    }
+                    Self::NUM_LOCK => {
+                        self.num_lock = !self.num_lock;
+                        self.update_leds();
+                        continue;
+                    }
+                    // Without Num Lock, the keypad's digits act as cursor keys.
+                    0x47..=0x53 if !self.num_lock && c != 0x4A && c != 0x4E => {
+                        Self::send_sequence(c);
+                        continue;
+                    }
+                    _ => {}
+                }
+
+                let shift = self.left_shift || self.right_shift;
+                let c = match KEYMAP.get(c as usize) {
                     None | Some(0) => continue,
-                    Some(&c) => crate::process::fd::CONSOLE_BUFFER.recv_input(c),
+                    // Caps Lock affects only letters, and Shift reverses it.
+                    Some(&l) if l.is_ascii_lowercase() && shift != self.caps_lock => {
+                        l.to_ascii_uppercase()
+                    }
+                    Some(&l) if l.is_ascii_lowercase() || !shift => l,
+                    Some(_) => SHIFTMAP[c as usize],
+                };
+                crate::process::fd::CONSOLE_BUFFER.recv_input(c);
+            }
+        }
+    }
+
+    /// Sends the escape sequence a terminal would send for a cursor key.
+    fn send_sequence(c: u8) {
+        let sequence: &[u8] = match c {
+            0x48 => b"\x1b[A", // up
+            0x50 => b"\x1b[B", // down
+            0x4D => b"\x1b[C", // right
+            0x4B => b"\x1b[D", // left
+            _ => b"",
+        };
+        for &c in sequence {
+            crate::process::fd::CONSOLE_BUFFER.recv_input(c);
+        }
+    }
+
+    /// Starts setting the keyboard LEDs to match the lock state. The command completes
+    /// asynchronously as the keyboard acknowledges each byte.
+    fn update_leds(&mut self) {
+        if self.led_command == LedCommand::Idle {
+            self.write_data(Self::SET_LEDS);
+            self.led_command = LedCommand::SentCommand;
+        } else {
+            // A command is in flight; send the new state once it completes.
+            self.leds_dirty = true;
+        }
+    }
+
+    /// Continues an LED command after the keyboard acknowledges a byte.
+    fn handle_led_ack(&mut self) {
+        match self.led_command {
+            LedCommand::Idle => {}
+            LedCommand::SentCommand => {
+                let leds = (self.num_lock as u8) << 1 | (self.caps_lock as u8) << 2;
+                self.write_data(leds);
+                self.led_command = LedCommand::SentLeds;
+                self.leds_dirty = false;
+            }
+            LedCommand::SentLeds => {
+                self.led_command = LedCommand::Idle;
+                if core::mem::take(&mut self.leds_dirty) {
+                    self.update_leds();
                 }
             }
         }
     }
+
+    /// Sends a byte to the keyboard.
+    fn write_data(&mut self, byte: u8) {
+        unsafe {
+            // Wait for the controller to accept the previous byte.
+            while self.status.read().input_ready() {}
+            self.data.write(byte);
+        }
+    }
+}
+
+/// The progress of a command to set the keyboard LEDs.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LedCommand {
+    Idle,
+    /// The 'set LEDs' command byte has been sent.
+    SentCommand,
+    /// The LED state has been sent.
+    SentLeds,
 }
 
 #[bitfield]
 #[repr(u8)]
 #[derive(Clone, Copy)]
-struct Status {
+pub(super) struct Status {
     pub output_ready: bool,
     pub input_ready: bool,
     pub system: bool,
@@ -176,15 +332,15 @@ impl IoRwConvertible for Status {
 #[repr(u8)]
 #[derive(Clone, Copy)]
 pub struct Config {
-    interrupt_0: bool,
-    interrupt_1: bool,
-    system: bool,
+    pub interrupt_0: bool,
+    pub interrupt_1: bool,
+    pub system: bool,
     #[skip]
     __: bool,
 
-    clock_disable_0: bool,
-    clock_disable_1: bool,
-    translation: bool,
+    pub clock_disable_0: bool,
+    pub clock_disable_1: bool,
+    pub translation: bool,
     #[skip]
     __: bool,
 }
@@ -3,8 +3,11 @@ use core::arch::asm;
 
 pub mod cga;
 pub mod keyboard;
+pub mod mouse;
 pub mod pio;
+pub mod rtc;
 pub mod serial;
+pub mod vga;
 
 /// An x86 I/O port (accessed using the 'in' and 'out' instructions).
 ///
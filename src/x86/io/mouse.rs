@@ -0,0 +1,200 @@
+#![allow(clippy::identity_op)]
+use super::{
+    keyboard::{Config, Keyboard, Status},
+    Input, Io, Output,
+};
+use crate::{
+    kwarn,
+    syscall::MouseEvent,
+    util::{Global, RingBuffer},
+    x86::interrupt::{self, InterruptFrame},
+};
+use modular_bitfield::prelude::*;
+
+/// A PS/2 mouse driver, for a mouse attached to the second port of the keyboard controller.
+pub struct Mouse {
+    data: Io<u8, 0x60, 0>,
+    status: Input<Status, 0x64, 0>,
+    command: Output<u8, 0x64, 0>,
+    /// The bytes received so far of the packet being assembled.
+    packet: [u8; 3],
+    packet_len: usize,
+    /// Events waiting to be read from the mouse device.
+    events: RingBuffer<MouseEvent, EVENT_BUFSIZE>,
+    /// The number of open mouse devices. Events are only buffered while this is nonzero.
+    readers: usize,
+}
+pub static MOUSE: Global<Mouse> = Global::lazy(|| unsafe { Mouse::new() }).interrupt_sensitive();
+
+/// The number of events that can be buffered for the mouse device.
+const EVENT_BUFSIZE: usize = 64;
+
+impl Mouse {
+    pub const IRQ: usize = 12;
+
+    const ENABLE_PORT1: u8 = 0xA8;
+    const WRITE_PORT1: u8 = 0xD4;
+
+    const SET_DEFAULTS: u8 = 0xF6;
+    const ENABLE_REPORTING: u8 = 0xF4;
+
+    /// Instantiates and initializes a PS/2 mouse.
+    ///
+    /// # Safety
+    ///
+    /// It is the caller's responsibility to avoid I/O space conflicts. The keyboard must be
+    /// initialized first, since resetting the keyboard disables the mouse.
+    unsafe fn new() -> Self {
+        let mut mouse = Self {
+            data: Io::default(),
+            status: Input::default(),
+            command: Output::default(),
+            packet: [0; 3],
+            packet_len: 0,
+            events: RingBuffer::new(MouseEvent::default()),
+            readers: 0,
+        };
+        mouse.reset();
+        mouse
+    }
+
+    pub fn reset(&mut self) {
+        unsafe {
+            // enable port1
+            self.command.write(Self::ENABLE_PORT1);
+
+            // configure interrupts
+            self.command.write(Keyboard::READ_CONFIG);
+            while !self.status.read().output_ready() {}
+            let old_config = Config::from(self.data.read());
+
+            self.command.write(Keyboard::WRITE_CONFIG);
+            self.write_data(
+                old_config
+                    .with_interrupt_1(true)
+                    .with_clock_disable_1(false)
+                    .into(),
+            );
+
+            // start sending movement packets
+            if !(self.send(Self::SET_DEFAULTS) && self.send(Self::ENABLE_REPORTING)) {
+                kwarn!("PS/2 mouse did not respond");
+                return;
+            }
+            self.packet_len = 0;
+
+            // unmask the interrupt in the interrupt controller
+            interrupt::controller().unmask(Self::IRQ);
+        }
+    }
+
+    /// Sends a command byte to the mouse, returning whether it was acknowledged.
+    fn send(&mut self, byte: u8) -> bool {
+        const TIMEOUT_SPINS: usize = 1_000_000;
+        unsafe {
+            self.command.write(Self::WRITE_PORT1);
+            self.write_data(byte);
+            for _ in 0..TIMEOUT_SPINS {
+                let status = self.status.read();
+                if status.output_ready() && status.mouse_or_transmission_error() {
+                    return self.data.read() == Keyboard::ACK;
+                }
+            }
+        }
+        false
+    }
+
+    /// Writes a byte to the controller's data port.
+    fn write_data(&mut self, byte: u8) {
+        unsafe {
+            // Wait for the controller to accept the previous byte.
+            while self.status.read().input_ready() {}
+            self.data.write(byte);
+        }
+    }
+
+    /// Starts buffering events for a newly-opened mouse device.
+    pub fn open(&mut self) {
+        self.readers += 1;
+    }
+
+    /// Stops buffering events once the last mouse device is closed.
+    pub fn close(&mut self) {
+        self.readers -= 1;
+        if self.readers == 0 {
+            self.events.clear();
+        }
+    }
+
+    /// Removes the oldest buffered event.
+    pub fn pop_event(&mut self) -> Option<MouseEvent> {
+        self.events.pop()
+    }
+
+    /// Returns the number of events waiting to be read.
+    pub fn events_available(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn handle_interrupt(_frame: &mut InterruptFrame) {
+        MOUSE.try_take().expect("mouse conflict").handle_input();
+        interrupt::controller().eoi(Self::IRQ);
+    }
+
+    pub fn handle_input(&mut self) {
+        loop {
+            let status = unsafe { self.status.read() };
+            // Bytes without the auxiliary flag came from the keyboard; leave them for its driver.
+            if !status.output_ready() || !status.mouse_or_transmission_error() {
+                break;
+            }
+            let byte = unsafe { self.data.read() };
+            self.handle_byte(byte);
+        }
+    }
+
+    fn handle_byte(&mut self, byte: u8) {
+        // The first byte of every packet has bit 3 set. If a byte was lost, skip ahead until we
+        // find one that could begin a packet.
+        if self.packet_len == 0 && !PacketHeader::from(byte).always_one() {
+            return;
+        }
+        self.packet[self.packet_len] = byte;
+        self.packet_len += 1;
+        if self.packet_len < self.packet.len() {
+            return;
+        }
+        self.packet_len = 0;
+
+        let header = PacketHeader::from(self.packet[0]);
+        if header.x_overflow() || header.y_overflow() {
+            return;
+        }
+        // The movement is a 9-bit two's complement value, with the sign bit in the header.
+        let delta = |low: u8, negative: bool| low as i16 - if negative { 0x100 } else { 0 };
+        let event = MouseEvent {
+            dx: delta(self.packet[1], header.x_sign()),
+            dy: delta(self.packet[2], header.y_sign()),
+            buttons: self.packet[0] & 0x7,
+        };
+        if self.readers != 0 && self.events.push(event) {
+            crate::process::scheduler::wake();
+        }
+    }
+}
+
+/// The first byte of a mouse packet.
+#[bitfield]
+#[repr(u8)]
+#[derive(Clone, Copy)]
+struct PacketHeader {
+    left: bool,
+    right: bool,
+    middle: bool,
+    always_one: bool,
+
+    x_sign: bool,
+    y_sign: bool,
+    x_overflow: bool,
+    y_overflow: bool,
+}
@@ -0,0 +1,180 @@
+#![allow(clippy::identity_op)]
+
+use super::{Io, Output};
+use crate::{util::Global, x86::interrupt};
+use modular_bitfield::prelude::*;
+
+const RTC_BASE: u16 = 0x70;
+
+/// A driver for the CMOS real-time clock.
+pub struct Rtc {
+    index: Output<u8, RTC_BASE, 0>,
+    data: Io<u8, RTC_BASE, 1>,
+}
+
+pub static RTC: Global<Rtc> = Global::lazy(|| unsafe { Rtc::new() });
+
+/// A date and time, as reported by the RTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u32,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl Rtc {
+    /// Instantiates an RTC driver.
+    ///
+    /// # Safety
+    ///
+    /// It is the caller's responsibility to avoid I/O space conflicts (such as two RTC drivers
+    /// sharing the same port).
+    unsafe fn new() -> Rtc {
+        Rtc {
+            index: Output::new(),
+            data: Io::new(),
+        }
+    }
+
+    fn read_register(&mut self, reg: Register) -> u8 {
+        // Selecting a register and reading it must not be interrupted.
+        interrupt::with_interrupts_disabled(|| unsafe {
+            self.index.write(reg as u8);
+            self.data.read()
+        })
+    }
+
+    fn update_in_progress(&mut self) -> bool {
+        StatusA::from(self.read_register(Register::StatusA)).update_in_progress()
+    }
+
+    /// Reads the raw time registers, waiting for any update in progress to finish.
+    fn read_raw(&mut self) -> [u8; 6] {
+        while self.update_in_progress() {}
+        [
+            self.read_register(Register::Seconds),
+            self.read_register(Register::Minutes),
+            self.read_register(Register::Hours),
+            self.read_register(Register::Day),
+            self.read_register(Register::Month),
+            self.read_register(Register::Year),
+        ]
+    }
+
+    /// Reads the current date and time. Returns None if the RTC reports an impossible date or
+    /// time, as it may if it has never been set or its battery has failed.
+    pub fn read(&mut self) -> Option<DateTime> {
+        // An update could still begin while we're reading the registers, so keep reading until we
+        // get the same values twice in a row.
+        let mut raw = self.read_raw();
+        loop {
+            let next = self.read_raw();
+            if next == raw {
+                break;
+            }
+            raw = next;
+        }
+        let [second, minute, hour, day, month, year] = raw;
+
+        let status = StatusB::from(self.read_register(Register::StatusB));
+        let convert = |x: u8| {
+            if status.binary() {
+                x
+            } else {
+                // Binary-coded decimal
+                (x >> 4) * 10 + (x & 0xf)
+            }
+        };
+
+        // In 12-hour mode, the high bit of the hour is set for PM.
+        let pm = hour & 0x80 != 0;
+        let mut hour = convert(hour & 0x7f);
+        if !status.hour_24() {
+            hour %= 12;
+            if pm {
+                hour += 12;
+            }
+        }
+
+        let time = DateTime {
+            // The century register isn't standardized, so assume we're in the 21st century.
+            year: 2000 + convert(year) as u32,
+            month: convert(month),
+            day: convert(day),
+            hour,
+            minute: convert(minute),
+            second: convert(second),
+        };
+        time.is_valid().then_some(time)
+    }
+}
+
+impl DateTime {
+    /// Returns true if each field is within its range. Days past the end of a short month are
+    /// accepted; `unix_timestamp` carries them into the next month.
+    pub fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month)
+            && (1..=31).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// Returns the number of seconds since 1970-01-01 00:00:00 UTC, assuming the RTC is set to
+    /// UTC. The date must be valid (see `is_valid`).
+    pub fn unix_timestamp(&self) -> u64 {
+        // Count days using a calendar where the year starts in March, so that the leap day falls
+        // at the end of the year.
+        let (year, month) = if self.month <= 2 {
+            (self.year as u64 - 1, self.month as u64 + 9)
+        } else {
+            (self.year as u64, self.month as u64 - 3)
+        };
+        let era = year / 400;
+        let year_of_era = year % 400;
+        let day_of_year = (153 * month + 2) / 5 + self.day as u64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        // 719468 is the number of days from 0000-03-01 to 1970-01-01.
+        let days = era * 146097 + day_of_era - 719468;
+
+        days * 86400 + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Register {
+    Seconds = 0x00,
+    Minutes = 0x02,
+    Hours = 0x04,
+    Day = 0x07,
+    Month = 0x08,
+    Year = 0x09,
+    StatusA = 0x0A,
+    StatusB = 0x0B,
+}
+
+#[bitfield]
+#[repr(u8)]
+#[derive(Clone, Copy)]
+struct StatusA {
+    rate: B4,
+    divider: B3,
+    update_in_progress: bool,
+}
+
+#[bitfield]
+#[repr(u8)]
+#[derive(Clone, Copy)]
+struct StatusB {
+    daylight_savings: bool,
+    hour_24: bool,
+    binary: bool,
+    square_wave: bool,
+    update_ended_interrupt: bool,
+    alarm_interrupt: bool,
+    periodic_interrupt: bool,
+    set: bool,
+}
@@ -3,21 +3,51 @@
 use core::fmt::Write;
 
 use super::{Input, Io, IoRwConvertible, Output};
-use crate::{util::Global, x86::interrupt};
+use crate::{
+    util::{Global, RingBuffer},
+    x86::interrupt,
+};
 use modular_bitfield::prelude::*;
 
 /// A basic PC16550 serial driver.
+///
+/// Once interrupts are enabled, output is queued in a software buffer and fed to the transmit FIFO
+/// from the interrupt handler, so writers don't have to wait for the line to drain. If the buffer
+/// fills up, writers block until there is room. Before interrupts are enabled, output is written
+/// synchronously.
 pub struct Serial<const BASE: u16> {
     io: SerialIo<BASE>,
+
+    /// Bytes waiting to be transmitted.
+    tx: RingBuffer<u8, TX_BUFSIZE>,
+    /// Whether `enable_interrupts` has been called.
+    interrupts_enabled: bool,
 }
 
 /// The default serial port.
-pub static COM1: Global<Serial<COM1_BASE>> = Global::lazy(|| unsafe { Serial::new() });
+pub static COM1: Global<Serial<COM1_BASE>> =
+    Global::lazy(|| unsafe { Serial::new() }).interrupt_sensitive();
 
 /// The I/O device base address for the default serial port.
 pub const COM1_BASE: u16 = 0x3F8;
 pub const COM1_IRQ: usize = 4;
 
+/// Asks the remote end to resume sending.
+pub const XON: u8 = 0x11;
+/// Asks the remote end to stop sending.
+pub const XOFF: u8 = 0x13;
+
+/// Whether to use RTS/CTS hardware flow control. When enabled, we deassert RTS while we can't
+/// accept input, and hold off transmitting while the remote end deasserts CTS.
+const HARDWARE_FLOW_CONTROL: bool = cfg!(feature = "rts-cts");
+
+/// The baud rate the serial port is configured with on reset.
+pub const BAUD_RATE: u32 = 115200;
+
+const TX_BUFSIZE: usize = 1024;
+/// The size of the 16550's transmit FIFO.
+const TX_FIFO_SIZE: usize = 16;
+
 impl<const BASE: u16> Serial<BASE> {
     /// Instantiates and initializes a serial port.
     ///
@@ -28,13 +58,17 @@ impl<const BASE: u16> Serial<BASE> {
     pub unsafe fn new() -> Self {
         let mut serial = Self {
             io: SerialIo::default(),
+            tx: RingBuffer::new(0),
+            interrupts_enabled: false,
         };
         serial.reset();
         serial
     }
 
-    /// Reinitializes the serial port.
+    /// Reinitializes the serial port, discarding any queued output.
     pub fn reset(&mut self) {
+        self.tx.clear();
+        self.interrupts_enabled = false;
         unsafe {
             self.set_divisor_latch(false);
 
@@ -47,17 +81,19 @@ impl<const BASE: u16> Serial<BASE> {
                     .with_enabled(true),
             );
 
-            self.set_baud_divisor((115200 / 9600) as u16);
+            self.set_baud_divisor((115200 / BAUD_RATE) as u16);
+
+            // Signal that we're ready to receive.
+            self.io.modem_control.write(Self::modem_control(true));
         }
     }
 
     pub fn enable_interrupts(&mut self) {
         unsafe {
             interrupt::with_interrupts_disabled(|| {
-                self.io
-                    .interrupt_enable
-                    .write(InterruptEnable::new().with_receiver_ready(true));
-                interrupt::pic::PIC.take().unwrap().unmask(COM1_IRQ);
+                self.interrupts_enabled = true;
+                self.set_tx_interrupt(false);
+                interrupt::controller().unmask(COM1_IRQ);
 
                 // flush the buffer
                 Self::recv();
@@ -65,22 +101,83 @@ impl<const BASE: u16> Serial<BASE> {
         }
     }
 
-    /// Outputs a single byte over the serial port. Blocks if the transmit FIFO is full.
+    /// Outputs a single byte over the serial port. Blocks if the transmit buffer is full.
     pub fn write_byte(&mut self, val: u8) {
+        self.write_bytes(&[val]);
+    }
+
+    /// Outputs some bytes over the serial port. Blocks if the transmit buffer is full.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        if !self.interrupts_enabled {
+            for b in bytes {
+                self.wait_for_transmitter();
+                unsafe { self.io.data_holding.write(*b) };
+            }
+            return;
+        }
+
+        interrupt::with_interrupts_disabled(|| {
+            for b in bytes {
+                while !self.tx.push(*b) {
+                    // The buffer is full, so wait for the FIFO to drain.
+                    self.wait_for_transmitter();
+                    self.feed();
+                }
+            }
+            self.feed();
+        })
+    }
+
+    /// Blocks until all queued output has been handed to the transmit FIFO.
+    pub fn flush(&mut self) {
+        interrupt::with_interrupts_disabled(|| {
+            while !self.tx.is_empty() {
+                self.wait_for_transmitter();
+                self.feed();
+            }
+        })
+    }
+
+    /// Waits until the transmit FIFO is empty and, if hardware flow control is enabled, the remote
+    /// end is ready to receive.
+    fn wait_for_transmitter(&mut self) {
         unsafe {
-            // Wait for space in the transmit FIFO
+            if HARDWARE_FLOW_CONTROL {
+                while !self.io.modem_status.read().cts() {}
+            }
             while !self.io.line_status.read().transmitter_holding_empty() {}
-            self.io.data_holding.write(val);
         }
     }
 
-    /// Outputs some bytes over the serial port. Blocks if the transmit FIFO is full.
-    pub fn write_bytes(&mut self, bytes: &[u8]) {
-        for b in bytes {
-            self.write_byte(*b);
+    /// Moves as much queued output as possible into the transmit FIFO, and enables the transmit
+    /// interrupt if there is more to send.
+    fn feed(&mut self) {
+        unsafe {
+            let clear_to_send = !HARDWARE_FLOW_CONTROL || self.io.modem_status.read().cts();
+            if clear_to_send && self.io.line_status.read().transmitter_holding_empty() {
+                for _ in 0..TX_FIFO_SIZE {
+                    match self.tx.pop() {
+                        Some(b) => self.io.data_holding.write(b),
+                        None => break,
+                    }
+                }
+            }
+
+            // If the remote end isn't ready, the modem status interrupt will tell us when it is.
+            self.set_tx_interrupt(clear_to_send && !self.tx.is_empty());
         }
     }
 
+    /// Enables or disables the transmit interrupt, leaving the receive interrupts enabled.
+    unsafe fn set_tx_interrupt(&mut self, enabled: bool) {
+        self.io.interrupt_enable.write(
+            InterruptEnable::new()
+                .with_receiver_ready(true)
+                .with_transmit_complete(enabled)
+                .with_modem_line_status(HARDWARE_FLOW_CONTROL),
+        );
+    }
+
     /// Sets the baud rate divisor.
     ///
     /// A divisor of 1 corresponds to a baud rate of 115,200 bits per second.
@@ -108,14 +205,29 @@ impl<const BASE: u16> Serial<BASE> {
         }
     }
 
-    /// Handles a serial port interrupt.
-    ///
-    /// # Safety
+    /// Asks the remote end to pause or resume sending, by sending XOFF or XON and (if hardware
+    /// flow control is enabled) deasserting or asserting RTS.
     ///
-    /// This function is not thread-safe or reentrant. The caller must ensure the ISR is
-    /// called only from an interrupt context.
-    pub unsafe fn handle_interrupt(_frame: &mut interrupt::InterruptFrame) {
-        Self::recv();
+    /// Like `recv`, this does not require exclusive access to the port, so it can be called from an
+    /// interrupt handler. It must only be called with interrupts disabled.
+    pub fn set_input_paused(paused: bool) {
+        let mut io = SerialIo::<BASE>::default();
+        unsafe {
+            if HARDWARE_FLOW_CONTROL {
+                io.modem_control.write(Self::modem_control(!paused));
+            }
+            while !io.line_status.read().transmitter_holding_empty() {}
+            io.data_holding.write(if paused { XOFF } else { XON });
+        }
+    }
+
+    /// The modem control register value to use, depending on whether we're ready to receive.
+    fn modem_control(ready: bool) -> ModemControl {
+        // OUT2 gates the serial interrupt line on PC-compatible hardware.
+        ModemControl::new()
+            .with_dtr(true)
+            .with_rts(ready || !HARDWARE_FLOW_CONTROL)
+            .with_op2(true)
     }
 
     unsafe fn set_divisor_latch(&mut self, latch: bool) {
@@ -138,8 +250,35 @@ impl<const BASE: u16> Serial<BASE> {
     }
 }
 
+impl Serial<COM1_BASE> {
+    /// Handles a serial port interrupt.
+    ///
+    /// # Safety
+    ///
+    /// This function is not thread-safe or reentrant. The caller must ensure the ISR is
+    /// called only from an interrupt context.
+    pub unsafe fn handle_interrupt(_frame: &mut interrupt::InterruptFrame) {
+        Self::recv();
+
+        let mut io = SerialIo::<COM1_BASE>::default();
+        if HARDWARE_FLOW_CONTROL {
+            // Acknowledge any change in CTS.
+            io.modem_status.read();
+        }
+        match COM1.try_take() {
+            Some(mut serial) => serial.feed(),
+            // Whoever holds the port will re-enable the transmit interrupt when they're done.
+            None => io
+                .interrupt_enable
+                .write(InterruptEnable::new().with_receiver_ready(true)),
+        }
+
+        interrupt::controller().eoi(COM1_IRQ);
+    }
+}
+
 impl<const BASE: u16> Write for Serial<BASE> {
-    /// Outputs a string over the serial port. Blocks if the transmit FIFO is full.
+    /// Outputs a string over the serial port. Blocks if the transmit buffer is full.
     /// Always succeeds.
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
         self.write_bytes(s.as_bytes());
@@ -0,0 +1,276 @@
+//! VGA mode switching, so that userspace can draw to a 320x200, 256-color framebuffer (BIOS mode
+//! 13h) instead of using the text console.
+
+use alloc::vec::Vec;
+
+use super::{cga, Input, Io, Output};
+use crate::util::Global;
+
+/// The physical address of the graphics framebuffer.
+pub const FRAMEBUFFER_PADDR: usize = 0xA0000;
+
+/// The size of the graphics framebuffer, in bytes (one byte per pixel).
+pub const FRAMEBUFFER_SIZE: usize = 320 * 200;
+
+/// The kernel virtual address of the VGA memory window at 0xA0000.
+const WINDOW_BASE: usize = 0xF00A0000;
+
+/// The size of the text-mode font: 256 characters of 32 bytes each, stored in plane 2.
+const FONT_SIZE: usize = 256 * 32;
+
+/// The number of bytes in the DAC palette (256 colors of 3 components each).
+const PALETTE_SIZE: usize = 256 * 3;
+
+/// The values of the VGA registers for a display mode.
+struct ModeRegisters {
+    misc: u8,
+    sequencer: [u8; 5],
+    crtc: [u8; 25],
+    graphics: [u8; 9],
+    attribute: [u8; 21],
+}
+
+/// 80x25 text mode (BIOS mode 3).
+const TEXT_MODE: ModeRegisters = ModeRegisters {
+    misc: 0x67,
+    sequencer: [0x03, 0x00, 0x03, 0x00, 0x02],
+    crtc: [
+        0x5F, 0x4F, 0x50, 0x82, 0x55, 0x81, 0xBF, 0x1F, 0x00, 0x4F, 0x0D, 0x0E, 0x00, 0x00, 0x00,
+        0x50, 0x9C, 0x0E, 0x8F, 0x28, 0x1F, 0x96, 0xB9, 0xA3, 0xFF,
+    ],
+    graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0E, 0x00, 0xFF],
+    attribute: [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E,
+        0x3F, 0x0C, 0x00, 0x0F, 0x08, 0x00,
+    ],
+};
+
+/// 320x200 with 256 colors (BIOS mode 13h).
+const GRAPHICS_MODE: ModeRegisters = ModeRegisters {
+    misc: 0x63,
+    sequencer: [0x03, 0x01, 0x0F, 0x00, 0x0E],
+    crtc: [
+        0x5F, 0x4F, 0x50, 0x82, 0x54, 0x80, 0xBF, 0x1F, 0x00, 0x41, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x9C, 0x0E, 0x8F, 0x28, 0x40, 0x96, 0xB9, 0xA3, 0xFF,
+    ],
+    graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x05, 0x0F, 0xFF],
+    attribute: [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E,
+        0x0F, 0x41, 0x00, 0x0F, 0x00, 0x00,
+    ],
+};
+
+/// A VGA driver that switches between text mode and 320x200x256 graphics mode.
+///
+/// Entering graphics mode saves the font and palette, which graphics mode clobbers. Leaving graphics
+/// mode restores them and redraws the console, including anything written while in graphics mode.
+///
+/// In graphics mode, the palette is set so that each pixel byte is a color in the form `RRRGGGBB`.
+pub struct Vga {
+    misc_write: Output<u8, 0x3C2, 0>,
+    seq_index: Output<u8, 0x3C4, 0>,
+    seq_data: Io<u8, 0x3C4, 1>,
+    crtc_index: Output<u8, 0x3D4, 0>,
+    crtc_data: Io<u8, 0x3D4, 1>,
+    gc_index: Output<u8, 0x3CE, 0>,
+    gc_data: Io<u8, 0x3CE, 1>,
+    attribute: Output<u8, 0x3C0, 0>,
+    input_status: Input<u8, 0x3DA, 0>,
+    dac_read_index: Output<u8, 0x3C7, 0>,
+    dac_write_index: Output<u8, 0x3C8, 0>,
+    dac_data: Io<u8, 0x3C8, 1>,
+
+    /// The address space using graphics mode, and the virtual address of the framebuffer within
+    /// it.
+    owner: Option<(usize, usize)>,
+    /// The text-mode state saved on entering graphics mode.
+    saved: Option<TextState>,
+}
+
+struct TextState {
+    font: Vec<u8>,
+    palette: Vec<u8>,
+}
+
+pub static VGA: Global<Vga> = Global::lazy(|| unsafe { Vga::new() });
+
+impl Vga {
+    /// Instantiates a VGA driver. The display is assumed to be in text mode.
+    ///
+    /// # Safety
+    ///
+    /// It is the caller's responsibility to avoid I/O space conflicts.
+    unsafe fn new() -> Self {
+        Vga {
+            misc_write: Output::new(),
+            seq_index: Output::new(),
+            seq_data: Io::new(),
+            crtc_index: Output::new(),
+            crtc_data: Io::new(),
+            gc_index: Output::new(),
+            gc_data: Io::new(),
+            attribute: Output::new(),
+            input_status: Input::new(),
+            dac_read_index: Output::new(),
+            dac_write_index: Output::new(),
+            dac_data: Io::new(),
+            owner: None,
+            saved: None,
+        }
+    }
+
+    /// Returns the virtual address of the framebuffer, if the address space `cr3` is using
+    /// graphics mode.
+    pub fn framebuffer(&self, cr3: usize) -> Option<usize> {
+        self.owner
+            .filter(|&(owner, _)| owner == cr3)
+            .map(|(_, vaddr)| vaddr)
+    }
+
+    /// Returns true if an address space is using graphics mode.
+    pub fn in_use(&self) -> bool {
+        self.owner.is_some()
+    }
+
+    /// Switches to graphics mode on behalf of the address space `cr3`, which has mapped the
+    /// framebuffer at `vaddr`. Graphics mode must not already be in use.
+    pub fn enter_graphics(&mut self, cr3: usize, vaddr: usize) {
+        assert!(!self.in_use(), "graphics mode is already in use");
+        let font = self.with_plane_2(|window| window[..FONT_SIZE].to_vec());
+        let palette = self.read_palette();
+
+        self.write_registers(&GRAPHICS_MODE);
+        let palette_332: Vec<u8> = (0..=255u8)
+            .flat_map(|color| {
+                let (r, g, b) = (color >> 5, (color >> 2) & 0x7, color & 0x3);
+                [r * 63 / 7, g * 63 / 7, b * 63 / 3]
+            })
+            .collect();
+        self.write_palette(&palette_332);
+
+        self.saved = Some(TextState { font, palette });
+        self.owner = Some((cr3, vaddr));
+    }
+
+    /// Returns to text mode if the address space `cr3` is using graphics mode, returning the
+    /// virtual address at which it mapped the framebuffer.
+    pub fn leave_graphics(&mut self, cr3: usize) -> Option<usize> {
+        let vaddr = self.framebuffer(cr3)?;
+        let saved = self.saved.take().unwrap();
+
+        self.write_registers(&TEXT_MODE);
+        self.with_plane_2(|window| window[..FONT_SIZE].copy_from_slice(&saved.font));
+        self.write_palette(&saved.palette);
+        cga::CGA.lock().redraw();
+
+        self.owner = None;
+        Some(vaddr)
+    }
+
+    fn write_registers(&mut self, mode: &ModeRegisters) {
+        unsafe {
+            self.misc_write.write(mode.misc);
+            for (i, &value) in mode.sequencer.iter().enumerate() {
+                self.seq_index.write(i as u8);
+                self.seq_data.write(value);
+            }
+
+            // Unlock the CRTC registers, and keep them unlocked.
+            const CRTC_END_HBLANK: u8 = 0x03;
+            const CRTC_END_VRETRACE: u8 = 0x11;
+            self.crtc_index.write(CRTC_END_HBLANK);
+            let value = self.crtc_data.read();
+            self.crtc_data.write(value | 0x80);
+            self.crtc_index.write(CRTC_END_VRETRACE);
+            let value = self.crtc_data.read();
+            self.crtc_data.write(value & !0x80);
+            for (i, &value) in mode.crtc.iter().enumerate() {
+                let value = match i as u8 {
+                    CRTC_END_HBLANK => value | 0x80,
+                    CRTC_END_VRETRACE => value & !0x80,
+                    _ => value,
+                };
+                self.crtc_index.write(i as u8);
+                self.crtc_data.write(value);
+            }
+
+            for (i, &value) in mode.graphics.iter().enumerate() {
+                self.gc_index.write(i as u8);
+                self.gc_data.write(value);
+            }
+
+            // The attribute controller alternates between index and data writes; reading the
+            // input status register resets it to expect an index.
+            for (i, &value) in mode.attribute.iter().enumerate() {
+                self.input_status.read();
+                self.attribute.write(i as u8);
+                self.attribute.write(value);
+            }
+
+            // Lock the palette and unblank the display.
+            self.input_status.read();
+            self.attribute.write(0x20);
+        }
+    }
+
+    /// Runs a closure with plane 2, which holds the text-mode font, mapped linearly at 0xA0000.
+    fn with_plane_2<F: FnOnce(&mut [u8]) -> T, T>(&mut self, f: F) -> T {
+        const SEQ_MAP_MASK: u8 = 2;
+        const SEQ_MEMORY_MODE: u8 = 4;
+        const GC_READ_MAP: u8 = 4;
+        const GC_MODE: u8 = 5;
+        const GC_MISC: u8 = 6;
+        unsafe {
+            let seq = [SEQ_MAP_MASK, SEQ_MEMORY_MODE].map(|i| {
+                self.seq_index.write(i);
+                self.seq_data.read()
+            });
+            let gc = [GC_READ_MAP, GC_MODE, GC_MISC].map(|i| {
+                self.gc_index.write(i);
+                self.gc_data.read()
+            });
+
+            // Access plane 2 alone, with odd/even addressing off, through a 64 KiB window at
+            // 0xA0000.
+            for (i, value) in [(SEQ_MAP_MASK, 0x04), (SEQ_MEMORY_MODE, 0x06)] {
+                self.seq_index.write(i);
+                self.seq_data.write(value);
+            }
+            for (i, value) in [(GC_READ_MAP, 0x02), (GC_MODE, 0x00), (GC_MISC, 0x04)] {
+                self.gc_index.write(i);
+                self.gc_data.write(value);
+            }
+
+            let result = f(core::slice::from_raw_parts_mut(
+                WINDOW_BASE as *mut u8,
+                0x10000,
+            ));
+
+            for (i, value) in [SEQ_MAP_MASK, SEQ_MEMORY_MODE].iter().copied().zip(seq) {
+                self.seq_index.write(i);
+                self.seq_data.write(value);
+            }
+            for (i, value) in [GC_READ_MAP, GC_MODE, GC_MISC].iter().copied().zip(gc) {
+                self.gc_index.write(i);
+                self.gc_data.write(value);
+            }
+            result
+        }
+    }
+
+    fn read_palette(&mut self) -> Vec<u8> {
+        unsafe {
+            self.dac_read_index.write(0);
+            (0..PALETTE_SIZE).map(|_| self.dac_data.read()).collect()
+        }
+    }
+
+    fn write_palette(&mut self, palette: &[u8]) {
+        unsafe {
+            self.dac_write_index.write(0);
+            for &value in palette {
+                self.dac_data.write(value);
+            }
+        }
+    }
+}
@@ -43,10 +43,33 @@
 //! ```
 //!
 //! (Not to scale)
+//!
+//! # PAE and NX
+//!
+//! Userspace data and stack pages are currently executable, because 32-bit non-PAE paging has no
+//! no-execute bit. Marking them non-executable requires switching to PAE paging (the entry types
+//! are defined in pagetables.rs), enabling `cr4.PAE` and `EFER.NXE` when `cpuid::FEATURES.nx()`
+//! is set, and then:
+//!
+//! - Entries become 64 bits, so a pagetable covers 2 MB instead of 4 MB, and the page directory
+//!   becomes 4 page directories (one per GB) referenced by a page directory pointer table. cr3
+//!   points to the PDPT, which must live below 4 GB.
+//! - The recursive mapping at `PAGETABLE_BASE` must map all 4 page directories into themselves.
+//!   This keeps it an 8 MB window, which no longer fits above `PAGEINFO_BASE`: one of the two
+//!   regions has to move down.
+//! - `_get_pte_ptr` and `get_mapping` must index with 8-byte entries and 2 MB-per-pagetable math,
+//!   and `find_unused_*`, `fork`, and `destroy_env` must walk 4 page directories instead of one.
+//! - The boot page directory (and kentry.asm) must be rebuilt using 2 MB pages, since PSE's 4 MB
+//!   pages don't exist under PAE.
+//! - `MappingFlags` gains a `no_execute` bit that `map_no_alloc` copies into the PTE, and the ELF
+//!   loader sets it for segments without `PF_X` and for the user stack. Pages shared between an
+//!   executable segment and a data segment must stay executable.
 
 #![allow(dead_code)]
 
+use core::alloc::AllocError;
 use core::arch::asm;
+use core::convert::Infallible;
 use core::mem::MaybeUninit;
 
 use crate::x86::mmu::{self, pagetables};
@@ -58,9 +81,10 @@ use modular_bitfield::prelude::*;
 pub struct MappingFlags {
     pub writable: bool,
     pub user_accessible: bool,
+    pub cache_disabled: bool,
 
     #[skip]
-    __: B6,
+    __: B5,
 }
 
 pub(super) const PAGEINFO_BASE: usize = 0xff800000;
@@ -75,6 +99,12 @@ pub fn zero_page_paddr() -> usize {
 #[derive(Default)]
 pub struct MemoryMapper;
 
+/// The order in which `find_unused` searches an address range.
+enum Direction {
+    Up,
+    Down,
+}
+
 impl MemoryMapper {
     /// Returns the physical address of the active page directory.
     pub fn cr3(&mut self) -> usize {
@@ -92,6 +122,7 @@ impl MemoryMapper {
     /// The caller is responsible for ensuring cr3 is valid and properly initialized.
     pub unsafe fn set_cr3(&mut self, cr3: usize) {
         asm!("mov cr3, {}", in(reg) cr3);
+        crate::x86::interrupt::double_fault::set_cr3(cr3);
     }
 
     /// Zero-initializes 'count' virtual pages (using copy-on-write semantics). Note that the 'writable' field of
@@ -118,6 +149,23 @@ impl MemoryMapper {
         }
     }
 
+    /// Allocates a physical page, maps it at 'vaddr', and zeroes it, returning its physical
+    /// address (or None if there is no memory). The page is always mapped writable, since it is
+    /// zeroed through the new mapping.
+    pub fn alloc_zeroed(
+        &mut self,
+        palloc: &mut PhysAllocator,
+        vaddr: usize,
+        flags: MappingFlags,
+    ) -> Option<usize> {
+        let paddr = palloc.alloc()?;
+        self.map(palloc, paddr, vaddr, flags.with_writable(true));
+        unsafe {
+            crate::util::mem::zero_page(vaddr as *mut u8);
+        }
+        Some(paddr)
+    }
+
     pub fn map(
         &mut self,
         palloc: &mut PhysAllocator,
@@ -131,78 +179,103 @@ impl MemoryMapper {
         }
     }
 
-    /// Unmaps the specified virtual address.
+    /// Unmaps the specified virtual address. The physical page is not deallocated.
     pub fn unmap(&mut self, palloc: &mut PhysAllocator, vaddr: usize) {
         self.map_ensure_pagetable(palloc, vaddr);
         unsafe {
             *(self.get_pte_ptr(vaddr) as *mut pagetables::Pte) = pagetables::Pte::unmapped();
+            asm!("invlpg [{}]", in(reg) vaddr, options(nostack));
         }
     }
 
     /// Finds and returns a block of 'pages' unmapped pages in the kernel portion of the virtual address space.
     pub fn find_unused_kernelspace(&self, pages: usize) -> Option<usize> {
-        let mut vaddr = mmu::KERNEL_RELOC_BASE as usize;
-        let mut base = vaddr;
-        let mut contiguous: usize = 0;
+        self.find_unused(mmu::KERNEL_RELOC_BASE..PAGEINFO_BASE, pages, Direction::Up)
+    }
 
-        loop {
-            if self.get_mapping(vaddr).is_none() {
-                contiguous += 1;
-                if contiguous == pages {
-                    return Some(base);
-                }
-                vaddr = vaddr.checked_add(mmu::PAGE_SIZE)?;
-            } else {
-                contiguous = 0;
-                vaddr = vaddr.checked_add(mmu::PAGE_SIZE)?;
-                base = vaddr;
-            }
-        }
+    /// Like 'find_unused_kernelspace', but searches down from the top of the kernel portion of the
+    /// virtual address space. The kernel heap uses this for big allocations, so that the single
+    /// pages handed out from the bottom can't fragment the space they need.
+    pub fn find_unused_kernelspace_high(&self, pages: usize) -> Option<usize> {
+        self.find_unused(
+            mmu::KERNEL_RELOC_BASE..PAGEINFO_BASE,
+            pages,
+            Direction::Down,
+        )
     }
 
     /// Finds and returns a block of 'pages' unmapped pages in the user portion of the virtual address space.
     pub fn find_unused_userspace(&self, pages: usize) -> Option<usize> {
-        let mut vaddr = 1024 * mmu::PAGE_SIZE as usize; // skip the null page
-        let mut base = vaddr;
-        let mut contiguous: usize = 0;
+        // skip the null page
+        self.find_unused(
+            1024 * mmu::PAGE_SIZE..mmu::KERNEL_RELOC_BASE,
+            pages,
+            Direction::Up,
+        )
+    }
 
-        while vaddr < mmu::KERNEL_RELOC_BASE as usize {
-            if self.get_mapping(vaddr).is_none() {
+    /// Like `find_unused_userspace`, but returns the highest such block, leaving the space above
+    /// the program heap free for it to grow into.
+    pub fn find_unused_userspace_high(&self, pages: usize) -> Option<usize> {
+        self.find_unused(
+            1024 * mmu::PAGE_SIZE..mmu::KERNEL_RELOC_BASE,
+            pages,
+            Direction::Down,
+        )
+    }
+
+    /// Finds a block of `pages` unmapped pages within `range`, searching in the given direction.
+    fn find_unused(
+        &self,
+        range: core::ops::Range<usize>,
+        pages: usize,
+        direction: Direction,
+    ) -> Option<usize> {
+        let block_size = pages.checked_mul(mmu::PAGE_SIZE)?;
+        let mut contiguous: usize = 0;
+        let mut is_end_of_block = |vaddr: usize| {
+            // The kernel stack's guard page is unmapped, but must stay that way.
+            if self.get_mapping(vaddr).is_none() && !mmu::is_kernel_stack_guard(vaddr) {
                 contiguous += 1;
-                if contiguous == pages {
-                    return Some(base);
-                }
-                vaddr += mmu::PAGE_SIZE;
             } else {
                 contiguous = 0;
-                vaddr += mmu::PAGE_SIZE;
-                base = vaddr;
             }
+            contiguous == pages
+        };
+
+        // Each search returns the page that completes the block, which is its last page when
+        // searching up and its first page when searching down.
+        let mut vaddrs = range.step_by(mmu::PAGE_SIZE);
+        match direction {
+            Direction::Up => vaddrs
+                .find(|&vaddr| is_end_of_block(vaddr))
+                .map(|vaddr| vaddr + mmu::PAGE_SIZE - block_size),
+            Direction::Down => vaddrs.rev().find(|&vaddr| is_end_of_block(vaddr)),
         }
-
-        // if we ran into kernelspace, we didn't find a contiguous block big enough
-        None
     }
 
     /// Ensures the pagetable for `vaddr` is allocated, listed in the page directory,
     /// and not marked as copy-on-write.
     fn map_ensure_pagetable(&mut self, palloc: &mut PhysAllocator, vaddr: usize) {
+        // Replacing a 4MB mapping with a pagetable would unmap everything else in it.
+        assert!(
+            self.get_pde(vaddr).get_mapping().is_none(),
+            "attempt to map a page within a 4MB mapping"
+        );
+
         let ptaddr = self.get_pte_ptr(vaddr);
         if self.get_mapping(ptaddr).is_none() {
-            // If the PTE is unmapped, map it.
-            let ptpaddr = palloc.alloc().expect("out of memory");
-            self.map(
-                palloc,
-                ptpaddr,
-                ptaddr,
-                MappingFlags::new().with_writable(true),
+            // Kernelspace pagetables are allocated by `init` and shared by every address space. One
+            // allocated now would only appear in the current page directory.
+            assert!(
+                vaddr < mmu::KERNEL_RELOC_BASE,
+                "kernelspace pagetable for {:#010x} is missing",
+                vaddr
             );
 
-            // Zero the pagetable.
-            unsafe {
-                let pagetable = ptaddr as *mut u8;
-                pagetable.write_bytes(0, mmu::PAGE_SIZE);
-            }
+            // If the PTE is unmapped, map a zeroed pagetable.
+            self.alloc_zeroed(palloc, ptaddr, MappingFlags::new())
+                .expect("out of memory");
         } else {
             // The pagetable is already mapped, but it may be copy-on-write.
             self.cow_if_needed(palloc, ptaddr);
@@ -232,7 +305,8 @@ impl MemoryMapper {
             pagetables::MappingPte::new()
                 .with_physaddr(paddr as u32)
                 .with_is_writable(flags.writable())
-                .with_userspace_accessible(flags.user_accessible()),
+                .with_userspace_accessible(flags.user_accessible())
+                .with_cache_disabled(flags.cache_disabled()),
         );
 
         // Flush the TLB for vaddr
@@ -245,13 +319,35 @@ impl MemoryMapper {
 
     /// Returns the virtual address which would point to the pagetable entry mapping `vaddr`.
     /// Note that the pagetable may not actually exist; call `get_mapping` on the returned pointer
-    /// to find out.
+    /// to find out. It never exists if `vaddr` is covered by a 4MB mapping.
     pub fn get_pte_ptr(&self, vaddr: usize) -> usize {
         MemoryMapper::_get_pte_ptr(vaddr)
     }
 
+    /// Returns the page directory entry covering `vaddr`.
+    pub fn get_pde(&self, vaddr: usize) -> pagetables::Pde {
+        unsafe { (*(PAGETABLE_BASE as *const pagetables::PageDirectory)).0[vaddr >> 22] }
+    }
+
     /// Returns the pagetable entry mapping `vaddr`, if it is present.
+    ///
+    /// If `vaddr` is covered by a 4MB page, there is no pagetable entry, so this returns the entry
+    /// that would map `vaddr` if the 4MB page were split into a pagetable.
     pub fn get_mapping(&self, vaddr: usize) -> Option<pagetables::MappingPte> {
+        if let Some(pde) = self.get_pde(vaddr).get_mapping() {
+            return Some(
+                pagetables::MappingPte::new()
+                    .with_physaddr(pde.paddr() as u32 | (vaddr & 0x3ff000) as u32)
+                    .with_is_writable(pde.is_writable())
+                    .with_userspace_accessible(pde.userspace_accessible())
+                    .with_writethrough_enabled(pde.writethrough_enabled())
+                    .with_cache_disabled(pde.cache_disabled())
+                    .with_accessed(pde.accessed())
+                    .with_is_dirty(pde.is_dirty())
+                    .with_is_global(pde.is_global()),
+            );
+        }
+
         // We don't know whether or not 'vaddr' is mapped; we also don't know whether the
         // pagetable containing the mapping for 'vaddr' is mapped. However, the pagetable mapping
         // the pagetables fits into a single page, and is always mapped, so we don't need to check
@@ -274,34 +370,98 @@ impl MemoryMapper {
         MappingFlags::new()
             .with_writable(mapping.is_writable())
             .with_user_accessible(mapping.userspace_accessible())
+            .with_cache_disabled(mapping.cache_disabled())
     }
 
     pub fn get_mapping_flags(&self, vaddr: usize) -> Option<MappingFlags> {
         self.get_mapping(vaddr).map(|m| self.mapping_to_flags(m))
     }
 
+    /// Returns whether the page mapping `vaddr` has been read or written since its accessed bit
+    /// was last cleared, or None if `vaddr` is unmapped.
+    pub fn is_accessed(&self, vaddr: usize) -> Option<bool> {
+        self.get_mapping(vaddr).map(|m| m.accessed())
+    }
+
+    /// Returns whether the page mapping `vaddr` has been written since it was mapped, or None if
+    /// `vaddr` is unmapped.
+    pub fn is_dirty(&self, vaddr: usize) -> Option<bool> {
+        self.get_mapping(vaddr).map(|m| m.is_dirty())
+    }
+
+    /// Clears the accessed bit of the page mapping `vaddr`, so that the CPU sets it again on the
+    /// next access. Does nothing if `vaddr` is unmapped.
+    pub fn clear_accessed(&mut self, palloc: &mut PhysAllocator, vaddr: usize) {
+        const ACCESSED: u32 = 1 << 5;
+        if self.get_pde(vaddr).get_mapping().is_some() {
+            unsafe {
+                (*(PAGETABLE_BASE as *mut pagetables::PageDirectory)).0[vaddr >> 22].raw &=
+                    !ACCESSED;
+            }
+        } else if self.get_mapping(vaddr).is_some() {
+            // The pagetable may be shared with another process.
+            let ptaddr = self.get_pte_ptr(vaddr);
+            self.cow_if_needed(palloc, ptaddr);
+            unsafe {
+                (*(ptaddr as *mut pagetables::Pte)).raw &= !ACCESSED;
+            }
+        } else {
+            return;
+        }
+
+        // The CPU only sets the accessed bit when it loads the mapping into the TLB, so flush it.
+        unsafe {
+            asm!("invlpg [{}]", in(reg) vaddr, options(nostack));
+        }
+    }
+
+    /// Counts the userspace pages that have been accessed since the last sweep, clearing their
+    /// accessed bits.
+    pub fn sweep_accessed(&mut self, palloc: &mut PhysAllocator) -> usize {
+        let mut count = 0;
+        for pde_idx in 1..(mmu::KERNEL_RELOC_BASE >> 22) {
+            if !self.get_pde(pde_idx << 22).is_present() {
+                continue;
+            }
+            for pte_idx in 0..1024 {
+                let vaddr = (pde_idx << 22) | (pte_idx << 12);
+                if self.is_accessed(vaddr) == Some(true) {
+                    count += 1;
+                    self.clear_accessed(palloc, vaddr);
+                }
+            }
+        }
+        count
+    }
+
     /// Verifies that the given address range exists and is accessible under the given access type.
     /// Useful for validating buffers passed in from userspace.
     pub fn validate_range(
         &self,
         palloc: &PhysAllocator,
-        mut vaddr: usize,
-        mut size: usize,
+        vaddr: usize,
+        size: usize,
         flags: MappingFlags,
     ) -> bool {
-        if size != 0 && vaddr > size.wrapping_neg() {
-            // overflow
+        if size == 0 {
+            return true;
+        }
+        let end = match vaddr.checked_add(size) {
+            Some(end) => end,
+            None => return false, // overflow
+        };
+        if flags.user_accessible() && end > mmu::KERNEL_RELOC_BASE {
+            // Userspace buffers may never reach into kernelspace, regardless of page permissions.
             return false;
         }
-        while size > 0 {
-            if let Some(mapping) = self.get_mapping(vaddr) {
+
+        for page in (mmu::page_align_down(vaddr)..end).step_by(mmu::PAGE_SIZE) {
+            if let Some(mapping) = self.get_mapping(page) {
                 if flags.user_accessible() && !mapping.userspace_accessible() {
                     // insufficient permissions
                     return false;
                 }
-                if flags.writable()
-                    && !(mapping.userspace_accessible() || self.is_cow(palloc, vaddr))
-                {
+                if flags.writable() && !(mapping.is_writable() || self.is_cow(palloc, page)) {
                     // attempt to write to read-only page
                     return false;
                 }
@@ -309,47 +469,63 @@ impl MemoryMapper {
                 // page is not mapped
                 return false;
             }
-
-            size = size.saturating_sub(mmu::PAGE_SIZE);
-            vaddr += mmu::PAGE_SIZE;
         }
 
         true
     }
 
     /// Creates a set of mappings for a new process.
-    /// Kernelspace is shared, while userspace is marked as copy-on-write.
+    /// Kernelspace is shared, while userspace is marked as copy-on-write. Since `init` allocates
+    /// every kernelspace pagetable up front, copying the page directory shares all of kernelspace,
+    /// including kernel heap pages mapped after the fork.
     ///
-    /// Returns the new value of cr3.
-    pub fn fork(&mut self, palloc: &mut PhysAllocator) -> usize {
-        // Mark all userspace pages as copy-on-write
-        for pde_idx in 0..(mmu::KERNEL_RELOC_BASE >> 22) {
-            unsafe {
-                if !(*(PAGETABLE_BASE as *const pagetables::PageDirectory)).0[pde_idx].is_present()
-                {
-                    continue;
-                }
+    /// Returns the new value of cr3, or an error if there is not enough memory (in which case the
+    /// partly built address space is torn down again, and the current process keeps its pages).
+    pub fn fork(&mut self, palloc: &mut PhysAllocator) -> Result<usize, AllocError> {
+        // Allocate the new page directory & metapagetable up front, so we can fail before making
+        // any changes.
+        let new_meta_pagetable_paddr = palloc.alloc().ok_or(AllocError)?;
+        let new_cr3 = match palloc.alloc() {
+            Some(paddr) => paddr,
+            None => {
+                unsafe { palloc.free(new_meta_pagetable_paddr, self) };
+                return Err(AllocError);
             }
-            for pte_idx in 0..1024 {
-                let vaddr = (pde_idx << 22) | (pte_idx << 12);
-                if self.get_mapping(vaddr).is_some() {
-                    unsafe {
-                        palloc.share_vaddr_cow(vaddr, self);
-                    }
+        };
+        // The new metapagetable also needs a temporary virtual address
+        let new_meta_pagetable_tmp_vaddr = match self.find_unused_kernelspace(1) {
+            Some(vaddr) => vaddr,
+            None => {
+                unsafe {
+                    palloc.free(new_meta_pagetable_paddr, self);
+                    palloc.free(new_cr3, self);
                 }
+                return Err(AllocError);
             }
-        }
-
-        // Mark all userspace pagetables as copy-on-write as well
-        for vaddr in (PAGETABLE_BASE..self.get_pte_ptr(mmu::KERNEL_RELOC_BASE))
-            .step_by(mmu::PAGE_SIZE)
-            .skip(1)
-        {
-            if self.get_mapping(vaddr).is_some() {
-                unsafe {
-                    palloc.share_vaddr_cow(vaddr, self);
+        };
+
+        // Mark all userspace pages and pagetables as copy-on-write. Sharing a page can run out of
+        // memory if its refcount is full and it has to be copied.
+        let shared = self.try_for_each_userspace_page(|mapper, vaddr| unsafe {
+            palloc.share_vaddr_cow(vaddr, mapper)
+        });
+        if let Err((failed_vaddr, err)) = shared {
+            // Drop the references taken for the new address space. The pages stay marked
+            // copy-on-write, so the next write to each one finds it unshared and just makes it
+            // writable again.
+            let _ = self.try_for_each_userspace_page(|mapper, vaddr| {
+                if vaddr == failed_vaddr {
+                    return Err(());
                 }
+                let paddr = mapper.get_mapping(vaddr).unwrap().physaddr() as usize;
+                unsafe { palloc.free(paddr, mapper) };
+                Ok(())
+            });
+            unsafe {
+                palloc.free(new_meta_pagetable_paddr, self);
+                palloc.free(new_cr3, self);
             }
+            return Err(err);
         }
 
         // Copy the pagetable that maps all the pagetables. We have to do this carefully, since
@@ -357,11 +533,7 @@ impl MemoryMapper {
         let meta_pagetable_vaddr = self.get_pte_ptr(PAGETABLE_BASE);
         let meta_pagetable_mapping = self.get_mapping(meta_pagetable_vaddr).unwrap();
         let meta_pagetable_flags = self.mapping_to_flags(meta_pagetable_mapping);
-        // Allocate the new metapagetable at a permanent physical address & temporary virtual address
-        let new_meta_pagetable_paddr = palloc.alloc().expect("out of memory");
-        let new_meta_pagetable_tmp_vaddr = self
-            .find_unused_kernelspace(1)
-            .expect("out of virtual address space");
+        // Map the new metapagetable at its temporary virtual address
         self.map(
             palloc,
             new_meta_pagetable_paddr,
@@ -370,10 +542,9 @@ impl MemoryMapper {
         );
         unsafe {
             // Copy the old page to the new one
-            core::ptr::copy_nonoverlapping(
-                meta_pagetable_vaddr as *mut u8,
+            crate::util::mem::copy_page(
                 new_meta_pagetable_tmp_vaddr as *mut u8,
-                mmu::PAGE_SIZE,
+                meta_pagetable_vaddr as *const u8,
             );
             // Update the new metapagetable to reference itself, rather than the old metapagetable.
             *(*(new_meta_pagetable_tmp_vaddr as *mut pagetables::Pagetable))
@@ -389,7 +560,6 @@ impl MemoryMapper {
 
         // Copy the page directory
         let old_cr3 = self.cr3();
-        let new_cr3 = palloc.alloc().expect("out of memory");
         let cr3_flags = self.get_mapping_flags(PAGETABLE_BASE).unwrap();
         self.move_page(palloc, PAGETABLE_BASE, new_cr3, cr3_flags);
         unsafe {
@@ -419,32 +589,56 @@ impl MemoryMapper {
             self.unmap(palloc, new_meta_pagetable_tmp_vaddr);
         }
 
-        new_cr3
+        Ok(new_cr3)
     }
 
-    /// Destroys the current memory-mapping environment & switches to another.
-    ///
-    /// # Safety
-    ///
-    /// The caller is responsible for ensuring the new environemnt is valid & the old environment
-    /// is no longer used.
-    pub unsafe fn destroy_env(&mut self, palloc: &mut PhysAllocator, new_cr3: usize) {
-        // Deallocate all userspace pages
-        for vaddr in (0..mmu::KERNEL_RELOC_BASE).step_by(mmu::PAGE_SIZE) {
-            if let Some(mapping) = self.get_mapping(vaddr) {
-                palloc.free(mapping.physaddr() as usize, self);
+    /// Calls `f` with the address of every mapped userspace page, and then with the address
+    /// (within the pagetable mapping) of every userspace pagetable. Stops at the first error,
+    /// returning it along with the address `f` failed on.
+    fn try_for_each_userspace_page<E>(
+        &mut self,
+        mut f: impl FnMut(&mut Self, usize) -> Result<(), E>,
+    ) -> Result<(), (usize, E)> {
+        for pde_idx in 0..(mmu::KERNEL_RELOC_BASE >> 22) {
+            // Nothing is mapped in a region with no pagetable.
+            if !self.get_pde(pde_idx << 22).is_present() {
+                continue;
+            }
+            for pte_idx in 0..1024 {
+                let vaddr = (pde_idx << 22) | (pte_idx << 12);
+                if self.get_mapping(vaddr).is_some() {
+                    f(self, vaddr).map_err(|e| (vaddr, e))?;
+                }
             }
         }
 
-        // Deallocate all userspace pagetables
-        for vaddr in (mmu::KERNEL_RELOC_BASE..self.get_pte_ptr(mmu::KERNEL_RELOC_BASE))
+        // Skip the page directory, which is mapped at the start of the pagetables
+        for vaddr in (PAGETABLE_BASE..self.get_pte_ptr(mmu::KERNEL_RELOC_BASE))
             .step_by(mmu::PAGE_SIZE)
             .skip(1)
         {
-            if let Some(mapping) = self.get_mapping(vaddr) {
-                palloc.free(mapping.physaddr() as usize, self);
+            if self.get_mapping(vaddr).is_some() {
+                f(self, vaddr).map_err(|e| (vaddr, e))?;
             }
         }
+        Ok(())
+    }
+
+    /// Destroys the current memory-mapping environment & switches to another.
+    ///
+    /// # Safety
+    ///
+    /// The caller is responsible for ensuring the new environemnt is valid & the old environment
+    /// is no longer used.
+    pub unsafe fn destroy_env(&mut self, palloc: &mut PhysAllocator, new_cr3: usize) {
+        // Deallocate all userspace pages, and then all userspace pagetables. This runs with
+        // interrupts disabled, so only visit the regions that have a pagetable.
+        self.try_for_each_userspace_page(|mapper, vaddr| {
+            let paddr = mapper.get_mapping(vaddr).unwrap().physaddr() as usize;
+            palloc.free(paddr, mapper);
+            Ok::<_, Infallible>(())
+        })
+        .unwrap_or_else(|(_, never)| match never {});
 
         // Save the page directory and meta-pagetable so we can deallocate those after abandoning
         // this environment
@@ -489,13 +683,13 @@ impl MemoryMapper {
             let mut contents: [MaybeUninit<u8>; mmu::PAGE_SIZE] =
                 MaybeUninit::uninit().assume_init();
             let contents_ptr = contents.as_mut_ptr() as *mut u8;
-            core::ptr::copy(vaddr as *const u8, contents_ptr, mmu::PAGE_SIZE);
+            crate::util::mem::copy_page(contents_ptr, vaddr as *const u8);
 
             // Map a new physical page
             mapper.map_no_alloc(dest_paddr, vaddr, flags);
 
             // Copy the contents of the old page to the new page
-            core::ptr::copy(contents_ptr, vaddr as *mut u8, mmu::PAGE_SIZE);
+            crate::util::mem::copy_page(vaddr as *mut u8, contents_ptr);
         }
         unsafe {
             do_copy(self, vaddr, dest_paddr, flags);
@@ -509,9 +703,7 @@ impl MemoryMapper {
         let src_paddr = mapping.physaddr() as usize;
 
         // Special case: the zero page is always copy-on-write
-        let zero_page_paddr =
-            core::ptr::addr_of!(ZERO_PAGE) as usize & !(mmu::KERNEL_RELOC_BASE as usize);
-        let is_zero_page = src_paddr == zero_page_paddr;
+        let is_zero_page = src_paddr == zero_page_paddr();
         unsafe {
             let info = (*palloc.get_page_info(src_paddr)).allocated;
             is_zero_page || info.copy_on_write()
@@ -529,13 +721,17 @@ impl MemoryMapper {
         let src_paddr = mapping.physaddr() as usize;
 
         // Special case: the zero page is always copy-on-write
-        let zero_page_paddr =
-            core::ptr::addr_of!(ZERO_PAGE) as usize & !(mmu::KERNEL_RELOC_BASE as usize);
-        let is_zero_page = src_paddr == zero_page_paddr;
+        let is_zero_page = src_paddr == zero_page_paddr();
 
         unsafe {
             let info = (*palloc.get_page_info(src_paddr)).allocated;
-            if is_zero_page || (info.copy_on_write() && info.refcount() > 0) {
+            if is_zero_page {
+                // There is nothing to copy; just map a fresh page of zeroes.
+                self.alloc_zeroed(palloc, vaddr, self.mapping_to_flags(mapping))
+                    .expect("not enough memory for copy-on-write");
+
+                true
+            } else if info.copy_on_write() && info.refcount() > 0 {
                 // We need to copy the page to a temporary buffer, map a new page, and copy the
                 // data into the new page. However, we need to be a little careful: we store the
                 // temporary buffer on the stack, so we want to avoid recursive calls to this
@@ -554,9 +750,7 @@ impl MemoryMapper {
                 );
 
                 // Release our reference to the old page.
-                if !is_zero_page {
-                    palloc.free(src_paddr, self);
-                }
+                palloc.free(src_paddr, self);
 
                 true
             } else if info.copy_on_write() && info.refcount() == 0 {
@@ -583,7 +777,7 @@ impl MemoryMapper {
     pub(super) unsafe fn init(&mut self, palloc: &mut PhysAllocator) {
         let page_directory =
             palloc.alloc().expect("out of memory") as *mut pagetables::PageDirectory;
-        core::ptr::write_bytes(page_directory as *mut u8, 0, mmu::PAGE_SIZE);
+        crate::util::mem::zero_page(page_directory as *mut u8);
 
         unsafe fn map(
             page_directory: *mut pagetables::PageDirectory,
@@ -592,25 +786,7 @@ impl MemoryMapper {
             vaddr: usize,
             writable: bool,
         ) {
-            let pde_index = vaddr >> 22;
-            assert!(pde_index > 0, "cannot map null page");
-            let pde = &mut (*page_directory).0[pde_index];
-
-            let (pt, is_new) = match pde.get_pagetable() {
-                Some(pt) => (pt.ptaddr() as usize as *mut pagetables::Pagetable, false),
-                None => {
-                    let pt = palloc.alloc().expect("out of memory");
-                    core::ptr::write_bytes(pt as *mut u8, 0, mmu::PAGE_SIZE);
-                    *pde = pagetables::Pde::pagetable(
-                        pagetables::PagetablePde::new()
-                            .with_ptaddr(pt as u32)
-                            .with_is_writable(true)
-                            .with_userspace_accessible(true),
-                    );
-                    (pt as usize as *mut pagetables::Pagetable, true)
-                }
-            };
-
+            let pt = pagetable(page_directory, palloc, vaddr);
             let pte = &mut (*pt).0[(vaddr >> 12) & 0x3ff];
             assert!(!pte.is_present(), "duplicate mapping");
             *pte = pagetables::Pte::mapping(
@@ -618,17 +794,55 @@ impl MemoryMapper {
                     .with_physaddr(paddr as u32)
                     .with_is_writable(writable),
             );
+        }
 
-            // If we created a new pagetable, map it too!
-            if is_new {
-                map(
-                    page_directory,
-                    palloc,
-                    pt as usize,
-                    mmu::page_align_down(MemoryMapper::_get_pte_ptr(vaddr)),
-                    true,
-                );
+        /// Returns the pagetable for `vaddr`, allocating and mapping it if needed.
+        unsafe fn pagetable(
+            page_directory: *mut pagetables::PageDirectory,
+            palloc: &mut PhysAllocator,
+            vaddr: usize,
+        ) -> *mut pagetables::Pagetable {
+            let pde_index = vaddr >> 22;
+            assert!(pde_index > 0, "cannot map null page");
+            let pde = &mut (*page_directory).0[pde_index];
+
+            if let Some(pt) = pde.get_pagetable() {
+                return pt.ptaddr() as usize as *mut pagetables::Pagetable;
             }
+
+            let pt = palloc.alloc().expect("out of memory");
+            crate::util::mem::zero_page(pt as *mut u8);
+            *pde = pagetables::Pde::pagetable(
+                pagetables::PagetablePde::new()
+                    .with_ptaddr(pt as u32)
+                    .with_is_writable(true)
+                    .with_userspace_accessible(true),
+            );
+
+            // We created a new pagetable, so map it too!
+            map(
+                page_directory,
+                palloc,
+                pt,
+                mmu::page_align_down(MemoryMapper::_get_pte_ptr(vaddr)),
+                true,
+            );
+            pt as *mut pagetables::Pagetable
+        }
+
+        /// Maps a writable 4MB page.
+        unsafe fn map_large(
+            page_directory: *mut pagetables::PageDirectory,
+            paddr: usize,
+            vaddr: usize,
+        ) {
+            let pde = &mut (*page_directory).0[vaddr >> 22];
+            assert!(!pde.is_present(), "duplicate mapping");
+            *pde = pagetables::Pde::mapping(
+                pagetables::MappingPde::new()
+                    .with_paddr(paddr as u64)
+                    .with_is_writable(true),
+            );
         }
 
         let map_rw = |palloc: &mut _, paddr, vaddr| map(page_directory, palloc, paddr, vaddr, true);
@@ -638,11 +852,37 @@ impl MemoryMapper {
         // Map the page directory itself
         map_rw(palloc, page_directory as usize, PAGETABLE_BASE);
 
-        // Map the kernel's identity mappings
+        // Map the kernel's identity mappings, using 4MB pages for every 4MB-aligned region that is
+        // entirely below the maximum allocated address and 4KB pages for the rest. The kernel
+        // stack's guard page is left unmapped, so its region needs 4KB pages too.
+        const LARGE_PAGE_SIZE: usize = 1 << 22;
+        let guard = core::ptr::addr_of!(mmu::KERNEL_STACK_GUARD) as usize - mmu::KERNEL_RELOC_BASE;
         let mut paddr = 0;
         while paddr <= palloc.get_max_allocated() {
-            map_rw(palloc, paddr, paddr + mmu::KERNEL_RELOC_BASE as usize);
-            paddr += mmu::PAGE_SIZE;
+            let vaddr = paddr + mmu::KERNEL_RELOC_BASE as usize;
+            if paddr == guard {
+                paddr += mmu::PAGE_SIZE;
+            } else if paddr % LARGE_PAGE_SIZE == 0
+                && paddr + (LARGE_PAGE_SIZE - 1) <= palloc.get_max_allocated()
+                && !(paddr..paddr + LARGE_PAGE_SIZE).contains(&guard)
+            {
+                map_large(page_directory, paddr, vaddr);
+                paddr += LARGE_PAGE_SIZE;
+            } else {
+                map_rw(palloc, paddr, vaddr);
+                paddr += mmu::PAGE_SIZE;
+            }
+        }
+
+        // Allocate the rest of the kernelspace pagetables now. Forking copies the page directory, so
+        // every address space shares these pagetables and sees the same kernel mappings (such as
+        // the kernel heap), no matter which address space created them.
+        let mut vaddr = mmu::KERNEL_RELOC_BASE;
+        while vaddr < PAGEINFO_BASE {
+            if !(*page_directory).0[vaddr >> 22].is_present() {
+                pagetable(page_directory, palloc, vaddr);
+            }
+            vaddr += LARGE_PAGE_SIZE;
         }
 
         // Map the physical memory map, as read-only zeroes (for copy-on-write)
@@ -663,12 +903,18 @@ impl core::fmt::Debug for MemoryMapper {
 
         let mut printed_prev = true;
         for pde_idx in 0..1024 {
-            unsafe {
-                if !(*(PAGETABLE_BASE as *const pagetables::PageDirectory)).0[pde_idx].is_present()
-                {
-                    printed_prev = false;
-                    continue;
+            let pde = self.get_pde(pde_idx << 22);
+            if !pde.is_present() {
+                printed_prev = false;
+                continue;
+            }
+            if let Some(mapping) = pde.get_mapping() {
+                if !printed_prev {
+                    f.entry(&"...", &"unmapped");
                 }
+                printed_prev = true;
+                f.entry(&(pde_idx << 22), &mapping.paddr());
+                continue;
             }
             for pte_idx in 0..1024 {
                 let vaddr = (pde_idx << 22) | (pte_idx << 12);
@@ -685,3 +931,38 @@ impl core::fmt::Debug for MemoryMapper {
         f.finish()
     }
 }
+
+/// Maps a page, clears its accessed bit, and checks that the CPU sets the bit again when the page
+/// is touched.
+#[cfg(feature = "self-test")]
+pub fn test_accessed_bit() {
+    let mut mmu = mmu::MMU.lock();
+    let mmu = &mut *mmu;
+    let (palloc, mapper) = (&mut mmu.allocator, &mut mmu.mapper);
+    let vaddr = mapper
+        .find_unused_kernelspace(1)
+        .expect("out of address space");
+    let paddr = palloc.alloc().expect("out of memory");
+    mapper.map(
+        palloc,
+        paddr,
+        vaddr,
+        MappingFlags::new().with_writable(true),
+    );
+    unsafe {
+        (vaddr as *mut u32).write_volatile(0);
+        assert_eq!(mapper.is_dirty(vaddr), Some(true));
+
+        mapper.clear_accessed(palloc, vaddr);
+        assert_eq!(mapper.is_accessed(vaddr), Some(false));
+        (vaddr as *const u32).read_volatile();
+        assert_eq!(
+            mapper.is_accessed(vaddr),
+            Some(true),
+            "reading a page did not set its accessed bit"
+        );
+
+        mapper.unmap(palloc, vaddr);
+        palloc.free(paddr, mapper);
+    }
+}
@@ -1,3 +1,5 @@
+use core::arch::asm;
+
 use crate::util::Global;
 
 pub mod boot_page_directory;
@@ -13,13 +15,21 @@ pub const KERNEL_RELOC_BASE: usize = 0xf0000000;
 extern "C" {
     pub static KERNEL_VIRT_START: u8;
     pub static KERNEL_VIRT_END: u8;
+    /// The unmapped page below the kernel stack.
+    pub static KERNEL_STACK_GUARD: u8;
+}
+
+/// Returns whether `vaddr` lies in the guard page below the kernel stack.
+pub fn is_kernel_stack_guard(vaddr: usize) -> bool {
+    let guard = core::ptr::addr_of!(KERNEL_STACK_GUARD) as usize;
+    (guard..guard + PAGE_SIZE).contains(&vaddr)
 }
 
 pub const PAGE_SHIFT: usize = 12;
 pub const PAGE_SIZE: usize = 1 << PAGE_SHIFT;
 pub const PAGE_MASK: usize = !(PAGE_SIZE - 1);
 
-pub const NUM_SEGMENTS: usize = 6;
+pub const NUM_SEGMENTS: usize = 7;
 
 #[repr(u16)]
 pub enum SegmentId {
@@ -29,6 +39,7 @@ pub enum SegmentId {
     UserCode = 0x18 | 3,
     UserData = 0x20 | 3,
     TaskState = 0x28,
+    DoubleFaultTask = 0x30,
 }
 
 #[repr(align(4096))]
@@ -108,6 +119,8 @@ impl Mmu {
                     .with_db(true),
                 // Task state segment (initialized later)
                 segment::SegmentDescriptor::new(),
+                // Double fault task state segment (initialized later)
+                segment::SegmentDescriptor::new(),
             ],
         }
     }
@@ -116,11 +129,15 @@ impl Mmu {
         assert!(!self.initialized, "attempt to initialize MMU twice");
 
         // Load the kernel GDT
+        crate::x86::interrupt::double_fault::init(&mut self.gdt);
         let gdtr = crate::x86::DescriptorTableRegister::new(
             (core::mem::size_of_val(&self.gdt) - 1) as u16,
             core::ptr::addr_of!(self.gdt) as usize,
         );
         gdtr.lgdt();
+        unsafe {
+            asm!("ltr {:x}", in(reg) SegmentId::TaskState as u16, options(nomem, nostack));
+        }
 
         unsafe {
             self.mapper.init(&mut self.allocator);
@@ -1,9 +1,10 @@
 #![allow(clippy::identity_op, dead_code)]
 use core::arch::asm;
+use core::fmt;
 use core::ops::DerefMut;
 
 use crate::{
-    kprintln,
+    kwarn,
     util::Global,
     x86::{interrupt, mmu},
 };
@@ -23,9 +24,9 @@ pub fn with_page_fault_handler<F: FnOnce() -> T, T>(
     // that handler does not outlive this function.
     let handler = unsafe { core::mem::transmute(handler) };
 
-    let old = PAGE_FAULT_HANDLER.take().unwrap().replace(handler);
+    let old = PAGE_FAULT_HANDLER.lock().replace(handler);
     let result = f();
-    *PAGE_FAULT_HANDLER.take().unwrap() = old;
+    *PAGE_FAULT_HANDLER.lock() = old;
 
     result
 }
@@ -88,6 +89,53 @@ pub struct PageFaultCode {
     __: B16,
 }
 
+/// Describes the access that caused the fault, for instance "user-mode write to a present page".
+impl fmt::Display for PageFaultCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = if self.user() { "user" } else { "kernel" };
+        let access = if self.instruction_fetch() {
+            "instruction fetch from"
+        } else if self.write() {
+            "write to"
+        } else {
+            "read from"
+        };
+        let page = if self.present() {
+            "a present"
+        } else {
+            "a non-present"
+        };
+        write!(f, "{}-mode {} {} page", mode, access, page)?;
+
+        if self.reserved() {
+            write!(f, ", with a reserved bit set in the page tables")?;
+        }
+        if self.protection_key_violated() {
+            write!(f, ", violating its protection key")?;
+        }
+        if self.shadow_stack() {
+            write!(f, ", by a shadow stack access")?;
+        }
+        if self.sgx() {
+            write!(f, ", violating SGX access control")?;
+        }
+        Ok(())
+    }
+}
+
+/// Describes which part of the address space `vaddr` lies in.
+fn address_region(vaddr: usize) -> &'static str {
+    if vaddr < (1 << 22) {
+        "in the null region"
+    } else if vaddr < mmu::KERNEL_RELOC_BASE {
+        "in userspace"
+    } else if mmu::is_kernel_stack_guard(vaddr) {
+        "in the kernel stack's guard page"
+    } else {
+        "in kernelspace"
+    }
+}
+
 #[allow(dead_code, clippy::identity_op)]
 pub extern "C" fn page_fault(frame: &mut interrupt::InterruptFrame) {
     let mut vaddr: usize;
@@ -98,7 +146,7 @@ pub extern "C" fn page_fault(frame: &mut interrupt::InterruptFrame) {
 
     // If we have a custom page fault handler, call it first.
     let handled = PAGE_FAULT_HANDLER
-        .take()
+        .try_take()
         .as_deref_mut()
         .unwrap_or(&mut None)
         .as_mut()
@@ -107,14 +155,18 @@ pub extern "C" fn page_fault(frame: &mut interrupt::InterruptFrame) {
 
     let unhandled = |msg| {
         panic!(
-            "{} accessing virtual address {:#08x}\nCode: {:#08x?}\nFrame: {:#08x?}",
-            msg, vaddr, code, frame
+            "{} accessing virtual address {:#010x} ({})\nCause: {}\nFrame: {:?}",
+            msg,
+            vaddr,
+            address_region(vaddr),
+            code,
+            frame
         )
     };
 
     let handled = handled || {
         let mut mmu = mmu::MMU
-            .take()
+            .try_take()
             .unwrap_or_else(|| unhandled("page fault in MMU"));
         let mmu = mmu.deref_mut();
 
@@ -139,18 +191,21 @@ pub extern "C" fn page_fault(frame: &mut interrupt::InterruptFrame) {
         if frame.is_userspace() {
             // Kill the offending process
             let continuation = {
-                let mut scheduler = crate::process::scheduler::SCHEDULER.take().unwrap();
+                let mut scheduler = crate::process::scheduler::SCHEDULER.try_take().unwrap();
                 let scheduler = scheduler.as_mut().unwrap();
-                kprintln!(
-                "terminating process {} due to unhandled pagefault at virtual address {:#010x}\nCode: {:#x?}\nFrame: {:#x?}",
+                kwarn!(
+                "terminating process {} due to unhandled pagefault at virtual address {:#010x} ({})\nCause: {}\nFrame: {:?}",
                 scheduler.current_pid(),
                 vaddr,
+                address_region(vaddr),
                 code,
                 frame
             );
-                scheduler.kill_current_process(frame).1
+                scheduler.kill_current_process(frame, crate::syscall::ExitStatus::Faulted)
             };
             continuation(frame);
+        } else if mmu::is_kernel_stack_guard(vaddr) {
+            unhandled("Kernel stack overflow");
         } else {
             unhandled("Unhandled pagefault");
         }
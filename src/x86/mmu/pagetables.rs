@@ -263,3 +263,126 @@ impl MappingPte {
         self
     }
 }
+
+// PAE paging structures.
+//
+// These aren't used yet; see the "PAE and NX" section of mmap.rs for what switching to PAE paging
+// involves.
+
+/// A page directory pointer table, the top level of PAE paging. Each of its 4 entries references
+/// a page directory covering 1 GB of the address space.
+#[repr(C, align(32))]
+pub struct PaePdpt(pub [PaePdpte; 4]);
+
+/// A PAE page directory is an array of 512 page directory entries, each covering 2 MB.
+#[repr(C, align(4096))]
+pub struct PaePageDirectory(pub [PaePde; 512]);
+
+/// A PAE pagetable is an array of 512 pagetable entries, each covering 4 KB.
+#[repr(C, align(4096))]
+pub struct PaePagetable(pub [PaePte; 512]);
+
+/// A page directory pointer table entry referencing a page directory.
+#[bitfield(bits = 64)]
+#[derive(Debug, Copy, Clone)]
+pub struct PaePdpte {
+    pub present: bool,
+    #[skip]
+    __: B2,
+    pub writethrough_enabled: bool,
+    pub cache_disabled: bool,
+    #[skip]
+    __: B7,
+
+    pdaddr_shifted: B40,
+    #[skip]
+    __: B12,
+}
+
+impl PaePdpte {
+    pub fn pdaddr(&self) -> u64 {
+        self.pdaddr_shifted() << 12
+    }
+
+    pub fn with_pdaddr(self, pdaddr: u64) -> Self {
+        assert!(
+            pdaddr & ((1 << 12) - 1) == 0,
+            "pdaddr must be alignned to a 4KB boundary"
+        );
+        self.with_pdaddr_shifted(pdaddr >> 12)
+    }
+}
+
+/// A PAE page directory entry referencing a pagetable.
+#[bitfield(bits = 64)]
+#[derive(Debug, Copy, Clone)]
+pub struct PaePde {
+    pub present: bool,
+    pub is_writable: bool,
+    pub userspace_accessible: bool,
+    pub writethrough_enabled: bool,
+    pub cache_disabled: bool,
+    pub accessed: bool,
+    #[skip]
+    __: B1,
+    /// Must be false: this structure does not describe 2 MB mappings.
+    pub is_mapping: bool,
+    #[skip]
+    __: B4,
+
+    ptaddr_shifted: B40,
+    #[skip]
+    __: B11,
+    pub no_execute: bool,
+}
+
+impl PaePde {
+    pub fn ptaddr(&self) -> u64 {
+        self.ptaddr_shifted() << 12
+    }
+
+    pub fn with_ptaddr(self, ptaddr: u64) -> Self {
+        assert!(
+            ptaddr & ((1 << 12) - 1) == 0,
+            "ptaddr must be alignned to a 4KB boundary"
+        );
+        self.with_ptaddr_shifted(ptaddr >> 12)
+    }
+}
+
+/// A PAE pagetable entry mapping a 4KB page.
+#[bitfield(bits = 64)]
+#[derive(Debug, Copy, Clone)]
+pub struct PaePte {
+    pub present: bool,
+    pub is_writable: bool,
+    pub userspace_accessible: bool,
+    pub writethrough_enabled: bool,
+    pub cache_disabled: bool,
+    pub accessed: bool,
+    pub is_dirty: bool,
+    pub pat_enabled: bool,
+    pub is_global: bool,
+    #[skip]
+    __: B3,
+
+    physaddr_shifted: B40,
+    #[skip]
+    __: B11,
+    /// Prevents instruction fetches from the page. Requires `EFER.NXE`.
+    pub no_execute: bool,
+}
+
+impl PaePte {
+    pub fn physaddr(&self) -> u64 {
+        self.physaddr_shifted() << 12
+    }
+
+    pub fn with_physaddr(self, physaddr: u64) -> Self {
+        assert!(
+            physaddr & ((1 << 12) - 1) == 0,
+            "physaddr must be alignned to a 4KB boundary"
+        );
+        self.with_physaddr_shifted(physaddr >> 12)
+    }
+}
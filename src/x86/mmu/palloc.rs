@@ -1,13 +1,20 @@
 #![allow(clippy::identity_op)]
 #![allow(dead_code)]
-use core::num::NonZeroUsize;
+use core::{alloc::AllocError, num::NonZeroUsize};
 
-use crate::x86::mmu;
+use crate::x86::{interrupt, mmu};
 use modular_bitfield::prelude::*;
 
 use super::mmap::MemoryMapper;
 
 /// The physical memory allocator.
+///
+/// The allocator is reentrant in one specific way: updating a page's PhysPageInfo may copy the
+/// (copy-on-write) page holding it, which allocates and frees pages. So `free` only touches the
+/// freelist after `get_page_info_mut` has returned, and the freelist updates themselves run with
+/// interrupts disabled, so that nothing can observe or modify a half-updated freelist.
+/// Page faults while the allocator is in use cannot reenter it either, since the page fault handler
+/// refuses to run while the MMU is locked.
 pub struct PhysAllocator {
     bump_allocator: BumpAllocator,
     max_allocated: usize,
@@ -18,10 +25,16 @@ pub struct PhysAllocator {
 }
 
 extern "C" {
-    /// The memory map obtained from the BIOS in boot.asm.
+    /// The memory map obtained from the BIOS in boot.asm, holding BIOS_MEMORY_MAP_COUNT entries.
+    /// boot.asm leaves out any entries the BIOS reports with a length of zero, but otherwise
+    /// stores them as-is: they may be in any order, and may overlap.
     /// NOTE: This is a physical address, not a virtual address!
     static mut BIOS_MEMORY_MAP: [MemoryRegion; 0];
 
+    /// The number of entries in BIOS_MEMORY_MAP.
+    /// NOTE: This is a physical address, not a virtual address!
+    static BIOS_MEMORY_MAP_COUNT: u32;
+
     static mut PHYSALLOC_START: u8;
 }
 
@@ -35,6 +48,8 @@ struct MemoryRegion {
 }
 
 impl MemoryRegion {
+    /// Returns true if the ACPI 3.0 "valid" attribute bit is clear. BIOSes that return 20-byte
+    /// entries don't report attributes; boot.asm fills them in with this bit set.
     fn should_ignore(&self) -> bool {
         self.attributes & 1 == 0
     }
@@ -42,21 +57,6 @@ impl MemoryRegion {
     fn is_usable(&self) -> bool {
         self.kind == 1
     }
-
-    /// Returns true iff every address in this region
-    /// comes after every address in the given region.
-    fn is_after(&self, start: usize, length: usize) -> bool {
-        let s = self.start as usize;
-        s > start && length <= (s - start)
-    }
-
-    /// Returns true iff every address in this region
-    /// comes before start.
-    fn is_before(&self, start: usize) -> bool {
-        let s = self.start as usize;
-        let l = self.length as usize;
-        start > s && l <= (start - s)
-    }
 }
 
 /// Virtual addresses 0xff800000-0xffc00000 store a physical memory map; with one entry per page.
@@ -86,6 +86,11 @@ pub struct AllocatedPageInfo {
     pub copy_on_write: bool,
 }
 
+impl AllocatedPageInfo {
+    /// The largest representable refcount.
+    pub const MAX_REFCOUNT: u32 = (1 << 31) - 1;
+}
+
 impl PhysAllocator {
     pub(super) unsafe fn new() -> Self {
         let start = core::ptr::addr_of_mut!(PHYSALLOC_START) as usize;
@@ -100,10 +105,14 @@ impl PhysAllocator {
     /// This function is deliberately very simple, and is designed to work without needing to
     /// access the PhysPageInfo structures so that it can work even before memory mappings have
     /// been properly initialized.
+    ///
+    /// The page is not zeroed, and may still hold data freed by another process. A page that
+    /// userspace can see must either be filled with a copy (as `move_page`, copy-on-write and
+    /// `fork` do) or be allocated with `MemoryMapper::alloc_zeroed`.
     pub fn alloc(&mut self) -> Option<usize> {
         if let Some(paddr) = self.freelist_head {
             let paddr = paddr.get();
-            unsafe {
+            interrupt::with_interrupts_disabled(|| unsafe {
                 // SAFETY: normally we can't assume a pageinfo is writable without calling
                 // mapper.cow_if_needed. However, in this case, the pointer has already been
                 // written to before (when it was added to the freelist), and there is no way for
@@ -111,7 +120,7 @@ impl PhysAllocator {
                 let info = self.get_page_info(paddr) as *mut PhysPageInfo;
                 self.freelist_head = (*info).free;
                 *info = PhysPageInfo::default();
-            }
+            });
             Some(paddr)
         } else {
             let result = self.bump_allocator.alloc()?;
@@ -120,37 +129,65 @@ impl PhysAllocator {
         }
     }
 
-    /// Increments the refcount of a physical page.
+    /// Increments the refcount of the physical page mapped at `vaddr`, so that it can be mapped
+    /// again elsewhere, and returns its physical address.
+    /// If the refcount is already at its maximum, `vaddr` is instead moved to a new copy of the
+    /// page, which is shared in its place. If there is no memory for the copy, nothing is changed
+    /// and an error is returned.
     ///
     /// # Safety
     ///
     /// The caller is responsible for ensuring the page was allocated.
-    pub unsafe fn share(&mut self, paddr: usize, mapper: &mut MemoryMapper) {
+    pub unsafe fn share(
+        &mut self,
+        vaddr: usize,
+        mapper: &mut MemoryMapper,
+    ) -> Result<usize, AllocError> {
+        let mapping = mapper.get_mapping(vaddr).expect("page is not mapped");
+        let paddr = mapping.physaddr() as usize;
         if paddr == super::mmap::zero_page_paddr() {
             // The zero page is protected and does not need to be shared.
-            return;
+            return Ok(paddr);
         }
-        let paddr = mmu::page_align_down(paddr);
         let info = self.get_page_info_mut(paddr, mapper).as_mut().unwrap();
+        if info.allocated.refcount() == AllocatedPageInfo::MAX_REFCOUNT {
+            // Copy the page eagerly, and release this mapping's reference to the old page.
+            let new_paddr = self.alloc().ok_or(AllocError)?;
+            mapper.move_page(self, vaddr, new_paddr, mapper.mapping_to_flags(mapping));
+            self.free(paddr, mapper);
+            return self.share(vaddr, mapper);
+        }
         info.allocated.set_refcount(info.allocated.refcount() + 1);
+        Ok(paddr)
     }
 
     /// Marks a page as copy-on-write and increments the refcount.
     /// The page's refcount must have formerly been zero, because it is not possible to track down
     /// all the existing references to the page.
+    /// If the refcount is already at its maximum, the mapping is instead moved to a new copy of
+    /// the page, which is shared in its place. If there is no memory for the copy, nothing is
+    /// changed and an error is returned.
     /// Note that this function takes a virtual address, not a physical address, because it has to
     /// update mappings.
     ///
     /// # Safety
     ///
     /// The caller is responsible for ensuring the page was in fact allocated.
-    pub unsafe fn share_vaddr_cow(&mut self, vaddr: usize, mapper: &mut MemoryMapper) {
+    pub unsafe fn share_vaddr_cow(
+        &mut self,
+        vaddr: usize,
+        mapper: &mut MemoryMapper,
+    ) -> Result<(), AllocError> {
         let mapping = mapper.get_mapping(vaddr).expect("page is not mapped");
 
         let paddr = mapping.physaddr() as usize;
         if paddr == super::mmap::zero_page_paddr() {
             // The zero page is protected and does not need to be shared.
-            return;
+            return Ok(());
+        }
+        if is_device_memory(paddr) {
+            // Device memory stays shared and writable.
+            return Ok(());
         }
         let info = self.get_page_info_mut(paddr, mapper).as_mut().unwrap();
         assert!(
@@ -158,6 +195,14 @@ impl PhysAllocator {
             "cannot mark a shared page as copy-on-write"
         );
 
+        if info.allocated.refcount() == AllocatedPageInfo::MAX_REFCOUNT {
+            // Copy the page eagerly, and release this mapping's reference to the old page.
+            let new_paddr = self.alloc().ok_or(AllocError)?;
+            mapper.move_page(self, vaddr, new_paddr, mapper.mapping_to_flags(mapping));
+            self.free(paddr, mapper);
+            return self.share_vaddr_cow(vaddr, mapper);
+        }
+
         info.allocated.set_copy_on_write(true);
         info.allocated.set_refcount(info.allocated.refcount() + 1);
 
@@ -168,6 +213,7 @@ impl PhysAllocator {
             vaddr,
             mapper.mapping_to_flags(mapping).with_writable(false),
         );
+        Ok(())
     }
 
     /// Decrements the reference count of a page of memory, freeing the page
@@ -181,6 +227,10 @@ impl PhysAllocator {
             // The zero page is protected and can never be freed.
             return;
         }
+        if is_device_memory(paddr) {
+            // Device memory can be mapped, but is never allocated.
+            return;
+        }
         assert!(
             paddr >= core::ptr::addr_of!(PHYSALLOC_START) as usize,
             "free: paddr {:#08x} < PHYSALLOC_START",
@@ -188,14 +238,17 @@ impl PhysAllocator {
         );
 
         let paddr = mmu::page_align_down(paddr);
+        // This may allocate or free pages, so it must happen before we touch the freelist.
         let info = self.get_page_info_mut(paddr, mapper).as_mut().unwrap();
 
         if info.allocated.refcount() == 0 {
-            *info = PhysPageInfo {
-                free: self.freelist_head,
-            };
-            self.freelist_head =
-                Some(NonZeroUsize::new(paddr).expect("attempt to free a null pointer"))
+            let paddr = NonZeroUsize::new(paddr).expect("attempt to free a null pointer");
+            interrupt::with_interrupts_disabled(|| {
+                *info = PhysPageInfo {
+                    free: self.freelist_head,
+                };
+                self.freelist_head = Some(paddr);
+            });
         } else {
             info.allocated.set_refcount(info.allocated.refcount() - 1);
         }
@@ -230,31 +283,165 @@ impl PhysAllocator {
     }
 }
 
+/// The most usable regions of memory the bump allocator can keep track of.
+const MAX_REGIONS: usize = 64;
+
+/// The legacy video memory and BIOS ROM area, which is never usable.
+const LEGACY_HOLE: Region = Region {
+    start: 0xa0000,
+    end: 0x100000,
+};
+
+/// Returns true if `paddr` is device memory (such as the VGA framebuffer), which may be mapped into
+/// a process but is not managed by the allocator.
+pub fn is_device_memory(paddr: usize) -> bool {
+    (LEGACY_HOLE.start..LEGACY_HOLE.end).contains(&(paddr as u64))
+}
+
+/// A range of usable physical memory.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+struct Region {
+    start: u64,
+    end: u64,
+}
+
+/// A list of at most MAX_REGIONS memory regions.
+struct RegionList {
+    regions: [Region; MAX_REGIONS],
+    count: usize,
+}
+
+impl RegionList {
+    fn new() -> Self {
+        Self {
+            regions: [Region::default(); MAX_REGIONS],
+            count: 0,
+        }
+    }
+
+    fn as_slice(&self) -> &[Region] {
+        &self.regions[..self.count]
+    }
+
+    /// Inserts a region into the list at the given index.
+    fn insert(&mut self, idx: usize, region: Region) {
+        assert!(
+            self.count < MAX_REGIONS,
+            "too many regions in the memory map"
+        );
+        self.regions.copy_within(idx..self.count, idx + 1);
+        self.regions[idx] = region;
+        self.count += 1;
+    }
+
+    /// Removes the range [start, end) from the regions.
+    fn reserve(&mut self, start: u64, end: u64) {
+        let mut i = 0;
+        while i < self.count {
+            let region = self.regions[i];
+            if end <= region.start || region.end <= start {
+                i += 1;
+                continue;
+            }
+
+            // Remove the region, keeping whatever is left on either side of the reserved range.
+            self.regions.copy_within((i + 1)..self.count, i);
+            self.count -= 1;
+            let before = Region {
+                start: region.start,
+                end: start,
+            };
+            let after = Region {
+                start: end,
+                end: region.end,
+            };
+            for piece in [before, after] {
+                if piece.start < piece.end {
+                    self.insert(i, piece);
+                    i += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Computes the usable memory described by a BIOS memory map, sorting the map in place. The
+/// result is sorted, non-overlapping, and page-aligned: usable regions are merged where they
+/// overlap or touch; reserved regions, the legacy hole, and everything below `start_addr` are cut
+/// out; and what remains is trimmed to whole pages below 4 GiB.
+fn usable_regions(memory_map: &mut [MemoryRegion], start_addr: u64) -> RegionList {
+    memory_map.sort_unstable_by_key(|r| r.start);
+    let mut result = RegionList::new();
+
+    // Merge overlapping and adjacent usable regions...
+    for region in memory_map
+        .iter()
+        .filter(|r| !r.should_ignore() && r.is_usable())
+    {
+        let end = region.start.saturating_add(region.length);
+        match result.regions[..result.count].last_mut() {
+            Some(last) if region.start <= last.end => last.end = last.end.max(end),
+            _ => result.insert(
+                result.count,
+                Region {
+                    start: region.start,
+                    end,
+                },
+            ),
+        }
+    }
+    // ...then cut out reserved regions, which take precedence over usable ones.
+    for region in memory_map
+        .iter()
+        .filter(|r| !r.should_ignore() && !r.is_usable())
+    {
+        result.reserve(region.start, region.start.saturating_add(region.length));
+    }
+    // Whatever the BIOS says, the video memory and BIOS ROMs are never usable, and everything
+    // below `start_addr` is in use by the bootloader and kernel.
+    result.reserve(LEGACY_HOLE.start, LEGACY_HOLE.end);
+    result.reserve(0, start_addr);
+
+    // We can only use whole pages, and only below 4 GiB since physical addresses are 32 bits.
+    let page_mask = !(mmu::PAGE_SIZE as u64 - 1);
+    let mut count = 0;
+    for i in 0..result.count {
+        let region = result.regions[i];
+        let start = region.start.saturating_add(!page_mask) & page_mask;
+        let end = region.end.min(1 << 32) & page_mask;
+        if start < end {
+            result.regions[count] = Region { start, end };
+            count += 1;
+        }
+    }
+    result.count = count;
+    result
+}
+
 /// A simple bump allocator that allocates physical memory pages in order from the map, with no
 /// support for freeing memory.
 struct BumpAllocator {
     next_addr: Option<usize>,
-    first_active_region_idx: usize,
-    memory_map: &'static [MemoryRegion],
+    /// The index of the region containing next_addr.
+    region_idx: usize,
+    /// The usable regions of memory: sorted, non-overlapping, and page-aligned.
+    regions: RegionList,
 }
 
 impl BumpAllocator {
     pub unsafe fn new(start_addr: usize) -> Self {
-        // Relocate the memory map pointer
+        // Relocate the memory map pointers
         let memory_map_ptr = ((BIOS_MEMORY_MAP.as_mut_ptr() as usize)
             + mmu::KERNEL_RELOC_BASE as usize) as *mut MemoryRegion;
-        let map_count = (0..)
-            .find(|&i| (*memory_map_ptr.add(i)).length == 0)
-            .unwrap();
+        let map_count = *((core::ptr::addr_of!(BIOS_MEMORY_MAP_COUNT) as usize
+            + mmu::KERNEL_RELOC_BASE) as *const u32) as usize;
+        assert!(map_count != 0, "the BIOS did not report any memory regions");
 
         let memory_map = core::slice::from_raw_parts_mut(memory_map_ptr, map_count);
-        // Sort the memory map in place
-        memory_map.sort_unstable_by_key(|r| r.start);
-
         let mut result = Self {
             next_addr: None,
-            first_active_region_idx: 0,
-            memory_map,
+            region_idx: 0,
+            regions: usable_regions(memory_map, start_addr as u64),
         };
         result.next_addr = result.find_next(start_addr);
         result
@@ -270,61 +457,121 @@ impl BumpAllocator {
     /// region. Returns `None` if no more usable memory is available beyond `addr`.
     ///
     /// Always returns a page-aligned address.
-    fn find_next(&mut self, mut addr: usize) -> Option<usize> {
-        addr = mmu::page_align_up(addr)?; // if this overflows, we're out of usable memory
-
-        let mut is_usable = false;
-        while !is_usable {
-            if self.first_active_region_idx >= self.memory_map.len() {
-                // We've exhausted available memory.
-                return None;
-            }
+    fn find_next(&mut self, addr: usize) -> Option<usize> {
+        let addr = mmu::page_align_up(addr)? as u64; // if this overflows, we're out of usable memory
 
-            // Iterate over all regions that may contain the target address.
-            let mut is_first = true;
-            for region in self.memory_map.iter().skip(self.first_active_region_idx) {
-                if !region.should_ignore() && region.is_after(addr, mmu::PAGE_SIZE) {
-                    // We haven't yet reached the start of this region.
-                    if is_first {
-                        // There is no region containing the address, so we need to bump up the
-                        // address to the start of this first region.
-                        addr = mmu::page_align_up(region.start as usize)?;
-                    } else {
-                        // We've gone through all the regions containing the target; we're done.
-                        break;
-                    }
-                }
-                // If we're already past the end of this region, or we should ignore it, skip it.
-                else if region.should_ignore() || region.is_before(addr) {
-                    // Bump up the first active region if needed
-                    if is_first {
-                        self.first_active_region_idx += 1;
-                    }
-                    continue;
-                }
-                is_first = false;
-
-                // The page intersects 'region'.
-                if region.is_usable() {
-                    // This region is marked as usable, but keep checking to make sure it's not
-                    // marked as unusable by an overlapping region.
-                    is_usable = true;
-                } else {
-                    // This region is marked as reserved.
-                    is_usable = false;
-
-                    // We can't possibly use any addresses within this region, so move the pointer
-                    // to the end. If this overflows, we're out of usable memory
-                    addr = mmu::page_align_up(
-                        (region.start as usize).checked_add(region.length as usize)?,
-                    )?;
-
-                    // Try again at the new address.
-                    break;
-                }
+        while let Some(&region) = self.regions.as_slice().get(self.region_idx) {
+            if addr < region.end {
+                return Some(addr.max(region.start) as usize);
             }
+            self.region_idx += 1;
         }
+        None
+    }
+}
 
-        Some(addr)
+/// Feeds `usable_regions` a memory map with out-of-order, overlapping, adjacent, ignored and
+/// unaligned entries.
+#[cfg(feature = "self-test")]
+pub fn test_usable_regions() {
+    let usable = |start, length| MemoryRegion {
+        start,
+        length,
+        kind: 1,
+        attributes: 1,
+    };
+    let reserved = |start, length| MemoryRegion {
+        start,
+        length,
+        kind: 2,
+        attributes: 1,
+    };
+    let mut memory_map = [
+        // adjacent to the merged region below
+        usable(0x300000, 0x100000),
+        // below start_addr
+        usable(0, 0x9f000),
+        usable(0x500000, 0x200000),
+        // overlapping
+        usable(0x100000, 0x180000),
+        usable(0x200000, 0x100000),
+        // splits a usable region, and is rounded out to whole pages
+        reserved(0x580000, 0x1800),
+        // ignored
+        MemoryRegion {
+            attributes: 0,
+            ..reserved(0x600000, 0x1000)
+        },
+        // smaller than a page once aligned
+        usable(0x70000800, 0x1000),
+        // crosses 4 GiB
+        usable(0xffff0000, 0x20000),
+    ];
+    let regions = usable_regions(&mut memory_map, 0x180000);
+    let region = |start, end| Region { start, end };
+    assert_eq!(
+        regions.as_slice(),
+        [
+            region(0x180000, 0x400000),
+            region(0x500000, 0x580000),
+            region(0x582000, 0x700000),
+            region(0xffff0000, 1 << 32),
+        ]
+    );
+}
+
+/// Shares a page up to its refcount limit, and checks that sharing it again copies the page
+/// instead of overflowing the refcount.
+#[cfg(feature = "self-test")]
+pub fn test_share_overflow() {
+    // Sharing the page 2^31 times would take far too long, so start close to the limit.
+    const SHARES: u32 = 256;
+
+    let mut mmu = mmu::MMU.lock();
+    let mmu = &mut *mmu;
+    let (palloc, mapper) = (&mut mmu.allocator, &mut mmu.mapper);
+    let vaddr = mapper
+        .find_unused_kernelspace(1)
+        .expect("out of address space");
+    let paddr = palloc.alloc().expect("out of memory");
+    mapper.map(
+        palloc,
+        paddr,
+        vaddr,
+        super::mmap::MappingFlags::new().with_writable(true),
+    );
+    unsafe {
+        let refcount =
+            |palloc: &PhysAllocator, paddr| (*palloc.get_page_info(paddr)).allocated.refcount();
+        (vaddr as *mut u8).write_bytes(0x5a, mmu::PAGE_SIZE);
+        (*palloc.get_page_info_mut(paddr, mapper))
+            .allocated
+            .set_refcount(AllocatedPageInfo::MAX_REFCOUNT - SHARES);
+        for _ in 0..SHARES {
+            assert_eq!(palloc.share(vaddr, mapper), Ok(paddr));
+        }
+        assert_eq!(refcount(palloc, paddr), AllocatedPageInfo::MAX_REFCOUNT);
+
+        let copy = palloc.share(vaddr, mapper).expect("out of memory");
+        assert_ne!(
+            copy, paddr,
+            "sharing a page past its refcount limit did not copy it"
+        );
+        assert_eq!(refcount(palloc, paddr), AllocatedPageInfo::MAX_REFCOUNT - 1);
+        assert_eq!(refcount(palloc, copy), 1);
+        let contents = core::slice::from_raw_parts(vaddr as *const u8, mmu::PAGE_SIZE);
+        assert!(
+            contents.iter().all(|&b| b == 0x5a),
+            "copied page is corrupted"
+        );
+
+        // Drop the references we made up, and free both pages.
+        mapper.unmap(palloc, vaddr);
+        for page in [paddr, copy] {
+            (*palloc.get_page_info_mut(page, mapper))
+                .allocated
+                .set_refcount(0);
+            palloc.free(page, mapper);
+        }
     }
 }
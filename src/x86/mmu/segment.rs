@@ -54,4 +54,13 @@ impl SegmentDescriptor {
         self.set_base(base);
         self
     }
+
+    /// Creates a descriptor for a 32-bit task state segment.
+    pub fn task_state(tss: &crate::x86::env::TaskStateSegment) -> Self {
+        Self::new()
+            .with_segment_type(0b1001)
+            .with_base(tss as *const _ as usize)
+            .with_limit(core::mem::size_of_val(tss) - 1)
+            .with_present(true)
+    }
 }
@@ -1,7 +1,11 @@
+pub mod cpuid;
+pub mod debug_regs;
 pub mod env;
+pub mod fpu;
 pub mod interrupt;
 pub mod io;
 pub mod mmu;
+pub mod tsc;
 
 use core::arch::asm;
 
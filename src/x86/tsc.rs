@@ -0,0 +1,46 @@
+//! The timestamp counter, a 64-bit counter incremented every CPU cycle.
+
+use core::arch::asm;
+
+use crate::{
+    util::Lazy,
+    x86::interrupt::{self, pit},
+};
+
+/// Reads the timestamp counter.
+pub fn rdtsc() -> u64 {
+    let (lo, hi): (u32, u32);
+    unsafe { asm!("rdtsc", out("eax") lo, out("edx") hi, options(nomem, nostack)) };
+    (hi as u64) << 32 | lo as u64
+}
+
+/// The frequency of the timestamp counter, in Hz.
+/// Measured against the PIT the first time it is accessed.
+pub static TSC_HZ: Lazy<u64> = Lazy::new(calibrate);
+
+/// Measures the frequency of the timestamp counter by counting cycles over 10ms of PIT ticks.
+fn calibrate() -> u64 {
+    const CALIBRATION_TICKS: u32 = pit::Pit::RATE / 100;
+
+    let mut pit = pit::PIT.lock();
+    let divisor = pit.divisor() as u32;
+
+    interrupt::with_interrupts_disabled(|| {
+        let mut elapsed = 0;
+        let mut last = pit.read_count() as u32;
+        let start = rdtsc();
+        while elapsed < CALIBRATION_TICKS {
+            // The counter counts down to 1, then wraps around to the divisor.
+            let count = pit.read_count() as u32;
+            elapsed += if count <= last {
+                last - count
+            } else {
+                last + divisor - count
+            };
+            last = count;
+        }
+        let end = rdtsc();
+
+        (end - start) * pit::Pit::RATE as u64 / elapsed as u64
+    })
+}